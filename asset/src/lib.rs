@@ -0,0 +1,383 @@
+//! Packed asset file format shared by the asset packer and the game.
+//!
+//! A file starts with a fixed size header, followed by the tag table, the asset table and the
+//! payloads. Every asset owns a contiguous range of the tag table. Bitmap payloads are top-down
+//! 0xAARRGGBB pixels, sound payloads are interleaved 16 bit samples. Everything is little endian
+//! and encoded field by field, and every payload starts at a multiple of 4 bytes.
+
+#![no_std]
+
+pub const ASSET_FILE_MAGIC: [u8; 4] = *b"HHAF";
+pub const ASSET_FILE_VERSION: u32 = 1;
+
+pub const ASSET_FILE_HEADER_SIZE: usize = 32;
+pub const ASSET_TAG_SIZE: usize = 8;
+pub const ASSET_ENTRY_SIZE: usize = 48;
+
+pub const PAYLOAD_ALIGNMENT: usize = 4;
+
+const ASSET_KIND_BITMAP: u32 = 1;
+const ASSET_KIND_SOUND: u32 = 2;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum AssetFileError {
+    BadMagic,
+    UnsupportedVersion(u32),
+    /// A table or payload lies outside of the file.
+    Truncated,
+    UnknownAssetType(u32),
+    UnknownAssetKind(u32),
+    UnknownTag(u32),
+    /// The tag range of an asset lies outside of the tag table.
+    InvalidTagRange,
+}
+
+macro_rules! id_enum {
+    ($name:ident { $($(#[$meta:meta])* $variant:ident = $value:expr, $label:expr,)* }) => {
+        #[derive(Copy, Clone, PartialEq, Eq, Debug)]
+        #[repr(u32)]
+        pub enum $name {
+            $($(#[$meta])* $variant = $value,)*
+        }
+
+        impl $name {
+            pub const ALL: &'static [$name] = &[$($name::$variant,)*];
+
+            pub fn from_u32(value: u32) -> Option<$name> {
+                match value {
+                    $($value => Some($name::$variant),)*
+                    _ => None,
+                }
+            }
+
+            /// Name used for this value in asset manifests.
+            pub fn name(self) -> &'static str {
+                match self {
+                    $($name::$variant => $label,)*
+                }
+            }
+
+            pub fn from_name(name: &str) -> Option<$name> {
+                $name::ALL.iter().copied().find(|value| value.name() == name)
+            }
+        }
+    };
+}
+
+id_enum!(AssetType {
+    Backdrop = 1, "backdrop",
+    HeroHead = 2, "hero_head",
+    HeroCape = 3, "hero_cape",
+    HeroTorso = 4, "hero_torso",
+    FootstepFloor = 5, "footstep_floor",
+    FootstepStairs = 6, "footstep_stairs",
+});
+
+id_enum!(AssetTagId {
+    /// Direction the asset is facing in radians, counterclockwise starting at the positive x
+    /// axis.
+    FacingDirection = 1, "facing_direction",
+    /// Height of what the asset shows in meters.
+    Height = 2, "height",
+    /// Coarse size variant, larger values are bigger variants of the same thing.
+    SizeClass = 3, "size_class",
+});
+
+impl AssetTagId {
+    /// Tags whose values wrap around, like angles, return the length of a full turn.
+    pub fn period(self) -> Option<f32> {
+        match self {
+            AssetTagId::FacingDirection => Some(core::f32::consts::TAU),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub struct AssetTag {
+    pub id: AssetTagId,
+    pub value: f32,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct BitmapInfo {
+    pub width: u32,
+    pub height: u32,
+    /// Offset of the point the bitmap is positioned by, in pixels from the top left corner.
+    pub align_x: i32,
+    pub align_y: i32,
+    pub has_alpha: bool,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct SoundInfo {
+    pub sample_count: u32,
+    pub channel_count: u32,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum AssetData {
+    Bitmap(BitmapInfo),
+    Sound(SoundInfo),
+}
+
+impl AssetData {
+    pub fn payload_size(&self) -> u64 {
+        match *self {
+            AssetData::Bitmap(info) => info.width as u64 * info.height as u64 * 4,
+            AssetData::Sound(info) => info.sample_count as u64 * info.channel_count as u64 * 2,
+        }
+    }
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct AssetEntry {
+    pub asset_type: AssetType,
+    pub first_tag: u32,
+    pub tag_count: u32,
+    pub data_offset: u64,
+    pub data: AssetData,
+}
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct AssetFileHeader {
+    pub tag_count: u32,
+    pub asset_count: u32,
+    pub tags_offset: u64,
+    pub assets_offset: u64,
+}
+
+impl AssetFileHeader {
+    pub fn write(&self, bytes: &mut [u8; ASSET_FILE_HEADER_SIZE]) {
+        let mut writer = ByteWriter::new(bytes);
+        writer.write_bytes(&ASSET_FILE_MAGIC);
+        writer.write_u32(ASSET_FILE_VERSION);
+        writer.write_u32(self.tag_count);
+        writer.write_u32(self.asset_count);
+        writer.write_u64(self.tags_offset);
+        writer.write_u64(self.assets_offset);
+    }
+
+    pub fn read(bytes: &[u8]) -> Result<AssetFileHeader, AssetFileError> {
+        if bytes.len() < ASSET_FILE_HEADER_SIZE {
+            return Err(AssetFileError::Truncated);
+        }
+
+        let mut reader = ByteReader::new(bytes);
+        if reader.read_bytes(4) != ASSET_FILE_MAGIC {
+            return Err(AssetFileError::BadMagic);
+        }
+        let version = reader.read_u32();
+        if version != ASSET_FILE_VERSION {
+            return Err(AssetFileError::UnsupportedVersion(version));
+        }
+
+        Ok(AssetFileHeader {
+            tag_count: reader.read_u32(),
+            asset_count: reader.read_u32(),
+            tags_offset: reader.read_u64(),
+            assets_offset: reader.read_u64(),
+        })
+    }
+}
+
+impl AssetTag {
+    pub fn write(&self, bytes: &mut [u8; ASSET_TAG_SIZE]) {
+        let mut writer = ByteWriter::new(bytes);
+        writer.write_u32(self.id as u32);
+        writer.write_u32(self.value.to_bits());
+    }
+
+    pub fn read(bytes: &[u8; ASSET_TAG_SIZE]) -> Result<AssetTag, AssetFileError> {
+        let mut reader = ByteReader::new(bytes);
+        let id = reader.read_u32();
+        Ok(AssetTag {
+            id: AssetTagId::from_u32(id).ok_or(AssetFileError::UnknownTag(id))?,
+            value: f32::from_bits(reader.read_u32()),
+        })
+    }
+}
+
+impl AssetEntry {
+    pub fn write(&self, bytes: &mut [u8; ASSET_ENTRY_SIZE]) {
+        let mut writer = ByteWriter::new(bytes);
+        writer.write_u32(self.asset_type as u32);
+        writer.write_u32(self.first_tag);
+        writer.write_u32(self.tag_count);
+        match self.data {
+            AssetData::Bitmap(info) => {
+                writer.write_u32(ASSET_KIND_BITMAP);
+                writer.write_u64(self.data_offset);
+                writer.write_u32(info.width);
+                writer.write_u32(info.height);
+                writer.write_u32(info.align_x as u32);
+                writer.write_u32(info.align_y as u32);
+                writer.write_u32(info.has_alpha as u32);
+            }
+            AssetData::Sound(info) => {
+                writer.write_u32(ASSET_KIND_SOUND);
+                writer.write_u64(self.data_offset);
+                writer.write_u32(info.sample_count);
+                writer.write_u32(info.channel_count);
+                writer.write_bytes(&[0; 12]);
+            }
+        }
+        writer.write_bytes(&[0; 4]);
+    }
+
+    pub fn read(bytes: &[u8; ASSET_ENTRY_SIZE]) -> Result<AssetEntry, AssetFileError> {
+        let mut reader = ByteReader::new(bytes);
+        let asset_type = reader.read_u32();
+        let asset_type =
+            AssetType::from_u32(asset_type).ok_or(AssetFileError::UnknownAssetType(asset_type))?;
+        let first_tag = reader.read_u32();
+        let tag_count = reader.read_u32();
+        let kind = reader.read_u32();
+        let data_offset = reader.read_u64();
+        let data = match kind {
+            ASSET_KIND_BITMAP => AssetData::Bitmap(BitmapInfo {
+                width: reader.read_u32(),
+                height: reader.read_u32(),
+                align_x: reader.read_u32() as i32,
+                align_y: reader.read_u32() as i32,
+                has_alpha: reader.read_u32() != 0,
+            }),
+            ASSET_KIND_SOUND => AssetData::Sound(SoundInfo {
+                sample_count: reader.read_u32(),
+                channel_count: reader.read_u32(),
+            }),
+            _ => return Err(AssetFileError::UnknownAssetKind(kind)),
+        };
+
+        Ok(AssetEntry {
+            asset_type,
+            first_tag,
+            tag_count,
+            data_offset,
+            data,
+        })
+    }
+}
+
+/// A validated view of a whole asset file in memory.
+pub struct AssetFile<'a> {
+    bytes: &'a [u8],
+    header: AssetFileHeader,
+}
+
+impl<'a> AssetFile<'a> {
+    pub fn parse(bytes: &'a [u8]) -> Result<AssetFile<'a>, AssetFileError> {
+        let header = AssetFileHeader::read(bytes)?;
+        let file = AssetFile { bytes, header };
+        file.range(
+            header.tags_offset,
+            header.tag_count as u64 * ASSET_TAG_SIZE as u64,
+        )?;
+        file.range(
+            header.assets_offset,
+            header.asset_count as u64 * ASSET_ENTRY_SIZE as u64,
+        )?;
+        Ok(file)
+    }
+
+    pub fn header(&self) -> &AssetFileHeader {
+        &self.header
+    }
+
+    fn range(&self, offset: u64, size: u64) -> Result<&'a [u8], AssetFileError> {
+        let end = offset.checked_add(size).ok_or(AssetFileError::Truncated)?;
+        if end > self.bytes.len() as u64 {
+            return Err(AssetFileError::Truncated);
+        }
+        Ok(&self.bytes[offset as usize..end as usize])
+    }
+
+    pub fn tag(&self, index: u32) -> Result<AssetTag, AssetFileError> {
+        if index >= self.header.tag_count {
+            return Err(AssetFileError::InvalidTagRange);
+        }
+        let offset = self.header.tags_offset + index as u64 * ASSET_TAG_SIZE as u64;
+        let bytes = self.range(offset, ASSET_TAG_SIZE as u64)?;
+        let mut tag = [0; ASSET_TAG_SIZE];
+        tag.copy_from_slice(bytes);
+        AssetTag::read(&tag)
+    }
+
+    /// Reads the entry of the asset at `index` and checks its tag range and payload.
+    pub fn entry(&self, index: u32) -> Result<AssetEntry, AssetFileError> {
+        if index >= self.header.asset_count {
+            return Err(AssetFileError::Truncated);
+        }
+        let offset = self.header.assets_offset + index as u64 * ASSET_ENTRY_SIZE as u64;
+        let bytes = self.range(offset, ASSET_ENTRY_SIZE as u64)?;
+        let mut entry = [0; ASSET_ENTRY_SIZE];
+        entry.copy_from_slice(bytes);
+        let entry = AssetEntry::read(&entry)?;
+
+        let tags_end = entry.first_tag as u64 + entry.tag_count as u64;
+        if tags_end > self.header.tag_count as u64 {
+            return Err(AssetFileError::InvalidTagRange);
+        }
+        self.payload(&entry)?;
+        Ok(entry)
+    }
+
+    pub fn payload(&self, entry: &AssetEntry) -> Result<&'a [u8], AssetFileError> {
+        self.range(entry.data_offset, entry.data.payload_size())
+    }
+}
+
+struct ByteWriter<'a> {
+    bytes: &'a mut [u8],
+    at: usize,
+}
+
+impl<'a> ByteWriter<'a> {
+    fn new(bytes: &'a mut [u8]) -> ByteWriter<'a> {
+        ByteWriter { bytes, at: 0 }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.bytes[self.at..self.at + bytes.len()].copy_from_slice(bytes);
+        self.at += bytes.len();
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(bytes: &'a [u8]) -> ByteReader<'a> {
+        ByteReader { bytes, at: 0 }
+    }
+
+    fn read_bytes(&mut self, count: usize) -> &'a [u8] {
+        let bytes = &self.bytes[self.at..self.at + count];
+        self.at += count;
+        bytes
+    }
+
+    fn read_array<const N: usize>(&mut self) -> [u8; N] {
+        let mut result = [0; N];
+        result.copy_from_slice(self.read_bytes(N));
+        result
+    }
+
+    fn read_u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.read_array())
+    }
+
+    fn read_u64(&mut self) -> u64 {
+        u64::from_le_bytes(self.read_array())
+    }
+}
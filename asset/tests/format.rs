@@ -0,0 +1,195 @@
+extern crate handmade_asset;
+
+use handmade_asset::*;
+
+fn bitmap_entry(first_tag: u32, tag_count: u32, data_offset: u64) -> AssetEntry {
+    AssetEntry {
+        asset_type: AssetType::HeroHead,
+        first_tag,
+        tag_count,
+        data_offset,
+        data: AssetData::Bitmap(BitmapInfo {
+            width: 2,
+            height: 1,
+            align_x: -3,
+            align_y: 183,
+            has_alpha: true,
+        }),
+    }
+}
+
+fn sound_entry(data_offset: u64) -> AssetEntry {
+    AssetEntry {
+        asset_type: AssetType::Backdrop,
+        first_tag: 0,
+        tag_count: 0,
+        data_offset,
+        data: AssetData::Sound(SoundInfo {
+            sample_count: 3,
+            channel_count: 2,
+        }),
+    }
+}
+
+/// Lays out a file the same way the asset packer does.
+fn build_file(tags: &[AssetTag], entries: &[AssetEntry], payload: &[u8]) -> Vec<u8> {
+    let tags_offset = ASSET_FILE_HEADER_SIZE;
+    let assets_offset = tags_offset + tags.len() * ASSET_TAG_SIZE;
+
+    let mut bytes = vec![0; ASSET_FILE_HEADER_SIZE];
+    let mut header = [0; ASSET_FILE_HEADER_SIZE];
+    AssetFileHeader {
+        tag_count: tags.len() as u32,
+        asset_count: entries.len() as u32,
+        tags_offset: tags_offset as u64,
+        assets_offset: assets_offset as u64,
+    }
+    .write(&mut header);
+    bytes.copy_from_slice(&header);
+
+    for tag in tags {
+        let mut tag_bytes = [0; ASSET_TAG_SIZE];
+        tag.write(&mut tag_bytes);
+        bytes.extend_from_slice(&tag_bytes);
+    }
+    for entry in entries {
+        let mut entry_bytes = [0; ASSET_ENTRY_SIZE];
+        entry.write(&mut entry_bytes);
+        bytes.extend_from_slice(&entry_bytes);
+    }
+    bytes.extend_from_slice(payload);
+    bytes
+}
+
+fn payloads_offset(tag_count: usize, asset_count: usize) -> u64 {
+    (ASSET_FILE_HEADER_SIZE + tag_count * ASSET_TAG_SIZE + asset_count * ASSET_ENTRY_SIZE) as u64
+}
+
+#[test]
+fn round_trip() {
+    let tags = [
+        AssetTag {
+            id: AssetTagId::FacingDirection,
+            value: 1.5707964,
+        },
+        AssetTag {
+            id: AssetTagId::FacingDirection,
+            value: -0.25,
+        },
+    ];
+    let offset = payloads_offset(tags.len(), 2);
+    let entries = [bitmap_entry(0, 2, offset), sound_entry(offset + 8)];
+    let payload: Vec<u8> = (0..20).collect();
+    let bytes = build_file(&tags, &entries, &payload);
+
+    let file = AssetFile::parse(&bytes).unwrap();
+    assert_eq!(file.header().tag_count, 2);
+    assert_eq!(file.header().asset_count, 2);
+    assert_eq!(file.tag(0).unwrap(), tags[0]);
+    assert_eq!(file.tag(1).unwrap(), tags[1]);
+    assert_eq!(file.entry(0).unwrap(), entries[0]);
+    assert_eq!(file.entry(1).unwrap(), entries[1]);
+    assert_eq!(file.payload(&entries[0]).unwrap(), &payload[..8]);
+    assert_eq!(file.payload(&entries[1]).unwrap(), &payload[8..]);
+}
+
+#[test]
+fn names() {
+    for &asset_type in AssetType::ALL {
+        assert_eq!(AssetType::from_name(asset_type.name()), Some(asset_type));
+        assert_eq!(AssetType::from_u32(asset_type as u32), Some(asset_type));
+    }
+    for &tag in AssetTagId::ALL {
+        assert_eq!(AssetTagId::from_name(tag.name()), Some(tag));
+    }
+    assert_eq!(AssetType::from_name("hero"), None);
+    assert_eq!(AssetType::from_u32(0), None);
+}
+
+#[test]
+fn rejects_bad_header() {
+    let bytes = build_file(&[], &[], &[]);
+
+    let mut bad_magic = bytes.clone();
+    bad_magic[0] = b'X';
+    assert_eq!(
+        AssetFile::parse(&bad_magic).err(),
+        Some(AssetFileError::BadMagic)
+    );
+
+    let mut bad_version = bytes.clone();
+    bad_version[4..8].copy_from_slice(&2u32.to_le_bytes());
+    assert_eq!(
+        AssetFile::parse(&bad_version).err(),
+        Some(AssetFileError::UnsupportedVersion(2))
+    );
+
+    assert_eq!(
+        AssetFile::parse(&bytes[..ASSET_FILE_HEADER_SIZE - 1]).err(),
+        Some(AssetFileError::Truncated)
+    );
+}
+
+#[test]
+fn rejects_truncated_tables() {
+    let offset = payloads_offset(0, 1);
+    let bytes = build_file(&[], &[bitmap_entry(0, 0, offset)], &[0; 8]);
+    assert_eq!(
+        AssetFile::parse(&bytes[..ASSET_FILE_HEADER_SIZE + 10]).err(),
+        Some(AssetFileError::Truncated)
+    );
+}
+
+#[test]
+fn rejects_bad_entries() {
+    let offset = payloads_offset(0, 3);
+    let entries = [
+        bitmap_entry(0, 1, offset),
+        bitmap_entry(0, 0, offset + 4),
+        bitmap_entry(0, 0, u64::MAX),
+    ];
+    let bytes = build_file(&[], &entries, &[0; 8]);
+    let file = AssetFile::parse(&bytes).unwrap();
+    assert_eq!(file.entry(0), Err(AssetFileError::InvalidTagRange));
+    assert_eq!(file.entry(1), Err(AssetFileError::Truncated));
+    assert_eq!(file.entry(2), Err(AssetFileError::Truncated));
+    assert_eq!(file.entry(3), Err(AssetFileError::Truncated));
+
+    let mut bytes = build_file(&[], &[bitmap_entry(0, 0, offset)], &[0; 8]);
+    let at = ASSET_FILE_HEADER_SIZE;
+    bytes[at..at + 4].copy_from_slice(&99u32.to_le_bytes());
+    assert_eq!(
+        AssetFile::parse(&bytes).unwrap().entry(0),
+        Err(AssetFileError::UnknownAssetType(99))
+    );
+    bytes[at..at + 4].copy_from_slice(&1u32.to_le_bytes());
+    bytes[at + 12..at + 16].copy_from_slice(&7u32.to_le_bytes());
+    assert_eq!(
+        AssetFile::parse(&bytes).unwrap().entry(0),
+        Err(AssetFileError::UnknownAssetKind(7))
+    );
+}
+
+#[test]
+fn rejects_unknown_tags() {
+    let tag = AssetTag {
+        id: AssetTagId::FacingDirection,
+        value: 0.0,
+    };
+    let mut bytes = build_file(&[tag], &[], &[]);
+    let at = ASSET_FILE_HEADER_SIZE;
+    bytes[at..at + 4].copy_from_slice(&42u32.to_le_bytes());
+    let file = AssetFile::parse(&bytes).unwrap();
+    assert_eq!(file.tag(0), Err(AssetFileError::UnknownTag(42)));
+    assert_eq!(file.tag(1), Err(AssetFileError::InvalidTagRange));
+}
+
+#[test]
+fn tag_periods() {
+    assert_eq!(
+        AssetTagId::FacingDirection.period(),
+        Some(std::f32::consts::TAU)
+    );
+    assert_eq!(AssetTagId::Height.period(), None);
+    assert_eq!(AssetTagId::SizeClass.period(), None);
+}
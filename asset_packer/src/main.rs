@@ -0,0 +1,306 @@
+//! Builds a packed asset file from a manifest.
+//!
+//! Every non-empty line of the manifest that does not start with `#` describes one asset:
+//!
+//! ```text
+//! bitmap <type> <path> [align=<x>,<y>] [<tag>=<value> ...]
+//! sound <type> <path> [<tag>=<value> ...]
+//! ```
+//!
+//! Paths are relative to the data directory. Bitmaps can be BMP or PNG files, sounds have to be
+//! 16 bit PCM WAV files.
+
+extern crate handmade_asset;
+extern crate software_renderer;
+
+use std::env;
+use std::fs;
+use std::path::PathBuf;
+use std::process;
+
+use handmade_asset::*;
+use software_renderer::*;
+
+const USAGE: &str = "usage: asset_packer --manifest <file> --data <dir> --out <file>";
+
+struct PackerOptions {
+    manifest_path: PathBuf,
+    data_path: PathBuf,
+    output_path: PathBuf,
+}
+
+struct SourceAsset {
+    asset_type: AssetType,
+    tags: Vec<AssetTag>,
+    data: AssetData,
+    payload: Vec<u8>,
+}
+
+fn parse_options() -> Result<PackerOptions, String> {
+    let mut manifest_path = None;
+    let mut data_path = None;
+    let mut output_path = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("{} needs a value", arg))
+                .map(PathBuf::from)
+        };
+        match arg.as_str() {
+            "--manifest" => manifest_path = Some(value()?),
+            "--data" => data_path = Some(value()?),
+            "--out" => output_path = Some(value()?),
+            "--help" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+
+    Ok(PackerOptions {
+        manifest_path: manifest_path.ok_or("--manifest is required")?,
+        data_path: data_path.ok_or("--data is required")?,
+        output_path: output_path.ok_or("--out is required")?,
+    })
+}
+
+fn load_bitmap(bytes: &[u8]) -> Result<(BitmapInfo, Vec<u8>), String> {
+    let (width, height, format, pixels) = if bytes.starts_with(b"\x89PNG") {
+        let info = read_png_info(bytes).map_err(|err| format!("invalid PNG: {:?}", err))?;
+        let mut scratch = vec![0; info.scratch_size()];
+        let mut pixels = vec![0; info.pixel_count()];
+        decode_png(bytes, &mut scratch, &mut pixels)
+            .map_err(|err| format!("invalid PNG: {:?}", err))?;
+        (info.width, info.height, info.format, pixels)
+    } else {
+        let info = read_bmp_info(bytes).map_err(|err| format!("invalid BMP: {:?}", err))?;
+        let mut pixels = vec![0; info.pixel_count()];
+        decode_bmp(bytes, &mut pixels).map_err(|err| format!("invalid BMP: {:?}", err))?;
+        (info.width, info.height, info.format, pixels)
+    };
+
+    let info = BitmapInfo {
+        width: width as u32,
+        height: height as u32,
+        align_x: 0,
+        align_y: 0,
+        has_alpha: format == PixelFormat::Argb8888,
+    };
+    let payload = pixels
+        .iter()
+        .flat_map(|pixel| pixel.to_le_bytes())
+        .collect();
+    Ok((info, payload))
+}
+
+fn load_sound(bytes: &[u8]) -> Result<(SoundInfo, Vec<u8>), String> {
+    if bytes.len() < 12 || &bytes[0..4] != b"RIFF" || &bytes[8..12] != b"WAVE" {
+        return Err(String::from("not a WAV file"));
+    }
+
+    let mut channel_count = None;
+    let mut samples = None;
+    let mut at = 12;
+    while at + 8 <= bytes.len() {
+        let id = &bytes[at..at + 4];
+        let size = u32::from_le_bytes([bytes[at + 4], bytes[at + 5], bytes[at + 6], bytes[at + 7]])
+            as usize;
+        let data = bytes
+            .get(at + 8..at + 8 + size)
+            .ok_or("truncated WAV chunk")?;
+        match id {
+            b"fmt " => {
+                if data.len() < 16 {
+                    return Err(String::from("truncated fmt chunk"));
+                }
+                let format = u16::from_le_bytes([data[0], data[1]]);
+                let channels = u16::from_le_bytes([data[2], data[3]]);
+                let bits_per_sample = u16::from_le_bytes([data[14], data[15]]);
+                if format != 1 || bits_per_sample != 16 || channels == 0 {
+                    return Err(String::from("only 16 bit PCM WAV files are supported"));
+                }
+                channel_count = Some(channels as u32);
+            }
+            b"data" => samples = Some(data),
+            _ => {}
+        }
+        // NOTE: Chunks are padded to an even size.
+        at += 8 + size + (size & 1);
+    }
+
+    let channel_count = channel_count.ok_or("missing fmt chunk")?;
+    let samples = samples.ok_or("missing data chunk")?;
+    let frame_size = channel_count as usize * 2;
+    let info = SoundInfo {
+        sample_count: (samples.len() / frame_size) as u32,
+        channel_count,
+    };
+    Ok((
+        info,
+        samples[..info.sample_count as usize * frame_size].to_vec(),
+    ))
+}
+
+fn parse_manifest_line(options: &PackerOptions, line: &str) -> Result<SourceAsset, String> {
+    let mut fields = line.split_whitespace();
+    let kind = fields.next().ok_or("missing asset kind")?;
+    let type_name = fields.next().ok_or("missing asset type")?;
+    let asset_type = AssetType::from_name(type_name)
+        .ok_or_else(|| format!("unknown asset type {}", type_name))?;
+    let path = options
+        .data_path
+        .join(fields.next().ok_or("missing asset path")?);
+    let bytes =
+        fs::read(&path).map_err(|err| format!("failed to read {}: {}", path.display(), err))?;
+
+    let (mut data, payload) = match kind {
+        "bitmap" => {
+            let (info, payload) =
+                load_bitmap(&bytes).map_err(|err| format!("{}: {}", path.display(), err))?;
+            (AssetData::Bitmap(info), payload)
+        }
+        "sound" => {
+            let (info, payload) =
+                load_sound(&bytes).map_err(|err| format!("{}: {}", path.display(), err))?;
+            (AssetData::Sound(info), payload)
+        }
+        _ => return Err(format!("unknown asset kind {}", kind)),
+    };
+
+    let mut tags = Vec::new();
+    for field in fields {
+        let (key, value) = field
+            .split_once('=')
+            .ok_or_else(|| format!("expected <key>=<value>, got {}", field))?;
+        match (key, &mut data) {
+            ("align", AssetData::Bitmap(info)) => {
+                let (x, y) = value
+                    .split_once(',')
+                    .and_then(|(x, y)| Some((x.parse().ok()?, y.parse().ok()?)))
+                    .ok_or_else(|| format!("invalid alignment {}", value))?;
+                info.align_x = x;
+                info.align_y = y;
+            }
+            _ => {
+                let id =
+                    AssetTagId::from_name(key).ok_or_else(|| format!("unknown tag {}", key))?;
+                let value = value
+                    .parse()
+                    .map_err(|_| format!("invalid value {} for tag {}", value, key))?;
+                tags.push(AssetTag { id, value });
+            }
+        }
+    }
+
+    Ok(SourceAsset {
+        asset_type,
+        tags,
+        data,
+        payload,
+    })
+}
+
+fn align(offset: usize) -> usize {
+    (offset + PAYLOAD_ALIGNMENT - 1) & !(PAYLOAD_ALIGNMENT - 1)
+}
+
+fn pack(assets: &[SourceAsset]) -> Vec<u8> {
+    let tag_count: usize = assets.iter().map(|asset| asset.tags.len()).sum();
+    let tags_offset = ASSET_FILE_HEADER_SIZE;
+    let assets_offset = tags_offset + tag_count * ASSET_TAG_SIZE;
+    let payloads_offset = align(assets_offset + assets.len() * ASSET_ENTRY_SIZE);
+
+    let mut bytes = vec![0; payloads_offset];
+    let header = AssetFileHeader {
+        tag_count: tag_count as u32,
+        asset_count: assets.len() as u32,
+        tags_offset: tags_offset as u64,
+        assets_offset: assets_offset as u64,
+    };
+    let mut header_bytes = [0; ASSET_FILE_HEADER_SIZE];
+    header.write(&mut header_bytes);
+    bytes[..ASSET_FILE_HEADER_SIZE].copy_from_slice(&header_bytes);
+
+    let mut tag_index = 0;
+    for (asset_index, asset) in assets.iter().enumerate() {
+        let entry = AssetEntry {
+            asset_type: asset.asset_type,
+            first_tag: tag_index as u32,
+            tag_count: asset.tags.len() as u32,
+            data_offset: bytes.len() as u64,
+            data: asset.data,
+        };
+        let mut entry_bytes = [0; ASSET_ENTRY_SIZE];
+        entry.write(&mut entry_bytes);
+        let at = assets_offset + asset_index * ASSET_ENTRY_SIZE;
+        bytes[at..at + ASSET_ENTRY_SIZE].copy_from_slice(&entry_bytes);
+
+        for tag in asset.tags.iter() {
+            let mut tag_bytes = [0; ASSET_TAG_SIZE];
+            tag.write(&mut tag_bytes);
+            let at = tags_offset + tag_index * ASSET_TAG_SIZE;
+            bytes[at..at + ASSET_TAG_SIZE].copy_from_slice(&tag_bytes);
+            tag_index += 1;
+        }
+
+        bytes.extend_from_slice(&asset.payload);
+        bytes.resize(align(bytes.len()), 0);
+    }
+    bytes
+}
+
+fn run(options: &PackerOptions) -> Result<(), String> {
+    let manifest = fs::read_to_string(&options.manifest_path).map_err(|err| {
+        format!(
+            "failed to read {}: {}",
+            options.manifest_path.display(),
+            err
+        )
+    })?;
+
+    let mut assets = Vec::new();
+    for (line_index, line) in manifest.lines().enumerate() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        let asset = parse_manifest_line(options, line).map_err(|err| {
+            format!(
+                "{}:{}: {}",
+                options.manifest_path.display(),
+                line_index + 1,
+                err
+            )
+        })?;
+        assets.push(asset);
+    }
+
+    let bytes = pack(&assets);
+    fs::write(&options.output_path, &bytes)
+        .map_err(|err| format!("failed to write {}: {}", options.output_path.display(), err))?;
+    println!(
+        "packed {} assets into {} ({} bytes)",
+        assets.len(),
+        options.output_path.display(),
+        bytes.len()
+    );
+    Ok(())
+}
+
+fn main() {
+    let options = match parse_options() {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(err) = run(&options) {
+        eprintln!("{}", err);
+        process::exit(1);
+    }
+}
@@ -1,4 +1,4 @@
 @echo off
 
-build_game && build_win32 && copy_res
+build_game && build_win32 && copy_res && pack_assets
 
@@ -38,6 +38,28 @@ typedef DEBUG_PLATFORM_FREE_FILE_MEMORY(DebugPlatformFreeFileMemory);
 typedef DEBUG_PLATFORM_WRITE_ENTIRE_FILE(DebugPlatformWriteEntireFile);
 #endif
 
+typedef struct PlatformWorkQueue PlatformWorkQueue;
+
+#define PLATFORM_WORK_QUEUE_CALLBACK(name) void name(PlatformWorkQueue *queue, void *data)
+typedef PLATFORM_WORK_QUEUE_CALLBACK(PlatformWorkQueueCallback);
+
+#define PLATFORM_ADD_ENTRY(name) void name(PlatformWorkQueue *queue, PlatformWorkQueueCallback *callback, void *data)
+typedef PLATFORM_ADD_ENTRY(PlatformAddEntry);
+
+#define PLATFORM_COMPLETE_ALL_WORK(name) void name(PlatformWorkQueue *queue)
+typedef PLATFORM_COMPLETE_ALL_WORK(PlatformCompleteAllWork);
+
+typedef struct PlatformFile PlatformFile;
+
+#define PLATFORM_OPEN_FILE(name) PlatformFile *name(char *file_name)
+typedef PLATFORM_OPEN_FILE(PlatformOpenFile);
+
+#define PLATFORM_READ_DATA_FROM_FILE(name) int name(PlatformFile *file, uint64_t offset, uint64_t size, void *dest)
+typedef PLATFORM_READ_DATA_FROM_FILE(PlatformReadDataFromFile);
+
+#define PLATFORM_CLOSE_FILE(name) void name(PlatformFile *file)
+typedef PLATFORM_CLOSE_FILE(PlatformCloseFile);
+
 typedef struct GameMemory {
     int is_initialized;
     size_t permanent_storage_size;
@@ -48,6 +70,16 @@ typedef struct GameMemory {
     DebugPlatformReadEntireFile *debug_platform_read_entire_file;
     DebugPlatformFreeFileMemory *debug_platform_free_file_memory;
     DebugPlatformWriteEntireFile *debug_platform_write_entire_file;
+
+    // NOTE: Optional, when these are null the game does its background work right away and
+    // reads files through debug_platform_read_entire_file.
+    PlatformWorkQueue *work_queue;
+    PlatformAddEntry *platform_add_entry;
+    PlatformCompleteAllWork *platform_complete_all_work;
+
+    PlatformOpenFile *platform_open_file;
+    PlatformReadDataFromFile *platform_read_data_from_file;
+    PlatformCloseFile *platform_close_file;
 } GameMemory;
 
 typedef struct GameOffscreenBuffer {
@@ -0,0 +1,459 @@
+//! Assets are streamed in from the packed asset file on demand. The asset table is loaded once,
+//! payloads are loaded through the platform work queue into a slab of transient storage when
+//! they are first used and evicted again, least recently used first, when the slab is full.
+
+use core::sync::atomic::{AtomicU32, Ordering};
+
+use handmade_asset::*;
+
+use software_renderer::*;
+
+use memory::{AllocError, ArenaArray, MemoryArena};
+use platform_add_entry;
+use {platform_close_file, platform_open_file, platform_read_file_range};
+use {PlatformFileHandle, PlatformWorkQueue};
+
+const ASSET_FILE_NAME: &str = "test.hha\0";
+
+/// Size of the transient storage payloads are streamed into.
+pub const ASSET_SLAB_SIZE: usize = 16 * 1024 * 1024;
+
+/// Alignment of payloads inside the slab, the slab itself has to start on this boundary too.
+pub const ASSET_SLAB_ALIGNMENT: usize = 16;
+
+const ASSET_STATE_UNLOADED: u32 = 0;
+const ASSET_STATE_QUEUED: u32 = 1;
+const ASSET_STATE_LOADED: u32 = 2;
+const ASSET_STATE_FAILED: u32 = 3;
+
+/// Index of an asset in the packed asset file.
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub struct AssetId(u32);
+
+pub struct BitmapAsset<'a> {
+    pub bitmap: Bitmap<'a>,
+    pub align_x: i32,
+    pub align_y: i32,
+}
+
+pub struct SoundAsset<'a> {
+    pub samples: &'a [i16],
+    pub channel_count: u32,
+}
+
+/// Everything a work queue entry needs to load one payload. The main thread leaves it alone
+/// while the asset is queued.
+struct LoadAssetWork {
+    file: Option<PlatformFileHandle>,
+    file_offset: u64,
+    memory: *mut u8,
+    size: usize,
+    state: *const AtomicU32,
+}
+
+struct Asset {
+    asset_type: AssetType,
+    first_tag: u32,
+    tag_count: u32,
+    data_offset: u64,
+    data: AssetData,
+
+    /// One of the `ASSET_STATE_*` values. Only a work queue entry moves an asset out of
+    /// `ASSET_STATE_QUEUED`, everything else happens on the main thread.
+    state: AtomicU32,
+    /// Range of the slab the payload occupies while the asset is queued or loaded.
+    memory_offset: usize,
+    memory_size: usize,
+    last_used_frame: u64,
+    work: LoadAssetWork,
+}
+
+impl Asset {
+    fn is_resident(&self) -> bool {
+        let state = self.state.load(Ordering::Acquire);
+        state == ASSET_STATE_QUEUED || state == ASSET_STATE_LOADED
+    }
+}
+
+/// One term of a best-match query: the wanted value of a tag and how much a difference in that
+/// tag counts compared to the other terms.
+#[derive(Copy, Clone, Debug)]
+pub struct TagMatch {
+    pub id: AssetTagId,
+    pub value: f32,
+    pub weight: f32,
+}
+
+impl TagMatch {
+    pub fn new(id: AssetTagId, value: f32) -> TagMatch {
+        TagMatch {
+            id,
+            value,
+            weight: 1.0,
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum LoadAssetsError {
+    /// The platform could not read the file.
+    Read,
+    File(AssetFileError),
+    OutOfMemory(AllocError),
+}
+
+pub struct Assets {
+    /// The asset file stays open for as long as the game runs, so streaming a payload in does
+    /// not have to open it again.
+    file: Option<PlatformFileHandle>,
+    tags: ArenaArray<AssetTag>,
+    assets: ArenaArray<Asset>,
+    slab: *mut u8,
+    slab_size: usize,
+    frame_index: u64,
+}
+
+impl Assets {
+    pub fn empty() -> Assets {
+        Assets {
+            file: None,
+            tags: ArenaArray::empty(),
+            assets: ArenaArray::empty(),
+            slab: core::ptr::null_mut(),
+            slab_size: 0,
+            frame_index: 0,
+        }
+    }
+
+    /// Loads the asset table into `arena`, payloads are streamed into `slab` later on. The
+    /// tables are read into temporary memory of `scratch`.
+    pub unsafe fn load(
+        arena: &mut MemoryArena,
+        slab: &'static mut [u8],
+        scratch: &mut MemoryArena,
+    ) -> Result<Assets, LoadAssetsError> {
+        debug_assert!((slab.as_ptr() as usize).is_multiple_of(ASSET_SLAB_ALIGNMENT));
+        let file_name = ASSET_FILE_NAME.as_ptr() as *const i8;
+        // NOTE: A load that fails drops `result` again, which closes the file.
+        let mut result = Assets::empty();
+        result.file = platform_open_file(file_name);
+        let file = result.file;
+        let mut scratch = scratch.begin_temporary_memory();
+
+        let mut header = [0; ASSET_FILE_HEADER_SIZE];
+        if !platform_read_file_range(file, file_name, 0, &mut header) {
+            return Err(LoadAssetsError::Read);
+        }
+        let header = AssetFileHeader::read(&header).map_err(LoadAssetsError::File)?;
+
+        let tags_size = header.tag_count as usize * ASSET_TAG_SIZE;
+        let entries_size = header.asset_count as usize * ASSET_ENTRY_SIZE;
+        let tag_bytes = scratch
+            .try_alloc_array_uninit::<u8>(tags_size)
+            .map_err(LoadAssetsError::OutOfMemory)?
+            .into_slice_mut();
+        let entry_bytes = scratch
+            .try_alloc_array_uninit::<u8>(entries_size)
+            .map_err(LoadAssetsError::OutOfMemory)?
+            .into_slice_mut();
+        if !platform_read_file_range(file, file_name, header.tags_offset, tag_bytes)
+            || !platform_read_file_range(file, file_name, header.assets_offset, entry_bytes)
+        {
+            return Err(LoadAssetsError::Read);
+        }
+
+        // NOTE: Validate the whole table before allocating, so a bad file leaves the arena alone.
+        for bytes in tag_bytes.chunks_exact(ASSET_TAG_SIZE) {
+            read_tag(bytes).map_err(LoadAssetsError::File)?;
+        }
+        for bytes in entry_bytes.chunks_exact(ASSET_ENTRY_SIZE) {
+            let entry = read_entry(bytes).map_err(LoadAssetsError::File)?;
+            if entry.first_tag as u64 + entry.tag_count as u64 > header.tag_count as u64 {
+                return Err(LoadAssetsError::File(AssetFileError::InvalidTagRange));
+            }
+        }
+
+        let mut tags = arena
+            .try_alloc_array_uninit::<AssetTag>(header.tag_count as usize)
+            .map_err(LoadAssetsError::OutOfMemory)?;
+        for (tag, bytes) in tags.iter_mut().zip(tag_bytes.chunks_exact(ASSET_TAG_SIZE)) {
+            core::ptr::write(tag, read_tag(bytes).unwrap());
+        }
+
+        let mut assets = arena
+            .try_alloc_array_uninit::<Asset>(header.asset_count as usize)
+            .map_err(LoadAssetsError::OutOfMemory)?;
+        for (asset, bytes) in assets
+            .iter_mut()
+            .zip(entry_bytes.chunks_exact(ASSET_ENTRY_SIZE))
+        {
+            let entry = read_entry(bytes).unwrap();
+            core::ptr::write(
+                asset,
+                Asset {
+                    asset_type: entry.asset_type,
+                    first_tag: entry.first_tag,
+                    tag_count: entry.tag_count,
+                    data_offset: entry.data_offset,
+                    data: entry.data,
+                    state: AtomicU32::new(ASSET_STATE_UNLOADED),
+                    memory_offset: 0,
+                    memory_size: 0,
+                    last_used_frame: 0,
+                    work: LoadAssetWork {
+                        file: None,
+                        file_offset: 0,
+                        memory: core::ptr::null_mut(),
+                        size: 0,
+                        state: core::ptr::null(),
+                    },
+                },
+            );
+        }
+
+        result.tags = tags;
+        result.assets = assets;
+        result.slab = slab.as_mut_ptr();
+        result.slab_size = slab.len();
+        Ok(result)
+    }
+
+    /// Assets are evicted least recently used first, but never in the frame they were used in.
+    pub fn begin_frame(&mut self) {
+        self.frame_index += 1;
+    }
+
+    /// All assets of `asset_type`, in the order of the asset manifest.
+    pub fn of_type(&self, asset_type: AssetType) -> impl Iterator<Item = AssetId> + '_ {
+        (0..self.assets.len() as u32)
+            .filter(move |&index| self.asset(AssetId(index)).asset_type == asset_type)
+            .map(AssetId)
+    }
+
+    pub fn first(&self, asset_type: AssetType) -> Option<AssetId> {
+        self.of_type(asset_type).next()
+    }
+
+    /// The asset of `asset_type` whose tags are closest to `matches`, measured as the weighted
+    /// sum of the differences. An asset without one of the tags counts as having it at 0, and
+    /// ties go to the asset that comes first in the manifest.
+    pub fn best_match(&self, asset_type: AssetType, matches: &[TagMatch]) -> Option<AssetId> {
+        let mut best = None;
+        let mut best_distance = f32::INFINITY;
+        for id in self.of_type(asset_type) {
+            let distance: f32 = matches
+                .iter()
+                .map(|tag_match| {
+                    let value = self.tag_value(id, tag_match.id).unwrap_or(0.0);
+                    tag_match.weight * tag_distance(tag_match.id, value, tag_match.value)
+                })
+                .sum();
+            if best.is_none() || distance < best_distance {
+                best = Some(id);
+                best_distance = distance;
+            }
+        }
+        best
+    }
+
+    pub fn tag_value(&self, id: AssetId, tag_id: AssetTagId) -> Option<f32> {
+        let asset = self.asset(id);
+        (asset.first_tag..asset.first_tag + asset.tag_count)
+            .filter_map(|index| self.tags.get(index as usize))
+            .find(|tag| tag.id == tag_id)
+            .map(|tag| tag.value)
+    }
+
+    fn asset(&self, id: AssetId) -> &Asset {
+        self.assets.get(id.0 as usize).unwrap()
+    }
+
+    fn asset_mut(&mut self, id: AssetId) -> &mut Asset {
+        self.assets.get_mut(id.0 as usize).unwrap()
+    }
+
+    /// The bitmap if it is resident. A bitmap that is not gets queued for loading, so it shows
+    /// up in a later frame.
+    pub fn bitmap(&mut self, id: AssetId) -> Option<BitmapAsset<'_>> {
+        let info = match self.asset(id).data {
+            AssetData::Bitmap(info) => info,
+            _ => return None,
+        };
+        let pixels = self.use_asset(id)? as *mut u32;
+
+        let (width, height) = (info.width as usize, info.height as usize);
+        let pixels = unsafe { core::slice::from_raw_parts_mut(pixels, width * height) };
+        let format = if info.has_alpha {
+            PixelFormat::Argb8888
+        } else {
+            PixelFormat::Xrgb8888
+        };
+        Some(BitmapAsset {
+            bitmap: Bitmap::from_pixels(pixels, width, height, width, format),
+            align_x: info.align_x,
+            align_y: info.align_y,
+        })
+    }
+
+    pub fn sound(&mut self, id: AssetId) -> Option<SoundAsset<'_>> {
+        let info = match self.asset(id).data {
+            AssetData::Sound(info) => info,
+            _ => return None,
+        };
+        let samples = self.use_asset(id)? as *const i16;
+
+        let sample_count = info.sample_count as usize * info.channel_count as usize;
+        Some(SoundAsset {
+            samples: unsafe { core::slice::from_raw_parts(samples, sample_count) },
+            channel_count: info.channel_count,
+        })
+    }
+
+    /// Marks the asset as used in this frame and returns its payload if it is resident,
+    /// queueing it for loading if it is not.
+    fn use_asset(&mut self, id: AssetId) -> Option<*mut u8> {
+        let frame_index = self.frame_index;
+        self.asset_mut(id).last_used_frame = frame_index;
+        if self.asset(id).state.load(Ordering::Acquire) == ASSET_STATE_UNLOADED {
+            self.queue_load(id);
+        }
+
+        // NOTE: Without a work queue the asset is loaded by now.
+        let asset = self.asset(id);
+        if asset.state.load(Ordering::Acquire) == ASSET_STATE_LOADED {
+            Some(unsafe { self.slab.add(asset.memory_offset) })
+        } else {
+            None
+        }
+    }
+
+    fn queue_load(&mut self, id: AssetId) {
+        let payload_size = self.asset(id).data.payload_size();
+        if payload_size > self.slab_size as u64 {
+            self.asset(id)
+                .state
+                .store(ASSET_STATE_FAILED, Ordering::Relaxed);
+            return;
+        }
+        let size = payload_size as usize;
+        let memory_size = (size + ASSET_SLAB_ALIGNMENT - 1) & !(ASSET_SLAB_ALIGNMENT - 1);
+
+        // NOTE: When everything resident was used this frame, try again next frame.
+        let memory_offset = match self.allocate(memory_size) {
+            Some(memory_offset) => memory_offset,
+            None => return,
+        };
+        let memory = unsafe { self.slab.add(memory_offset) };
+
+        let file = self.file;
+        let asset = self.asset_mut(id);
+        asset.memory_offset = memory_offset;
+        asset.memory_size = memory_size;
+        asset.work = LoadAssetWork {
+            file,
+            file_offset: asset.data_offset,
+            memory,
+            size,
+            state: &asset.state,
+        };
+        asset.state.store(ASSET_STATE_QUEUED, Ordering::Relaxed);
+        platform_add_entry(
+            load_asset_work,
+            &mut asset.work as *mut LoadAssetWork as *mut core::ffi::c_void,
+        );
+    }
+
+    /// Finds room for `size` bytes in the slab, evicting loaded assets as needed.
+    fn allocate(&mut self, size: usize) -> Option<usize> {
+        loop {
+            if let Some(offset) = self.find_free_range(size) {
+                return Some(offset);
+            }
+
+            let frame_index = self.frame_index;
+            let evicted = self
+                .assets
+                .iter_mut()
+                .filter(|asset| {
+                    asset.state.load(Ordering::Acquire) == ASSET_STATE_LOADED
+                        && asset.last_used_frame < frame_index
+                })
+                .min_by_key(|asset| asset.last_used_frame)?;
+            evicted.state.store(ASSET_STATE_UNLOADED, Ordering::Relaxed);
+        }
+    }
+
+    /// First fit, a free range starts either at the start of the slab or right after a payload.
+    fn find_free_range(&self, size: usize) -> Option<usize> {
+        let overlaps_resident = |start: usize| {
+            self.assets.iter().any(|asset| {
+                asset.is_resident()
+                    && start < asset.memory_offset + asset.memory_size
+                    && asset.memory_offset < start + size
+            })
+        };
+        core::iter::once(0)
+            .chain(
+                self.assets
+                    .iter()
+                    .filter(|asset| asset.is_resident())
+                    .map(|asset| asset.memory_offset + asset.memory_size),
+            )
+            .filter(|&start| start + size <= self.slab_size)
+            .find(|&start| !overlaps_resident(start))
+    }
+}
+
+/// NOTE: Only an `Assets` without loads in flight may be dropped, the work queue reads from the
+/// file.
+impl Drop for Assets {
+    fn drop(&mut self) {
+        if let Some(file) = self.file.take() {
+            platform_close_file(file);
+        }
+    }
+}
+
+extern "C" fn load_asset_work(_queue: *mut PlatformWorkQueue, data: *mut core::ffi::c_void) {
+    unsafe {
+        let work = &*(data as *const LoadAssetWork);
+        let memory = core::slice::from_raw_parts_mut(work.memory, work.size);
+        // NOTE: Payloads are little endian like every platform the game runs on, so they are
+        // read straight into place.
+        let loaded = platform_read_file_range(
+            work.file,
+            ASSET_FILE_NAME.as_ptr() as *const i8,
+            work.file_offset,
+            memory,
+        );
+        let state = if loaded {
+            ASSET_STATE_LOADED
+        } else {
+            ASSET_STATE_FAILED
+        };
+        (*work.state).store(state, Ordering::Release);
+    }
+}
+
+fn read_tag(bytes: &[u8]) -> Result<AssetTag, AssetFileError> {
+    let mut tag = [0; ASSET_TAG_SIZE];
+    tag.copy_from_slice(bytes);
+    AssetTag::read(&tag)
+}
+
+fn read_entry(bytes: &[u8]) -> Result<AssetEntry, AssetFileError> {
+    let mut entry = [0; ASSET_ENTRY_SIZE];
+    entry.copy_from_slice(bytes);
+    AssetEntry::read(&entry)
+}
+
+fn tag_distance(id: AssetTagId, a: f32, b: f32) -> f32 {
+    let distance = (a - b).abs();
+    match id.period() {
+        Some(period) => {
+            let distance = distance % period;
+            distance.min(period - distance)
+        }
+        None => distance,
+    }
+}
@@ -0,0 +1,545 @@
+//! Growable containers backed by a `MemoryArena`.
+//!
+//! The containers do not remember the arena they allocate from, everything that can grow takes
+//! the arena as an argument. Growing abandons the old storage inside the arena unless it was the
+//! last allocation, in which case it is extended in place.
+
+use core::hash::{Hash, Hasher};
+use core::ops::{Deref, DerefMut};
+use core::ptr::NonNull;
+
+use memory::{AllocError, ArenaArray, MemoryArena};
+
+const MIN_VEC_CAPACITY: usize = 4;
+const MIN_MAP_CAPACITY: usize = 8;
+
+pub struct ArenaVec<T> {
+    ptr: *mut T,
+    len: usize,
+    capacity: usize,
+}
+
+impl<T> ArenaVec<T> {
+    pub fn new() -> ArenaVec<T> {
+        ArenaVec {
+            ptr: NonNull::dangling().as_ptr(),
+            len: 0,
+            capacity: 0,
+        }
+    }
+
+    #[track_caller]
+    pub fn with_capacity(arena: &mut MemoryArena, capacity: usize) -> ArenaVec<T> {
+        let mut vec = ArenaVec::new();
+        vec.reserve(arena, capacity);
+        vec
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    #[track_caller]
+    pub fn push(&mut self, arena: &mut MemoryArena, value: T) {
+        if let Err(err) = self.try_push(arena, value) {
+            arena.out_of_memory(err);
+        }
+    }
+
+    #[track_caller]
+    pub fn try_push(&mut self, arena: &mut MemoryArena, value: T) -> Result<(), AllocError> {
+        if self.len == self.capacity {
+            self.try_grow(arena, self.len + 1)?;
+        }
+        unsafe { core::ptr::write(self.ptr.add(self.len), value) };
+        self.len += 1;
+        Ok(())
+    }
+
+    /// Makes room for at least `additional` more elements.
+    #[track_caller]
+    pub fn reserve(&mut self, arena: &mut MemoryArena, additional: usize) {
+        if let Err(err) = self.try_reserve(arena, additional) {
+            arena.out_of_memory(err);
+        }
+    }
+
+    #[track_caller]
+    pub fn try_reserve(
+        &mut self,
+        arena: &mut MemoryArena,
+        additional: usize,
+    ) -> Result<(), AllocError> {
+        let min_capacity = self.len.saturating_add(additional);
+        if min_capacity > self.capacity {
+            self.try_grow(arena, min_capacity)?;
+        }
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        Some(unsafe { core::ptr::read(self.ptr.add(self.len)) })
+    }
+
+    /// Removes the element at `index` and shifts the following elements down.
+    pub fn remove(&mut self, index: usize) -> T {
+        assert!(index < self.len, "index {} out of bounds", index);
+        unsafe {
+            let at = self.ptr.add(index);
+            let value = core::ptr::read(at);
+            core::ptr::copy(at.add(1), at, self.len - index - 1);
+            self.len -= 1;
+            value
+        }
+    }
+
+    /// Removes the element at `index` and moves the last element into its place.
+    pub fn swap_remove(&mut self, index: usize) -> T {
+        assert!(index < self.len, "index {} out of bounds", index);
+        self.len -= 1;
+        unsafe {
+            let at = self.ptr.add(index);
+            let value = core::ptr::read(at);
+            core::ptr::copy(self.ptr.add(self.len), at, 1);
+            value
+        }
+    }
+
+    pub fn clear(&mut self) {
+        let elements: *mut [T] = &mut **self;
+        self.len = 0;
+        unsafe { core::ptr::drop_in_place(elements) };
+    }
+
+    #[track_caller]
+    fn try_grow(&mut self, arena: &mut MemoryArena, min_capacity: usize) -> Result<(), AllocError> {
+        let capacity = min_capacity
+            .max(self.capacity.saturating_mul(2))
+            .max(MIN_VEC_CAPACITY);
+
+        let element_size = core::mem::size_of::<T>();
+        if self.capacity > 0 {
+            if let Some(new_size) = element_size.checked_mul(capacity) {
+                let size = element_size * self.capacity;
+                if arena.try_extend(self.ptr as *mut u8, size, new_size) {
+                    self.capacity = capacity;
+                    return Ok(());
+                }
+            }
+        }
+
+        let mut storage = unsafe { arena.try_alloc_array_uninit::<T>(capacity)? };
+        unsafe { core::ptr::copy_nonoverlapping(self.ptr, storage.as_mut_ptr(), self.len) };
+        self.ptr = storage.as_mut_ptr();
+        self.capacity = capacity;
+        Ok(())
+    }
+}
+
+impl<T> Deref for ArenaVec<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { core::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl<T> DerefMut for ArenaVec<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { core::slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+/// FNV-1a, good enough for the small keys the game uses.
+struct FnvHasher(u64);
+
+impl Hasher for FnvHasher {
+    fn finish(&self) -> u64 {
+        self.0
+    }
+
+    fn write(&mut self, bytes: &[u8]) {
+        for &byte in bytes {
+            self.0 ^= byte as u64;
+            self.0 = self.0.wrapping_mul(0x0000_0100_0000_01b3);
+        }
+    }
+}
+
+fn hash_key<K: Hash>(key: &K) -> usize {
+    let mut hasher = FnvHasher(0xcbf2_9ce4_8422_2325);
+    key.hash(&mut hasher);
+    hasher.finish() as usize
+}
+
+/// Open addressing hash map with linear probing. Removal shifts the following entries back
+/// instead of leaving tombstones, so lookups never have to skip deleted slots.
+pub struct ArenaHashMap<K, V> {
+    slots: ArenaArray<Option<(K, V)>>,
+    len: usize,
+}
+
+impl<K: Hash + Eq, V> ArenaHashMap<K, V> {
+    pub fn new() -> ArenaHashMap<K, V> {
+        ArenaHashMap {
+            slots: ArenaArray::empty(),
+            len: 0,
+        }
+    }
+
+    pub fn get(&self, key: &K) -> Option<&V> {
+        let index = self.find(key)?;
+        self.slots[index].as_ref().map(|(_, value)| value)
+    }
+
+    pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
+        let index = self.find(key)?;
+        self.slots[index].as_mut().map(|(_, value)| value)
+    }
+
+    pub fn contains_key(&self, key: &K) -> bool {
+        self.find(key).is_some()
+    }
+
+    /// Inserts `value` under `key` and returns the value it replaced.
+    #[track_caller]
+    pub fn insert(&mut self, arena: &mut MemoryArena, key: K, value: V) -> Option<V> {
+        match self.try_insert(arena, key, value) {
+            Ok(old_value) => old_value,
+            Err(err) => arena.out_of_memory(err),
+        }
+    }
+
+    #[track_caller]
+    pub fn try_insert(
+        &mut self,
+        arena: &mut MemoryArena,
+        key: K,
+        value: V,
+    ) -> Result<Option<V>, AllocError> {
+        if let Some(index) = self.find(&key) {
+            let (_, old_value) = self.slots[index].as_mut().unwrap();
+            return Ok(Some(core::mem::replace(old_value, value)));
+        }
+
+        let index = self.try_make_room(arena, &key)?;
+        self.slots[index] = Some((key, value));
+        self.len += 1;
+        Ok(None)
+    }
+
+    /// Returns the value under `key`, inserting the result of `make_value` first if there is none.
+    #[track_caller]
+    pub fn get_or_insert_with<F: FnOnce() -> V>(
+        &mut self,
+        arena: &mut MemoryArena,
+        key: K,
+        make_value: F,
+    ) -> &mut V {
+        let index = match self.find(&key) {
+            Some(index) => index,
+            None => {
+                let index = match self.try_make_room(arena, &key) {
+                    Ok(index) => index,
+                    Err(err) => arena.out_of_memory(err),
+                };
+                self.slots[index] = Some((key, make_value()));
+                self.len += 1;
+                index
+            }
+        };
+        self.slots[index].as_mut().map(|(_, value)| value).unwrap()
+    }
+
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        let mut hole = self.find(key)?;
+        let (_, value) = self.slots[hole].take().unwrap();
+        self.len -= 1;
+
+        // NOTE: Shift back every entry of the probe run that would no longer be reachable across
+        // the hole.
+        let mask = self.slots.len() - 1;
+        let mut index = hole;
+        loop {
+            index = (index + 1) & mask;
+            let home = match self.slots[index] {
+                Some((ref key, _)) => hash_key(key) & mask,
+                None => break,
+            };
+            if (index.wrapping_sub(home) & mask) >= (index.wrapping_sub(hole) & mask) {
+                self.slots[hole] = self.slots[index].take();
+                hole = index;
+            }
+        }
+        Some(value)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&K, &V)> {
+        self.slots
+            .iter()
+            .filter_map(|slot| slot.as_ref().map(|(key, value)| (key, value)))
+    }
+
+    fn find(&self, key: &K) -> Option<usize> {
+        if self.len == 0 {
+            return None;
+        }
+        let mask = self.slots.len() - 1;
+        let mut index = hash_key(key) & mask;
+        loop {
+            match self.slots[index] {
+                Some((ref slot_key, _)) if slot_key == key => return Some(index),
+                Some(_) => index = (index + 1) & mask,
+                None => return None,
+            }
+        }
+    }
+
+    /// Returns the free slot `key` goes into, growing the map first if it is too full.
+    #[track_caller]
+    fn try_make_room(&mut self, arena: &mut MemoryArena, key: &K) -> Result<usize, AllocError> {
+        // NOTE: Keep the load factor at most 3/4 so probe runs stay short and there is always a
+        // free slot to stop at.
+        if (self.len + 1) * 4 > self.slots.len() * 3 {
+            let slot_count = (self.slots.len() * 2).max(MIN_MAP_CAPACITY);
+            self.try_resize(arena, slot_count)?;
+        }
+
+        let mask = self.slots.len() - 1;
+        let mut index = hash_key(key) & mask;
+        while self.slots[index].is_some() {
+            index = (index + 1) & mask;
+        }
+        Ok(index)
+    }
+
+    #[track_caller]
+    fn try_resize(&mut self, arena: &mut MemoryArena, slot_count: usize) -> Result<(), AllocError> {
+        debug_assert!(slot_count.is_power_of_two());
+        let mut slots = unsafe { arena.try_alloc_array_uninit::<Option<(K, V)>>(slot_count)? };
+        for index in 0..slot_count {
+            unsafe { core::ptr::write(slots.as_mut_ptr().add(index), None) };
+        }
+
+        let mut old_slots = core::mem::replace(&mut self.slots, slots);
+        let mask = slot_count - 1;
+        for slot in old_slots.iter_mut() {
+            if let Some((key, value)) = slot.take() {
+                let mut index = hash_key(&key) & mask;
+                while self.slots[index].is_some() {
+                    index = (index + 1) & mask;
+                }
+                self.slots[index] = Some((key, value));
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+    use core::hash::{Hash, Hasher};
+
+    use super::*;
+
+    #[repr(align(16))]
+    struct Buffer([u8; 16 * 1024]);
+
+    fn arena_over(buffer: &mut Buffer) -> MemoryArena {
+        MemoryArena::from_raw_parts("test", buffer.0.as_mut_ptr(), buffer.0.len())
+    }
+
+    /// Keys with the same `home` hash the same, so they collide on purpose.
+    #[derive(Clone, Copy, Debug, PartialEq, Eq)]
+    struct Key {
+        home: u32,
+        id: u32,
+    }
+
+    impl Hash for Key {
+        fn hash<H: Hasher>(&self, state: &mut H) {
+            self.home.hash(state);
+        }
+    }
+
+    /// A `home` whose keys start probing at `slot` in a map with `slot_count` slots.
+    fn home_for_slot(slot: usize, slot_count: usize) -> u32 {
+        (0..)
+            .find(|&home| hash_key(&Key { home, id: 0 }) & (slot_count - 1) == slot)
+            .unwrap()
+    }
+
+    fn slot_of(map: &ArenaHashMap<Key, u32>, key: Key) -> usize {
+        map.find(&key).unwrap()
+    }
+
+    #[test]
+    fn colliding_keys_wrap_around_the_end_of_the_slots() {
+        let mut buffer = Buffer([0; 16 * 1024]);
+        let mut arena = arena_over(&mut buffer);
+        let mut map = ArenaHashMap::new();
+        let last = home_for_slot(MIN_MAP_CAPACITY - 1, MIN_MAP_CAPACITY);
+        for id in 0..3 {
+            assert_eq!(map.insert(&mut arena, Key { home: last, id }, id), None);
+        }
+        assert_eq!(map.slots.len(), MIN_MAP_CAPACITY);
+
+        assert_eq!(
+            slot_of(&map, Key { home: last, id: 0 }),
+            MIN_MAP_CAPACITY - 1
+        );
+        assert_eq!(slot_of(&map, Key { home: last, id: 1 }), 0);
+        assert_eq!(slot_of(&map, Key { home: last, id: 2 }), 1);
+        for id in 0..3 {
+            assert_eq!(map.get(&Key { home: last, id }), Some(&id));
+        }
+        assert_eq!(map.get(&Key { home: last, id: 3 }), None);
+
+        assert_eq!(
+            map.insert(&mut arena, Key { home: last, id: 1 }, 10),
+            Some(1)
+        );
+        assert_eq!(map.get(&Key { home: last, id: 1 }), Some(&10));
+        assert_eq!(map.iter().count(), 3);
+    }
+
+    #[test]
+    fn removal_shifts_the_probe_run_back() {
+        let mut buffer = Buffer([0; 16 * 1024]);
+        let mut arena = arena_over(&mut buffer);
+        let mut map = ArenaHashMap::new();
+        let last = home_for_slot(MIN_MAP_CAPACITY - 1, MIN_MAP_CAPACITY);
+        let first = home_for_slot(0, MIN_MAP_CAPACITY);
+        let second = home_for_slot(1, MIN_MAP_CAPACITY);
+        let a = Key { home: last, id: 0 };
+        let b = Key { home: last, id: 1 };
+        let c = Key { home: first, id: 2 };
+        let d = Key {
+            home: second,
+            id: 3,
+        };
+        for (value, &key) in [a, b, c, d].iter().enumerate() {
+            map.insert(&mut arena, key, value as u32);
+        }
+        // NOTE: b wraps around to slot 0, which pushes c and d one slot past their homes.
+        assert_eq!(slot_of(&map, c), 1);
+        assert_eq!(slot_of(&map, d), 2);
+
+        assert_eq!(map.remove(&a), Some(0));
+        assert_eq!(map.remove(&a), None);
+        assert_eq!(slot_of(&map, b), MIN_MAP_CAPACITY - 1);
+        assert_eq!(slot_of(&map, c), 0);
+        assert_eq!(slot_of(&map, d), 1);
+
+        assert_eq!(map.remove(&c), Some(2));
+        assert_eq!(slot_of(&map, b), MIN_MAP_CAPACITY - 1);
+        assert_eq!(slot_of(&map, d), 1);
+        assert!(map.slots[0].is_none());
+
+        assert_eq!(map.get(&a), None);
+        assert_eq!(map.get(&b), Some(&1));
+        assert_eq!(map.get(&c), None);
+        assert_eq!(map.get(&d), Some(&3));
+        assert_eq!(map.iter().count(), 2);
+    }
+
+    #[test]
+    fn maps_grow_and_keep_their_entries() {
+        let mut buffer = Buffer([0; 16 * 1024]);
+        let mut arena = arena_over(&mut buffer);
+        let mut map = ArenaHashMap::new();
+        for id in 0..100 {
+            map.insert(&mut arena, Key { home: id % 7, id }, id);
+            assert!(map.slots.len().is_power_of_two());
+            assert!(4 * map.iter().count() <= 3 * map.slots.len());
+        }
+        for id in 0..100 {
+            assert_eq!(map.get(&Key { home: id % 7, id }), Some(&id));
+        }
+        for id in (0..100).filter(|id| id % 2 == 0) {
+            assert_eq!(map.remove(&Key { home: id % 7, id }), Some(id));
+        }
+        for id in 0..100 {
+            let expected = if id % 2 == 0 { None } else { Some(&id) };
+            assert_eq!(map.get(&Key { home: id % 7, id }), expected);
+        }
+    }
+
+    #[test]
+    fn vecs_grow_in_place_when_they_were_allocated_last() {
+        let mut buffer = Buffer([0; 16 * 1024]);
+        let mut arena = arena_over(&mut buffer);
+        let mut vec = ArenaVec::new();
+        vec.push(&mut arena, 0u32);
+        let ptr = vec.as_ptr();
+        for value in 1..100 {
+            vec.push(&mut arena, value);
+        }
+        assert_eq!(vec.as_ptr(), ptr);
+
+        let mut other = ArenaVec::new();
+        other.push(&mut arena, 0u32);
+        vec.try_reserve(&mut arena, 100).unwrap();
+        assert_ne!(vec.as_ptr(), ptr);
+        assert!(vec.iter().cloned().eq(0..100));
+
+        assert_eq!(vec.swap_remove(10), 10);
+        assert_eq!(vec[10], 99);
+        assert_eq!(vec.pop(), Some(98));
+        assert_eq!(vec.len(), 98);
+    }
+
+    #[test]
+    fn vec_removal_keeps_the_order() {
+        let mut buffer = Buffer([0; 16 * 1024]);
+        let mut arena = arena_over(&mut buffer);
+        let mut vec = ArenaVec::with_capacity(&mut arena, 10);
+        let ptr = vec.as_ptr();
+        for value in 0..10u32 {
+            vec.push(&mut arena, value);
+        }
+        assert_eq!(vec.as_ptr(), ptr);
+
+        assert_eq!(vec.remove(3), 3);
+        assert_eq!(vec.remove(0), 0);
+        assert_eq!(vec.remove(7), 9);
+        assert_eq!(&vec[..], &[1, 2, 4, 5, 6, 7, 8]);
+
+        vec.clear();
+        assert!(vec.is_empty());
+        vec.push(&mut arena, 20);
+        assert_eq!(&vec[..], &[20]);
+    }
+
+    #[test]
+    fn clearing_a_vec_drops_its_elements() {
+        struct CountDrops<'a>(&'a Cell<u32>);
+
+        impl<'a> Drop for CountDrops<'a> {
+            fn drop(&mut self) {
+                self.0.set(self.0.get() + 1);
+            }
+        }
+
+        let mut buffer = Buffer([0; 16 * 1024]);
+        let mut arena = arena_over(&mut buffer);
+        let drops = Cell::new(0);
+        let mut vec = ArenaVec::new();
+        for _ in 0..5 {
+            vec.push(&mut arena, CountDrops(&drops));
+        }
+        drop(vec.remove(1));
+        assert_eq!(drops.get(), 1);
+        vec.clear();
+        assert_eq!(drops.get(), 5);
+    }
+}
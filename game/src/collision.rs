@@ -0,0 +1,72 @@
+//! Collision shapes of entities and tiles.
+
+use base::math::V2;
+
+/// Most boxes a `CollisionVolumeGroup` can be made of.
+pub const MAX_COLLISION_VOLUMES: usize = 4;
+
+/// An axis-aligned box, `offset` is its center relative to the position of its owner.
+#[derive(Clone, Copy)]
+pub struct CollisionVolume {
+    pub offset: V2,
+    pub dim: V2,
+}
+
+impl CollisionVolume {
+    pub fn new(offset: V2, dim: V2) -> CollisionVolume {
+        CollisionVolume { offset, dim }
+    }
+
+    pub fn min_corner(&self) -> V2 {
+        self.offset - 0.5 * self.dim
+    }
+
+    pub fn max_corner(&self) -> V2 {
+        self.offset + 0.5 * self.dim
+    }
+}
+
+/// The boxes a shape is made of, together with a box around all of them that is tested first.
+#[derive(Clone, Copy)]
+pub struct CollisionVolumeGroup {
+    pub total: CollisionVolume,
+    volumes: [CollisionVolume; MAX_COLLISION_VOLUMES],
+    volume_count: usize,
+}
+
+impl CollisionVolumeGroup {
+    /// A single box of `dim` centered on the owner.
+    pub fn single(dim: V2) -> CollisionVolumeGroup {
+        CollisionVolumeGroup::new(&[CollisionVolume::new(V2::zero(), dim)])
+    }
+
+    pub fn new(volumes: &[CollisionVolume]) -> CollisionVolumeGroup {
+        assert!(
+            !volumes.is_empty() && volumes.len() <= MAX_COLLISION_VOLUMES,
+            "a collision volume group needs between 1 and {} volumes, got {}",
+            MAX_COLLISION_VOLUMES,
+            volumes.len()
+        );
+
+        let mut min_corner = volumes[0].min_corner();
+        let mut max_corner = volumes[0].max_corner();
+        for volume in &volumes[1..] {
+            min_corner.x = min_corner.x.min(volume.min_corner().x);
+            min_corner.y = min_corner.y.min(volume.min_corner().y);
+            max_corner.x = max_corner.x.max(volume.max_corner().x);
+            max_corner.y = max_corner.y.max(volume.max_corner().y);
+        }
+
+        let mut group = CollisionVolumeGroup {
+            total: CollisionVolume::new(0.5 * (min_corner + max_corner), max_corner - min_corner),
+            volumes: [volumes[0]; MAX_COLLISION_VOLUMES],
+            volume_count: volumes.len(),
+        };
+        group.volumes[..volumes.len()].copy_from_slice(volumes);
+        group
+    }
+
+    pub fn volumes(&self) -> &[CollisionVolume] {
+        &self.volumes[..self.volume_count]
+    }
+}
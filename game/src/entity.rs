@@ -0,0 +1,376 @@
+//! Entities and the collection that owns them.
+
+use base::math::V2;
+
+use collections::{ArenaHashMap, ArenaVec};
+use collision::{CollisionVolume, CollisionVolumeGroup};
+use memory::{AllocError, ArenaObject, MemoryArena};
+use pool::ArenaPool;
+use tile_map::TileMapPosition;
+
+/// Entities are indexed by the block of `1 << ENTITY_CHUNK_SHIFT` by `1 << ENTITY_CHUNK_SHIFT`
+/// tiles they stand in, so nearby entities can be found without looking at all of them.
+pub const ENTITY_CHUNK_SHIFT: u32 = 4;
+
+/// Distance a sword flies before it disappears, in meters.
+pub const SWORD_RANGE: f32 = 5.0;
+pub const MONSTER_HIT_POINTS: u32 = 3;
+
+/// What an entity is, together with the state only that kind of entity needs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EntityKind {
+    Wall,
+    Hero {
+        /// The sword the hero threw last, a hero has only one sword in flight at a time.
+        sword: Option<EntityHandle>,
+    },
+    Familiar {
+        /// Phase of the hovering animation, in radians.
+        bob_t: f32,
+    },
+    Monster {
+        hit_points: u32,
+    },
+    Sword {
+        distance_remaining: f32,
+    },
+}
+
+#[derive(Clone)]
+pub struct Entity {
+    pub kind: EntityKind,
+    /// Has to be changed through `EntityCollection::set_entity_position` once the entity is in a
+    /// collection, so the chunk index follows it.
+    pub p: TileMapPosition,
+    pub dp: V2,
+    /// Radians counterclockwise from the positive x axis.
+    pub facing_direction: f32,
+    pub collision: CollisionVolumeGroup,
+    /// Whether moving entities run into this one, and this one into others while it moves.
+    pub collidable: bool,
+}
+
+impl Entity {
+    fn new(kind: EntityKind, p: TileMapPosition, collision: CollisionVolumeGroup) -> Entity {
+        Entity {
+            kind,
+            p,
+            dp: V2::zero(),
+            facing_direction: 0.0,
+            collision,
+            collidable: true,
+        }
+    }
+
+    pub fn hero(p: TileMapPosition) -> Entity {
+        let collision = CollisionVolumeGroup::single(V2::new(1.0, 0.5));
+        Entity::new(EntityKind::Hero { sword: None }, p, collision)
+    }
+
+    pub fn familiar(p: TileMapPosition) -> Entity {
+        let collision = CollisionVolumeGroup::single(V2::new(1.0, 0.5));
+        Entity::new(EntityKind::Familiar { bob_t: 0.0 }, p, collision)
+    }
+
+    pub fn monster(p: TileMapPosition) -> Entity {
+        let kind = EntityKind::Monster {
+            hit_points: MONSTER_HIT_POINTS,
+        };
+        // NOTE: A wide body with a narrower head on top of it.
+        let collision = CollisionVolumeGroup::new(&[
+            CollisionVolume::new(V2::zero(), V2::new(1.0, 0.5)),
+            CollisionVolume::new(V2::new(0.0, 0.5), V2::new(0.5, 0.5)),
+        ]);
+        Entity::new(kind, p, collision)
+    }
+
+    pub fn sword(p: TileMapPosition, dp: V2) -> Entity {
+        let kind = EntityKind::Sword {
+            distance_remaining: SWORD_RANGE,
+        };
+        let collision = CollisionVolumeGroup::single(V2::new(0.5, 0.5));
+        let mut sword = Entity::new(kind, p, collision);
+        sword.dp = dp;
+        sword.facing_direction = dp.y.atan2(dp.x);
+        sword
+    }
+}
+
+/// Refers to an entity in an `EntityCollection`. The generation makes handles to removed
+/// entities stale instead of letting them alias whatever reuses the slot.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct EntityHandle {
+    index: u32,
+    generation: u32,
+}
+
+struct EntitySlot {
+    generation: u32,
+    entity: Option<ArenaObject<Entity>>,
+    next_free: Option<u32>,
+}
+
+/// Chunk coordinates of the entity index.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct EntityChunkKey {
+    pub chunk_x: u32,
+    pub chunk_y: u32,
+    pub chunk_z: u32,
+}
+
+impl EntityChunkKey {
+    pub fn containing(p: &TileMapPosition) -> EntityChunkKey {
+        EntityChunkKey {
+            chunk_x: p.abs_tile_x >> ENTITY_CHUNK_SHIFT,
+            chunk_y: p.abs_tile_y >> ENTITY_CHUNK_SHIFT,
+            chunk_z: p.abs_tile_z,
+        }
+    }
+}
+
+pub struct EntityCollection {
+    slots: ArenaVec<EntitySlot>,
+    first_free_slot: Option<u32>,
+    pool: ArenaPool<Entity>,
+    /// Only chunks with entities in them are indexed.
+    chunks: ArenaHashMap<EntityChunkKey, ArenaVec<EntityHandle>>,
+    /// Handle lists of chunks that were emptied, for the next chunk an entity moves into.
+    spare_chunk_lists: ArenaVec<ArenaVec<EntityHandle>>,
+}
+
+impl EntityCollection {
+    pub fn new() -> EntityCollection {
+        EntityCollection {
+            slots: ArenaVec::new(),
+            first_free_slot: None,
+            pool: ArenaPool::new(),
+            chunks: ArenaHashMap::new(),
+            spare_chunk_lists: ArenaVec::new(),
+        }
+    }
+
+    /// Adds `entity`, reusing the slot of a removed one if there is any. Fails when the arena has
+    /// no room left for it.
+    pub fn add_entity(
+        &mut self,
+        arena: &mut MemoryArena,
+        entity: Entity,
+    ) -> Result<EntityHandle, AllocError> {
+        let chunk_key = EntityChunkKey::containing(&entity.p);
+        let entity = self.pool.try_alloc(arena, entity)?;
+
+        let index = match self.first_free_slot {
+            Some(index) => index,
+            None => {
+                let slot = EntitySlot {
+                    generation: 0,
+                    entity: None,
+                    next_free: None,
+                };
+                if let Err(err) = self.slots.try_push(arena, slot) {
+                    unsafe { self.pool.free(entity) };
+                    return Err(err);
+                }
+                let index = (self.slots.len() - 1) as u32;
+                self.slots[index as usize].next_free = self.first_free_slot;
+                self.first_free_slot = Some(index);
+                index
+            }
+        };
+        let handle = EntityHandle {
+            index,
+            generation: self.slots[index as usize].generation,
+        };
+
+        if let Err(err) = self.try_add_to_chunk(arena, chunk_key, handle) {
+            unsafe { self.pool.free(entity) };
+            return Err(err);
+        }
+
+        let slot = &mut self.slots[index as usize];
+        self.first_free_slot = slot.next_free.take();
+        slot.entity = Some(entity);
+        Ok(handle)
+    }
+
+    /// Returns false if the handle is stale.
+    pub fn remove_entity(&mut self, arena: &mut MemoryArena, handle: EntityHandle) -> bool {
+        let slot = match self.slots.get_mut(handle.index as usize) {
+            Some(slot) if slot.generation == handle.generation && slot.entity.is_some() => slot,
+            _ => return false,
+        };
+
+        let entity = slot.entity.take().unwrap();
+        let chunk_key = EntityChunkKey::containing(&entity.p);
+        unsafe { self.pool.free(entity) };
+        slot.generation = slot.generation.wrapping_add(1);
+        slot.next_free = self.first_free_slot;
+        self.first_free_slot = Some(handle.index);
+
+        self.remove_from_chunk(arena, chunk_key, handle);
+        true
+    }
+
+    /// Moves the entity to `p`, keeping the chunk index up to date. Returns false if the handle
+    /// is stale.
+    pub fn set_entity_position(
+        &mut self,
+        arena: &mut MemoryArena,
+        handle: EntityHandle,
+        p: TileMapPosition,
+    ) -> bool {
+        let entity = match self.get_entity_mut(handle) {
+            Some(entity) => entity,
+            None => return false,
+        };
+        let old_chunk_key = EntityChunkKey::containing(&entity.p);
+        entity.p = p;
+
+        let new_chunk_key = EntityChunkKey::containing(&p);
+        if new_chunk_key != old_chunk_key {
+            self.remove_from_chunk(arena, old_chunk_key, handle);
+            if let Err(err) = self.try_add_to_chunk(arena, new_chunk_key, handle) {
+                arena.out_of_memory(err);
+            }
+        }
+        true
+    }
+
+    /// Handles of the entities standing in the chunk.
+    pub fn chunk_entities(&self, chunk_key: EntityChunkKey) -> &[EntityHandle] {
+        match self.chunks.get(&chunk_key) {
+            Some(handles) => handles,
+            None => &[],
+        }
+    }
+
+    /// Every live entity together with its handle.
+    pub fn iter(&self) -> impl Iterator<Item = (EntityHandle, &Entity)> {
+        self.slots.iter().enumerate().filter_map(|(index, slot)| {
+            let entity = slot.entity.as_ref()?;
+            let handle = EntityHandle {
+                index: index as u32,
+                generation: slot.generation,
+            };
+            Some((handle, entity.as_ref()))
+        })
+    }
+
+    fn try_add_to_chunk(
+        &mut self,
+        arena: &mut MemoryArena,
+        chunk_key: EntityChunkKey,
+        handle: EntityHandle,
+    ) -> Result<(), AllocError> {
+        if !self.chunks.contains_key(&chunk_key) {
+            let handles = self.spare_chunk_lists.pop().unwrap_or_else(ArenaVec::new);
+            self.chunks.try_insert(arena, chunk_key, handles)?;
+        }
+        let handles = self.chunks.get_mut(&chunk_key).unwrap();
+        handles.try_push(arena, handle)
+    }
+
+    fn remove_from_chunk(
+        &mut self,
+        arena: &mut MemoryArena,
+        chunk_key: EntityChunkKey,
+        handle: EntityHandle,
+    ) {
+        let handles = self.chunks.get_mut(&chunk_key).unwrap();
+        let index = handles.iter().position(|&other| other == handle).unwrap();
+        handles.swap_remove(index);
+
+        // NOTE: Without room to keep the list around the empty chunk just stays in the index.
+        if handles.is_empty() && self.spare_chunk_lists.try_reserve(arena, 1).is_ok() {
+            let handles = self.chunks.remove(&chunk_key).unwrap();
+            self.spare_chunk_lists.push(arena, handles);
+        }
+    }
+
+    pub fn get_entity(&self, handle: EntityHandle) -> Option<&Entity> {
+        match self.slots.get(handle.index as usize) {
+            Some(slot) if slot.generation == handle.generation => {
+                slot.entity.as_ref().map(|entity| entity.as_ref())
+            }
+            _ => None,
+        }
+    }
+
+    pub fn get_entity_mut(&mut self, handle: EntityHandle) -> Option<&mut Entity> {
+        match self.slots.get_mut(handle.index as usize) {
+            Some(slot) if slot.generation == handle.generation => {
+                slot.entity.as_mut().map(|entity| entity.as_mut())
+            }
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn arena_over(buffer: &mut [u64]) -> MemoryArena {
+        MemoryArena::from_raw_parts("test", buffer.as_mut_ptr() as *mut u8, 8 * buffer.len())
+    }
+
+    #[test]
+    fn handles_to_removed_entities_go_stale() {
+        let mut buffer = [0u64; 1024];
+        let mut arena = arena_over(&mut buffer);
+        let mut entities = EntityCollection::new();
+        let p = TileMapPosition::centered(3, 4, 0);
+        let stale = entities.add_entity(&mut arena, Entity::hero(p)).unwrap();
+        assert!(entities.remove_entity(&mut arena, stale));
+
+        // NOTE: The new entity takes over the slot of the removed one, with the next generation.
+        let monster = entities.add_entity(&mut arena, Entity::monster(p)).unwrap();
+        assert_eq!(monster.index, stale.index);
+        assert_ne!(monster.generation, stale.generation);
+
+        assert!(entities.get_entity(stale).is_none());
+        assert!(entities.get_entity_mut(stale).is_none());
+        assert!(!entities.set_entity_position(&mut arena, stale, p));
+        assert!(!entities.remove_entity(&mut arena, stale));
+        assert_eq!(
+            entities.get_entity(monster).unwrap().kind,
+            EntityKind::Monster {
+                hit_points: MONSTER_HIT_POINTS
+            }
+        );
+        assert_eq!(
+            entities.chunk_entities(EntityChunkKey::containing(&p)),
+            &[monster]
+        );
+    }
+
+    #[test]
+    fn emptied_chunks_leave_the_index() {
+        let mut buffer = [0u64; 1024];
+        let mut arena = arena_over(&mut buffer);
+        let mut entities = EntityCollection::new();
+        let p = TileMapPosition::centered(3, 4, 0);
+        let far_p = TileMapPosition::centered(100, 4, 0);
+        let hero = entities.add_entity(&mut arena, Entity::hero(p)).unwrap();
+        entities.set_entity_position(&mut arena, hero, far_p);
+
+        assert!(entities
+            .chunk_entities(EntityChunkKey::containing(&p))
+            .is_empty());
+        assert_eq!(
+            entities.chunk_entities(EntityChunkKey::containing(&far_p)),
+            &[hero]
+        );
+        assert_eq!(entities.chunks.iter().count(), 1);
+
+        // NOTE: Moving back reuses the handle list the first chunk left behind.
+        let used = arena.remaining();
+        entities.set_entity_position(&mut arena, hero, p);
+        assert_eq!(arena.remaining(), used);
+        assert_eq!(
+            entities.chunk_entities(EntityChunkKey::containing(&p)),
+            &[hero]
+        );
+        assert_eq!(entities.chunks.iter().count(), 1);
+    }
+}
@@ -1,352 +1,239 @@
-use core::ops::{Deref, DerefMut, RangeInclusive};
-use core::ptr::null_mut;
+use core::f32::consts::TAU;
 
 use base::math::V2;
 
+use handmade_asset::{AssetFileError, AssetTagId, AssetType};
 use software_renderer::*;
 
-use debug_platform_read_entire_file;
-use random::RANDOM_NUMBER_TABLE;
+use asset::{AssetId, Assets, LoadAssetsError, TagMatch, ASSET_SLAB_ALIGNMENT, ASSET_SLAB_SIZE};
+use collections::ArenaVec;
+use collision::CollisionVolumeGroup;
+use entity::*;
+use memory::*;
+use random::RandomSeries;
+use sim_region::{SimEntity, SimRegion};
 use tile_map::*;
+use world_file::{read_world, write_world, LoadedWorld, SavedWorld, WorldFileError};
+use world_gen::{generate_world, WorldGenParams};
+use GameButtonState;
 use GameInput;
 use GameOffscreenBuffer;
 use GameSoundBuffer;
+use {
+    debug_platform_free_file_memory, debug_platform_read_entire_file,
+    debug_platform_write_entire_file,
+};
 
 struct World {
     tile_map: ArenaObject<TileMap>,
 }
 
-pub struct MemoryArena {
-    base: *mut u8,
-    size: usize,
-    used: usize,
-}
-
-pub struct ArenaArray<T> {
-    ptr: *mut T,
-    len: usize,
-}
-
-impl<T> ArenaArray<T> {
-    pub fn empty() -> ArenaArray<T> {
-        ArenaArray {
-            ptr: null_mut(),
-            len: 0,
-        }
-    }
-
-    pub fn from_raw_parts(ptr: *mut T, len: usize) -> ArenaArray<T> {
-        ArenaArray { ptr, len }
-    }
-
-    pub fn len(&self) -> usize {
-        self.len
-    }
-
-    pub fn iter(&self) -> ArenaArrayIter<T> {
-        ArenaArrayIter {
-            array: self,
-            index: 0,
-        }
-    }
-
-    pub fn iter_mut(&mut self) -> ArenaArrayIterMut<T> {
-        ArenaArrayIterMut {
-            array: self,
-            index: 0,
-        }
-    }
-
-    pub fn get(&self, index: usize) -> Option<&T> {
-        if index >= self.len {
-            return None;
-        }
-        Some(unsafe { self.get_unchecked(index) })
-    }
-
-    pub fn get_mut(&mut self, index: usize) -> Option<&mut T> {
-        if index >= self.len {
-            return None;
-        }
-        Some(unsafe { self.get_unchecked_mut(index) })
-    }
-
-    pub unsafe fn get_unchecked(&self, index: usize) -> &T {
-        &*self.ptr.offset(index as isize)
-    }
-
-    pub unsafe fn get_unchecked_mut(&mut self, index: usize) -> &mut T {
-        &mut *self.ptr.offset(index as isize)
-    }
-}
-
-pub struct ArenaArrayIter<'a, T> {
-    array: &'a ArenaArray<T>,
-    index: usize,
-}
-
-impl<'a, T> Iterator for ArenaArrayIter<'a, T> {
-    type Item = &'a T;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.index >= self.array.len() {
-            return None;
-        }
-
-        let item = unsafe { self.array.get_unchecked(self.index) };
-        self.index += 1;
-        Some(item)
-    }
-}
-
-pub struct ArenaArrayIterMut<'a, T> {
-    array: &'a mut ArenaArray<T>,
-    index: usize,
-}
-
-impl<'a, T> Iterator for ArenaArrayIterMut<'a, T> {
-    type Item = &'a mut T;
-
-    fn next(&mut self) -> Option<Self::Item> {
-        if self.index >= self.array.len() {
-            return None;
-        }
-        let item = unsafe { &mut *self.array.ptr.offset(self.index as isize) };
-        self.index += 1;
-        Some(item)
-    }
-}
-
-pub struct ArenaObject<T: ?Sized> {
-    ptr: *mut T,
-}
-
-impl<T> ArenaObject<T> {
-    pub fn from_raw(ptr: *mut T) -> ArenaObject<T> {
-        ArenaObject { ptr }
-    }
-}
+pub struct GameState {
+    world_arena: MemoryArena,
+    world: ArenaObject<World>,
 
-impl<T> AsRef<T> for ArenaObject<T> {
-    fn as_ref(&self) -> &T {
-        unsafe { &*self.ptr }
-    }
-}
+    camera_following_entity: Option<EntityHandle>,
+    camera_p: TileMapPosition,
+    /// Where heroes appear when a player joins.
+    hero_start_p: TileMapPosition,
 
-impl<T> AsMut<T> for ArenaObject<T> {
-    fn as_mut(&mut self) -> &mut T {
-        unsafe { &mut *self.ptr }
-    }
-}
+    player_for_controller: [Option<EntityHandle>; 5],
 
-impl<T> Deref for ArenaObject<T> {
-    type Target = T;
+    entities: EntityCollection,
 
-    fn deref(&self) -> &Self::Target {
-        unsafe { &*self.ptr }
-    }
+    /// Randomness of the game itself, world generation has a series of its own.
+    random_series: RandomSeries,
+
+    assets: Assets,
+    /// Why the asset file was rejected. The game then runs in flat colors and marks the top left
+    /// corner of the screen.
+    asset_file_error: Option<AssetFileError>,
+    /// A new sound cuts off the one that is still playing.
+    playing_sound: Option<PlayingSound>,
+    /// Transient storage that is not taken by the asset slab, for scratch memory that lives at
+    /// most one frame.
+    transient_arena: MemoryArena,
 }
 
-impl<T> DerefMut for ArenaObject<T> {
-    fn deref_mut(&mut self) -> &mut Self::Target {
-        unsafe { &mut *self.ptr }
-    }
+struct PlayingSound {
+    id: AssetId,
+    /// Samples per channel that were output already.
+    samples_played: usize,
 }
 
-impl MemoryArena {
-    pub fn from_raw_parts(base: *mut u8, size: usize) -> MemoryArena {
-        MemoryArena {
-            base,
-            size,
-            used: 0,
-        }
-    }
-
-    pub fn alloc<T>(&mut self, val: T) -> ArenaObject<T> {
-        unsafe {
-            let mut result = self.alloc_uninit::<T>();
-            *result = val;
-            result
-        }
-    }
-
-    pub unsafe fn alloc_uninit<T>(&mut self) -> ArenaObject<T> {
-        let size = core::mem::size_of::<T>();
-        let memory = self.alloc_size(size);
-        ArenaObject::from_raw(memory as *mut T)
-    }
-
-    pub fn alloc_array<T: Clone>(&mut self, val: T, len: usize) -> ArenaArray<T> {
-        let mut array = unsafe { self.alloc_array_uninit::<T>(len) };
-        for e in array.iter_mut() {
-            *e = val.clone();
-        }
-        array
-    }
-
-    pub unsafe fn alloc_array_uninit<T>(&mut self, len: usize) -> ArenaArray<T> {
-        let size = core::mem::size_of::<T>() * len;
-        let memory = self.alloc_size(size);
-        ArenaArray::from_raw_parts(memory as *mut T, len)
-    }
-
-    unsafe fn alloc_size(&mut self, size: usize) -> *mut u8 {
-        assert!(self.used + size <= self.size);
-
-        let memory = self.base.offset(self.used as isize);
-        self.used += size;
-        memory
-    }
-
-    pub fn reserve(&mut self, size: usize) -> MemoryArena {
-        assert!(self.used + size <= self.size);
-        let base = unsafe { self.base.offset(self.used as isize) };
-        self.used += size;
-        MemoryArena::from_raw_parts(base, size)
-    }
-
-    pub fn remaining(&self) -> usize {
-        self.size - self.used
-    }
+/// How an entity accelerates, `speed` scales the unit acceleration and `drag` slows it down
+/// proportional to its velocity.
+pub struct MoveSpec {
+    pub speed: f32,
+    pub drag: f32,
 }
 
-#[derive(Default, Clone)]
-pub struct Entity {
-    p: TileMapPosition,
-    dp: V2,
-    facing_direction: usize,
-    width: f32,
-    height: f32,
+const HERO_MOVE_SPEC: MoveSpec = MoveSpec {
+    speed: 50.0,
+    drag: 8.0,
+};
+const FAMILIAR_MOVE_SPEC: MoveSpec = MoveSpec {
+    speed: 25.0,
+    drag: 8.0,
+};
+const SWORD_MOVE_SPEC: MoveSpec = MoveSpec {
+    speed: 0.0,
+    drag: 0.0,
+};
+
+/// What a moving entity does after running into another one.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Collision {
+    /// Stops at the other entity like at a wall.
+    Block,
+    /// Carries on as if the other entity was not there.
+    PassThrough,
 }
 
-pub struct EntityCollection {
-    entity_count: usize,
-    entities: [Option<Entity>; 256],
-}
+/// Meters per second a thrown sword flies at.
+const SWORD_SPEED: f32 = 5.0;
+/// The familiar stays put unless a hero is within this many meters.
+const FAMILIAR_SIGHT: f32 = 10.0;
+/// Closer than this the familiar stops approaching a hero.
+const FAMILIAR_PERSONAL_SPACE: f32 = 3.0;
+/// How hard the familiar drifts about while no hero is in sight.
+const FAMILIAR_WANDER: f32 = 0.3;
 
-impl EntityCollection {
-    pub fn new() -> EntityCollection {
-        EntityCollection {
-            entity_count: 0,
-            entities: unsafe {
-                let mut entities: [Option<Entity>; 256] =
-                    core::mem::MaybeUninit::uninit().assume_init();
-                for entity in entities.iter_mut() {
-                    *entity = None
-                }
-                entities
-            },
+fn test_wall(wall_x: f32, rel: V2, delta: V2, min_y: f32, max_y: f32, t_min: &mut f32) -> bool {
+    let mut hit = false;
+    let epsilon = 0.0001;
+    if delta.x != 0.0 {
+        let t = (wall_x - rel.x) / delta.x;
+        let y = rel.y + t * delta.y;
+        if t >= 0.0 && t < *t_min && y >= min_y && y <= max_y {
+            *t_min = (t - epsilon).max(0.0);
+            hit = true;
         }
     }
-
-    pub fn add_entity(&mut self) -> usize {
-        self.entities[self.entity_count] = Some(Entity::default());
-        self.entity_count += 1;
-        self.entity_count - 1
-    }
-
-    pub fn get_entity(&self, index: usize) -> Option<&Entity> {
-        self.entities.get(index).and_then(|entry| entry.into())
-    }
-
-    pub fn get_entity_mut(&mut self, index: usize) -> Option<&mut Entity> {
-        self.entities.get_mut(index).and_then(|entry| entry.into())
-    }
-
-    pub fn iter(&self) -> impl Iterator<Item = &Entity> {
-        self.entities
-            .iter()
-            .take(self.entity_count)
-            .filter_map(|entry| entry.into())
-    }
+    hit
 }
 
-pub struct GameState {
-    world_arena: MemoryArena,
-    world: ArenaObject<World>,
-
-    camera_following_entity_index: Option<usize>,
-    camera_p: TileMapPosition,
-
-    player_index_for_controller: [Option<usize>; 5],
-
-    entities: EntityCollection,
-
-    backdrop: LoadedBitmap,
-    hero_bitmaps: [HeroBitmaps; 4],
-}
-
-pub fn initialize_player(
-    entity: &mut Entity,
-    entity_index: usize,
-    camera_following_entity_index: &mut Option<usize>,
-) {
-    *entity = Entity::default();
-    entity.p = TileMapPosition {
-        abs_tile_x: 1,
-        abs_tile_y: 3,
-        abs_tile_z: 0,
-        offset: V2::new(0.0, 0.0),
-    };
-    entity.dp = V2::zero();
-    entity.height = 0.5;
-    entity.width = 1.0;
-
-    if camera_following_entity_index.is_none() {
-        *camera_following_entity_index = Some(entity_index);
+/// Sweeps a point at `rel` along `delta` against a box around the origin reaching from
+/// `min_corner` to `max_corner`. Returns the normal of the side it runs into, if it gets there
+/// before `t_min`.
+fn test_box(rel: V2, delta: V2, min_corner: V2, max_corner: V2, t_min: &mut f32) -> Option<V2> {
+    let mut wall_normal = None;
+    // NOTE: The horizontal walls are tested with the axes swapped.
+    let swapped_rel = V2::new(rel.y, rel.x);
+    let swapped_delta = V2::new(delta.y, delta.x);
+    if test_wall(min_corner.x, rel, delta, min_corner.y, max_corner.y, t_min) {
+        wall_normal = Some(V2::new(1.0, 0.0));
+    }
+    if test_wall(max_corner.x, rel, delta, min_corner.y, max_corner.y, t_min) {
+        wall_normal = Some(V2::new(-1.0, 0.0));
+    }
+    if test_wall(
+        min_corner.y,
+        swapped_rel,
+        swapped_delta,
+        min_corner.x,
+        max_corner.x,
+        t_min,
+    ) {
+        wall_normal = Some(V2::new(0.0, 1.0));
+    }
+    if test_wall(
+        max_corner.y,
+        swapped_rel,
+        swapped_delta,
+        min_corner.x,
+        max_corner.x,
+        t_min,
+    ) {
+        wall_normal = Some(V2::new(0.0, -1.0));
     }
+    wall_normal
 }
 
-fn test_wall(
-    wall_x: f32,
-    rel_x: f32,
-    rel_y: f32,
-    delta_x: f32,
-    delta_y: f32,
+/// Sweeps `mover` along `delta` against `other`, `rel` is the position of the mover relative to
+/// the other. The bounding volumes are tested first, the individual volumes only if the sweep
+/// gets near them.
+fn test_volume_groups(
+    rel: V2,
+    delta: V2,
+    mover: &CollisionVolumeGroup,
+    other: &CollisionVolumeGroup,
     t_min: &mut f32,
-    min_y: f32,
-    max_y: f32,
-) -> bool {
-    let mut hit = false;
-    let epsilon = 0.0001;
-    if delta_x != 0.0 {
-        let t = (wall_x - rel_x) / delta_x;
-        let y = rel_y + t * delta_y;
-        if t >= 0.0 && t < *t_min {
-            if y >= min_y && y <= max_y {
-                *t_min = (t - epsilon).max(0.0);
-                hit = true;
+) -> Option<V2> {
+    let total_half_dim = 0.5 * (mover.total.dim + other.total.dim);
+    let total_rel = rel + mover.total.offset - other.total.offset;
+    let total_end = total_rel + delta;
+    if total_rel.x.max(total_end.x) < -total_half_dim.x
+        || total_rel.x.min(total_end.x) > total_half_dim.x
+        || total_rel.y.max(total_end.y) < -total_half_dim.y
+        || total_rel.y.min(total_end.y) > total_half_dim.y
+    {
+        return None;
+    }
+
+    let mut wall_normal = None;
+    for mover_volume in mover.volumes() {
+        for other_volume in other.volumes() {
+            let half_dim = 0.5 * (mover_volume.dim + other_volume.dim);
+            let volume_rel = rel + mover_volume.offset - other_volume.offset;
+            if let Some(normal) = test_box(volume_rel, delta, -half_dim, half_dim, t_min) {
+                wall_normal = Some(normal);
             }
         }
     }
-    return hit;
+    wall_normal
 }
 
-fn move_player(tile_map: &TileMap, entity: &mut Entity, dt: f32, mut ddp: V2) {
+/// Moves the region entity at `index`, stopping it at walls and at the other collidable entities
+/// on its level. `handle_collision` is called with the mover and the entity it ran into, and
+/// decides whether the hit stops the mover. Returns the type of the tile the entity stepped onto,
+/// if it left the tile it was on.
+fn move_entity<F>(
+    tile_map: &TileMap,
+    region: &mut SimRegion,
+    index: usize,
+    dt: f32,
+    spec: &MoveSpec,
+    mut ddp: V2,
+    mut handle_collision: F,
+) -> Option<TileType>
+where
+    F: FnMut(&mut SimEntity, &mut SimEntity) -> Collision,
+{
+    let region_origin = region.origin;
+    let (others_before, rest) = region.entities_mut().split_at_mut(index);
+    let (entity, others_after) = rest.split_first_mut().unwrap();
+
     let ddp_len_sq = ddp.len_sq();
     if ddp_len_sq > 1.0 {
         ddp *= 1.0 / ddp_len_sq.sqrt();
     }
 
-    let speed = 50.0;
-
-    ddp *= speed;
+    ddp *= spec.speed;
 
     // TODO: ODE
-    ddp += -8.0 * entity.dp;
+    ddp += -spec.drag * entity.dp;
 
-    let old_player_p = entity.p;
+    // NOTE: The region origin on the level of the entity, tiles are looked up relative to it.
+    let origin = TileMapPosition {
+        abs_tile_z: entity.abs_tile_z,
+        ..region_origin
+    };
+    let old_player_p = tile_map.offset(origin, entity.p);
     let mut player_delta = 0.5 * ddp * dt.powi(2) + entity.dp * dt;
     entity.dp += ddp * dt;
-    let new_player_p = tile_map.offset(entity.p, player_delta);
+    let new_player_p = tile_map.offset(origin, entity.p + player_delta);
 
-    let mut min_tile_x = entity.p.abs_tile_x.min(new_player_p.abs_tile_x);
-    let mut min_tile_y = entity.p.abs_tile_y.min(new_player_p.abs_tile_y);
-    let mut max_tile_x = entity.p.abs_tile_x.max(new_player_p.abs_tile_x);
-    let mut max_tile_y = entity.p.abs_tile_y.max(new_player_p.abs_tile_y);
+    let mut min_tile_x = old_player_p.abs_tile_x.min(new_player_p.abs_tile_x);
+    let mut min_tile_y = old_player_p.abs_tile_y.min(new_player_p.abs_tile_y);
+    let mut max_tile_x = old_player_p.abs_tile_x.max(new_player_p.abs_tile_x);
+    let mut max_tile_y = old_player_p.abs_tile_y.max(new_player_p.abs_tile_y);
 
-    let entity_tile_width = (entity.width / tile_map.tile_side_in_meters).ceil() as u32;
-    let entity_tile_height = (entity.height / tile_map.tile_side_in_meters).ceil() as u32;
+    let total = entity.collision.total;
+    let entity_tile_width =
+        ((total.offset.x.abs() + total.dim.x) / tile_map.tile_side_in_meters).ceil() as u32;
+    let entity_tile_height =
+        ((total.offset.y.abs() + total.dim.y) / tile_map.tile_side_in_meters).ceil() as u32;
 
     min_tile_x -= entity_tile_width;
     max_tile_x += entity_tile_width;
@@ -356,306 +243,225 @@ fn move_player(tile_map: &TileMap, entity: &mut Entity, dt: f32, mut ddp: V2) {
     assert!(max_tile_x - min_tile_x < 32);
     assert!(max_tile_y - min_tile_y < 32);
 
+    // NOTE: Entities the mover passed through are not tested again, it would run into them
+    // from the inside right away.
+    const MAX_ITERATIONS: usize = 4;
+    let mut passed_through = [None; MAX_ITERATIONS];
+
     let mut t_remaining = 1.0;
-    let abs_tile_z = entity.p.abs_tile_z;
-    for _ in 0..4 {
+    let abs_tile_z = entity.abs_tile_z;
+    for iteration in 0..MAX_ITERATIONS {
         if t_remaining <= 0.0 {
             break;
         }
         let mut t_min = 1.0;
         let mut wall_normal = V2::zero();
+        let mut hit_entity = None;
         for abs_tile_y in min_tile_y..=max_tile_y {
             for abs_tile_x in min_tile_x..=max_tile_x {
-                let test_tile_p =
-                    TileMapPosition::centered(abs_tile_x as u32, abs_tile_y as u32, abs_tile_z);
-                if !tile_map.is_point_empty(test_tile_p) {
-                    let diameter_w = tile_map.tile_side_in_meters + entity.width;
-                    let diameter_h = tile_map.tile_side_in_meters + entity.height;
-                    let min_corner = -0.5 * V2::new(diameter_w, diameter_h);
-                    let max_corner = 0.5 * V2::new(diameter_w, diameter_h);
-                    let rel = tile_map.subtract(entity.p, test_tile_p).dxy;
-
-                    if test_wall(
-                        min_corner.x,
-                        rel.x,
-                        rel.y,
-                        player_delta.x,
-                        player_delta.y,
-                        &mut t_min,
-                        min_corner.y,
-                        max_corner.y,
-                    ) {
-                        wall_normal = V2::new(1.0, 0.0);
-                    }
-                    if test_wall(
-                        max_corner.x,
-                        rel.x,
-                        rel.y,
-                        player_delta.x,
-                        player_delta.y,
+                let test_tile_p = TileMapPosition::centered(abs_tile_x, abs_tile_y, abs_tile_z);
+                if let Some(tile_collision) = tile_map.get_tile_collision(test_tile_p) {
+                    let rel = entity.p - tile_map.subtract(test_tile_p, origin).dxy;
+                    if let Some(normal) = test_volume_groups(
+                        rel,
+                        player_delta,
+                        &entity.collision,
+                        &tile_collision,
                         &mut t_min,
-                        min_corner.y,
-                        max_corner.y,
                     ) {
-                        wall_normal = V2::new(-1.0, 0.0);
-                    }
-                    if test_wall(
-                        min_corner.y,
-                        rel.y,
-                        rel.x,
-                        player_delta.y,
-                        player_delta.x,
-                        &mut t_min,
-                        min_corner.x,
-                        max_corner.x,
-                    ) {
-                        wall_normal = V2::new(0.0, 1.0);
-                    }
-                    if test_wall(
-                        max_corner.y,
-                        rel.y,
-                        rel.x,
-                        player_delta.y,
-                        player_delta.x,
-                        &mut t_min,
-                        min_corner.x,
-                        max_corner.x,
-                    ) {
-                        wall_normal = V2::new(0.0, -1.0);
+                        wall_normal = normal;
+                        hit_entity = None;
                     }
                 }
             }
         }
 
-        entity.p = tile_map.offset(entity.p, t_min * player_delta);
-        entity.dp = entity.dp - 1.0 * entity.dp * wall_normal * wall_normal;
-        player_delta = player_delta - 1.0 * player_delta * wall_normal * wall_normal;
+        if entity.collidable {
+            let others = others_before.iter().chain(others_after.iter());
+            for (other_index, other) in others.enumerate() {
+                if !other.collidable
+                    || other.removed
+                    || other.abs_tile_z != abs_tile_z
+                    || passed_through.contains(&Some(other_index))
+                {
+                    continue;
+                }
+                let rel = entity.p - other.p;
+                if let Some(normal) = test_volume_groups(
+                    rel,
+                    player_delta,
+                    &entity.collision,
+                    &other.collision,
+                    &mut t_min,
+                ) {
+                    wall_normal = normal;
+                    hit_entity = Some(other_index);
+                }
+            }
+        }
 
-        t_remaining -= t_min * t_remaining;
-    }
+        entity.p += t_min * player_delta;
 
-    if !entity.p.is_on_same_tile(&old_player_p) {
-        match tile_map.get_tile_value(
-            entity.p.abs_tile_x,
-            entity.p.abs_tile_y,
-            entity.p.abs_tile_z,
-        ) {
-            Some(3) => {
-                entity.p.abs_tile_z += 1;
-            }
-            Some(4) => {
-                entity.p.abs_tile_z -= 1;
+        let mut blocked = true;
+        if let Some(other_index) = hit_entity {
+            let other = if other_index < others_before.len() {
+                &mut others_before[other_index]
+            } else {
+                &mut others_after[other_index - others_before.len()]
+            };
+            if handle_collision(entity, other) == Collision::PassThrough {
+                passed_through[iteration] = Some(other_index);
+                player_delta = (1.0 - t_min) * player_delta;
+                blocked = false;
             }
-            _ => {}
         }
+        if blocked {
+            entity.dp = entity.dp - 1.0 * entity.dp * wall_normal * wall_normal;
+            player_delta = player_delta - 1.0 * player_delta * wall_normal * wall_normal;
+        }
+
+        t_remaining -= t_min * t_remaining;
     }
 
-    if entity.dp.y.abs() > entity.dp.x.abs() {
-        if entity.dp.y > 0.0 {
-            entity.facing_direction = 1;
-        } else {
-            entity.facing_direction = 3;
-        }
-    } else if entity.dp.x.abs() > entity.dp.y.abs() {
-        if entity.dp.x > 0.0 {
-            entity.facing_direction = 0;
-        } else {
-            entity.facing_direction = 2;
+    let tile_p = tile_map.offset(origin, entity.p);
+    let mut entered_tile = None;
+    if !tile_p.is_on_same_tile(&old_player_p) {
+        entered_tile =
+            tile_map.get_tile_type(tile_p.abs_tile_x, tile_p.abs_tile_y, tile_p.abs_tile_z);
+        if let Some(tile_type) = entered_tile {
+            let level_change = tile_type.properties().level_change;
+            entity.abs_tile_z = entity.abs_tile_z.wrapping_add(level_change as u32);
         }
     }
-}
 
-impl GameState {
-    pub unsafe fn new(permanent_storage: &mut MemoryArena) -> GameState {
-        let backdrop = debug_load_bmp("test/test_background.bmp\0".as_ptr() as *const i8).unwrap();
-        let hero_bitmaps = [
-            HeroBitmaps {
-                align_x: 72,
-                align_y: 183,
-                head: debug_load_bmp("test/test_hero_right_head.bmp\0".as_ptr() as *const i8)
-                    .unwrap(),
-                torso: debug_load_bmp("test/test_hero_right_torso.bmp\0".as_ptr() as *const i8)
-                    .unwrap(),
-                cape: debug_load_bmp("test/test_hero_right_cape.bmp\0".as_ptr() as *const i8)
-                    .unwrap(),
-            },
-            HeroBitmaps {
-                align_x: 72,
-                align_y: 183,
-                head: debug_load_bmp("test/test_hero_back_head.bmp\0".as_ptr() as *const i8)
-                    .unwrap(),
-                torso: debug_load_bmp("test/test_hero_back_torso.bmp\0".as_ptr() as *const i8)
-                    .unwrap(),
-                cape: debug_load_bmp("test/test_hero_back_cape.bmp\0".as_ptr() as *const i8)
-                    .unwrap(),
-            },
-            HeroBitmaps {
-                align_x: 72,
-                align_y: 183,
-                head: debug_load_bmp("test/test_hero_left_head.bmp\0".as_ptr() as *const i8)
-                    .unwrap(),
-                torso: debug_load_bmp("test/test_hero_left_torso.bmp\0".as_ptr() as *const i8)
-                    .unwrap(),
-                cape: debug_load_bmp("test/test_hero_left_cape.bmp\0".as_ptr() as *const i8)
-                    .unwrap(),
-            },
-            HeroBitmaps {
-                align_x: 72,
-                align_y: 183,
-                head: debug_load_bmp("test/test_hero_front_head.bmp\0".as_ptr() as *const i8)
-                    .unwrap(),
-                torso: debug_load_bmp("test/test_hero_front_torso.bmp\0".as_ptr() as *const i8)
-                    .unwrap(),
-                cape: debug_load_bmp("test/test_hero_front_cape.bmp\0".as_ptr() as *const i8)
-                    .unwrap(),
-            },
-        ];
-
-        let mut world_arena = permanent_storage.reserve(permanent_storage.remaining());
-        let mut tile_map = world_arena.alloc_uninit::<TileMap>();
-        tile_map.tile_side_in_meters = 1.4;
-        tile_map.chunk_shift = 4;
-        tile_map.chunk_mask = (1 << tile_map.chunk_shift) - 1;
-        tile_map.chunk_dim = 1 << tile_map.chunk_shift;
-        tile_map.tile_chunk_count_x = 128;
-        tile_map.tile_chunk_count_y = 128;
-        tile_map.tile_chunk_count_z = 2;
-        tile_map.tile_chunks = world_arena.alloc_array_uninit::<TileChunk>(
-            (tile_map.tile_chunk_count_x
-                * tile_map.tile_chunk_count_y
-                * tile_map.tile_chunk_count_z) as usize,
-        );
+    if entity.dp.x != 0.0 || entity.dp.y != 0.0 {
+        entity.facing_direction = entity.dp.y.atan2(entity.dp.x);
+    }
+    entered_tile
+}
 
-        for tile_chunk_z in 0..tile_map.tile_chunk_count_z {
-            for tile_chunk_y in 0..tile_map.tile_chunk_count_y {
-                for tile_chunk_x in 0..tile_map.tile_chunk_count_x {
-                    let chunk_dim = tile_map.chunk_dim;
-                    let tile_chunk = tile_map
-                        .get_tile_chunk_mut(tile_chunk_x, tile_chunk_y, tile_chunk_z)
-                        .unwrap();
-                    tile_chunk.chunk_dim = chunk_dim;
-                    tile_chunk.tiles = ArenaArray::empty();
-                }
-            }
+/// Gameplay rules for `mover` running into `other`.
+fn handle_collision(mover: &mut SimEntity, other: &mut SimEntity) -> Collision {
+    match (mover.kind, other.kind) {
+        (EntityKind::Sword { .. }, EntityKind::Monster { hit_points }) => {
+            let hit_points = hit_points.saturating_sub(1);
+            other.kind = EntityKind::Monster { hit_points };
+            other.removed = hit_points == 0;
+            mover.removed = true;
+            Collision::Block
         }
+        // NOTE: Swords start out inside the hero that throws them, and only ever hurt monsters.
+        (EntityKind::Sword { .. }, _) | (_, EntityKind::Sword { .. }) => Collision::PassThrough,
+        _ => Collision::Block,
+    }
+}
 
-        // for tile_chunk in tile_map.tile_chunks.iter() {
-        //     assert_eq!(
-        //         tile_chunk.tiles.len(),
-        //         (tile_map.chunk_dim * tile_map.chunk_dim) as usize
-        //     );
-        // }
-
-        let tiles_per_width = 17;
-        let tiles_per_height = 9;
-        let mut screen_x = 0;
-        let mut screen_y = 0;
-        let mut random_number_index = 0;
-        let mut door_left = false;
-        let mut door_right = false;
-        let mut door_top = false;
-        let mut door_bottom = false;
-        let mut door_up = false;
-        let mut door_down = false;
-        let mut abs_tile_z = 0;
-        for _ in 0..100 {
-            assert!(random_number_index < RANDOM_NUMBER_TABLE.len());
-            let random_choice = if door_up || door_down {
-                RANDOM_NUMBER_TABLE[random_number_index] % 2
-            } else {
-                RANDOM_NUMBER_TABLE[random_number_index] % 3
-            };
-            random_number_index += 1;
-
-            let mut created_z_door = false;
-            match random_choice {
-                2 => {
-                    created_z_door = true;
-                    if abs_tile_z == 0 {
-                        door_up = true;
-                    } else {
-                        door_down = true;
-                    }
-                }
-                1 => {
-                    door_right = true;
-                }
-                _ => {
-                    door_top = true;
-                }
-            }
-
-            for tile_y in 0..tiles_per_height {
-                for tile_x in 0..tiles_per_width {
-                    let abs_tile_x = screen_x * tiles_per_width + tile_x;
-                    let abs_tile_y = screen_y * tiles_per_height + tile_y;
-
-                    let mut tile_value = 1;
-                    if tile_x == 0 && (tile_y != tiles_per_height / 2 || !door_left) {
-                        tile_value = 2;
-                    }
-
-                    if tile_x == (tiles_per_width - 1)
-                        && (tile_y != tiles_per_height / 2 || !door_right)
-                    {
-                        tile_value = 2;
-                    }
-
-                    if tile_y == 0 && (tile_x != tiles_per_width / 2 || !door_bottom) {
-                        tile_value = 2;
-                    }
+#[derive(Debug)]
+pub enum SaveWorldError {
+    OutOfMemory(AllocError),
+    /// The platform could not write the file.
+    Write,
+}
 
-                    if tile_y == (tiles_per_height - 1)
-                        && (tile_x != tiles_per_width / 2 || !door_top)
-                    {
-                        tile_value = 2;
-                    }
+#[derive(Debug)]
+pub enum LoadWorldError {
+    /// The platform could not read the file.
+    Read,
+    /// The file is not a valid world file.
+    File,
+}
 
-                    if tile_x == 10 && tile_y == 6 {
-                        if door_up {
-                            tile_value = 3;
-                        } else if door_down {
-                            tile_value = 4;
-                        }
-                    }
+const WORLD_FILE_NAME: &str = "world.hhw\0";
+
+const WORLD_GEN_PARAMS: WorldGenParams = WorldGenParams {
+    seed: 0,
+    room_count: 100,
+    min_room_width: 5,
+    max_room_width: 15,
+    min_room_height: 3,
+    max_room_height: 7,
+    floor_count: 2,
+    branching_factor: 3,
+};
+
+/// NOTE: Far away from the world seed in the table, so the series of the game does not repeat
+/// the numbers the world was generated from.
+const GAME_RANDOM_SEED: u32 = 2048;
+
+/// NOTE: Debug builds keep statistics, so running out of memory reports where it went.
+fn track_debug_stats(arena: &mut MemoryArena) {
+    if cfg!(debug_assertions) {
+        let stats = arena.alloc(ArenaStats::new());
+        arena.track_stats(stats);
+    }
+}
 
-                    tile_map.set_tile_value(
-                        &mut world_arena,
-                        abs_tile_x,
-                        abs_tile_y,
-                        abs_tile_z,
-                        tile_value,
-                    );
-                }
-            }
+fn was_pressed(button: &GameButtonState) -> bool {
+    button.ended_down != 0 && button.half_transition_count > 0
+}
 
-            door_left = door_right;
-            door_bottom = door_top;
-            if created_z_door {
-                door_up = !door_up;
-                door_down = !door_down;
-            } else {
-                door_up = false;
-                door_down = false;
+impl GameState {
+    pub unsafe fn new(
+        permanent_storage: &mut MemoryArena,
+        transient_storage: &mut MemoryArena,
+    ) -> GameState {
+        let slab = transient_storage
+            .alloc_array_uninit_aligned::<u8>(ASSET_SLAB_SIZE, ASSET_SLAB_ALIGNMENT)
+            .into_slice_mut();
+        let mut asset_file_error = None;
+        let assets = match Assets::load(permanent_storage, slab, transient_storage) {
+            Ok(assets) => assets,
+            // NOTE: Without the asset file the game still runs, everything is drawn in flat
+            // colors.
+            Err(LoadAssetsError::Read) => Assets::empty(),
+            Err(LoadAssetsError::File(err)) => {
+                asset_file_error = Some(err);
+                Assets::empty()
             }
+            Err(LoadAssetsError::OutOfMemory(err)) => permanent_storage.out_of_memory(err),
+        };
+        let mut transient_arena = transient_storage.reserve("frame", transient_storage.remaining());
+        let mut world_arena = permanent_storage.reserve("world", permanent_storage.remaining());
+        track_debug_stats(&mut transient_arena);
+        track_debug_stats(&mut world_arena);
+
+        let mut tile_map = world_arena.alloc(TileMap::new(1.4, 4));
+
+        let mut entities = EntityCollection::new();
+        let mut random_series = RandomSeries::seed(GAME_RANDOM_SEED);
+
+        let generated = {
+            let mut scratch = transient_arena.begin_temporary_memory();
+            generate_world(
+                &WORLD_GEN_PARAMS,
+                &mut world_arena,
+                &mut scratch,
+                &mut tile_map,
+            )
+        };
 
-            door_right = false;
-            door_top = false;
-
-            match random_choice {
-                2 => {
-                    if abs_tile_z == 0 {
-                        abs_tile_z = 1;
-                    } else {
-                        abs_tile_z = 0;
-                    }
-                }
-                1 => {
-                    screen_x += 1;
-                }
-                _ => {
-                    screen_y += 1;
-                }
+        // NOTE: The familiar and the monster start in the corners of the start room, rooms are
+        // at least 3 by 3 tiles so they are never on top of stairs or the hero.
+        let start_room = generated.start_room;
+        let start_z = start_room.abs_tile_z;
+        let mut familiar = Entity::familiar(TileMapPosition::centered(
+            start_room.min_tile_x,
+            start_room.max_tile_y,
+            start_z,
+        ));
+        familiar.kind = EntityKind::Familiar {
+            bob_t: random_series.range_f32(0.0, TAU),
+        };
+        for entity in [
+            familiar,
+            Entity::monster(TileMapPosition::centered(
+                start_room.max_tile_x,
+                start_room.max_tile_y,
+                start_z,
+            )),
+        ] {
+            if let Err(err) = entities.add_entity(&mut world_arena, entity) {
+                world_arena.out_of_memory(err);
             }
         }
 
@@ -663,88 +469,333 @@ impl GameState {
         GameState {
             world_arena,
             world,
-            camera_following_entity_index: None,
-            camera_p: TileMapPosition {
-                abs_tile_x: 17 / 2,
-                abs_tile_y: 9 / 2,
-                abs_tile_z: 0,
-                offset: V2::zero(),
-            },
-            player_index_for_controller: [None; 5],
-            entities: EntityCollection::new(),
-            backdrop,
-            hero_bitmaps,
+            camera_following_entity: None,
+            camera_p: generated.camera_p,
+            hero_start_p: start_room.center(),
+            player_for_controller: [None; 5],
+            entities,
+            random_series,
+            assets,
+            asset_file_error,
+            playing_sound: None,
+            transient_arena,
         }
     }
 
+    pub fn save_world(&mut self) -> Result<(), SaveWorldError> {
+        let mut scratch = self.transient_arena.begin_temporary_memory();
+        let world = SavedWorld {
+            tile_map: &self.world.tile_map,
+            entities: &self.entities,
+            camera_p: self.camera_p,
+            hero_start_p: self.hero_start_p,
+            camera_following_entity: self.camera_following_entity,
+            player_for_controller: &self.player_for_controller,
+            random_series: self.random_series,
+        };
+        let bytes = write_world(&mut scratch, &world).map_err(SaveWorldError::OutOfMemory)?;
+
+        let file_name = WORLD_FILE_NAME.as_ptr() as *const i8;
+        if debug_platform_write_entire_file(file_name, &bytes) {
+            Ok(())
+        } else {
+            Err(SaveWorldError::Write)
+        }
+    }
+
+    /// Replaces the world with the one in the world file.
+    pub fn load_world(&mut self) -> Result<(), LoadWorldError> {
+        let file_name = WORLD_FILE_NAME.as_ptr() as *const i8;
+        let file = debug_platform_read_entire_file(file_name);
+        if file.contents.is_null() {
+            return Err(LoadWorldError::Read);
+        }
+        let bytes = unsafe {
+            core::slice::from_raw_parts(file.contents as *const u8, file.content_size as usize)
+        };
+        let result = self.load_world_from(bytes);
+        debug_platform_free_file_memory(file.contents);
+        result.map_err(|_| LoadWorldError::File)
+    }
+
+    fn load_world_from(&mut self, bytes: &[u8]) -> Result<(), WorldFileError> {
+        // NOTE: Decode into scratch memory first, the world arena is only cleared once the file
+        // is known to be good.
+        {
+            let mut scratch = self.transient_arena.begin_temporary_memory();
+            read_world(bytes, &mut scratch)?;
+        }
+
+        self.world_arena.clear();
+        track_debug_stats(&mut self.world_arena);
+        let LoadedWorld {
+            tile_map,
+            entities,
+            camera_p,
+            hero_start_p,
+            camera_following_entity,
+            player_for_controller,
+            random_series,
+        } = match read_world(bytes, &mut self.world_arena) {
+            Ok(world) => world,
+            // NOTE: The same bytes decoded fine into scratch memory, so only a smaller world arena
+            // can fail here, and the old world is already gone.
+            Err(WorldFileError::OutOfMemory(err)) => self.world_arena.out_of_memory(err),
+            Err(err) => unreachable!("world file failed to decode a second time: {:?}", err),
+        };
+        let tile_map = self.world_arena.alloc(tile_map);
+        self.world = self.world_arena.alloc(World { tile_map });
+        self.entities = entities;
+        self.camera_p = camera_p;
+        self.hero_start_p = hero_start_p;
+        self.camera_following_entity = camera_following_entity;
+        self.player_for_controller = player_for_controller;
+        self.random_series = random_series;
+        Ok(())
+    }
+
     pub fn update_and_render(
         &mut self,
         input: &GameInput,
         offscreen_buffer: &mut GameOffscreenBuffer,
     ) {
+        // NOTE: A failed save or load leaves the world as it was. The frame arena being too small
+        // to write the world into is a bug though.
+        for controller in input.controllers.iter() {
+            if was_pressed(&controller.left_shoulder) {
+                if let Err(SaveWorldError::OutOfMemory(err)) = self.save_world() {
+                    self.transient_arena.out_of_memory(err);
+                }
+            } else if was_pressed(&controller.right_shoulder) {
+                let _ = self.load_world();
+            }
+        }
+
+        // NOTE: Everything allocated from the transient arena during the frame is released at
+        // the end of it.
+        let mut frame_memory = self.transient_arena.begin_temporary_memory();
+
         let screen_center_x = offscreen_buffer.width as f32 / 2.0;
         let screen_center_y = offscreen_buffer.height as f32 / 2.0;
 
+        let world_arena = &mut self.world_arena;
         let entities = &mut self.entities;
+        let random_series = &mut self.random_series;
+
+        let tile_map = &self.world.tile_map;
+        let assets = &self.assets;
+
+        // NOTE: A failed add just means no hero joins this frame.
+        for (controller_index, controller) in input.controllers.iter().enumerate() {
+            let hero_handle = self.player_for_controller[controller_index];
+            let has_hero = hero_handle
+                .and_then(|handle| entities.get_entity(handle))
+                .is_some();
+            if !has_hero && controller.start.ended_down > 0 {
+                let hero = Entity::hero(self.hero_start_p);
+                if let Ok(handle) = entities.add_entity(world_arena, hero) {
+                    self.player_for_controller[controller_index] = Some(handle);
+                    if self.camera_following_entity.is_none() {
+                        self.camera_following_entity = Some(handle);
+                    }
+                }
+            }
+        }
 
-        {
-            for (controller_index, controller) in input.controllers.iter().enumerate() {
-                if let Some(controlling_entity) = self.player_index_for_controller[controller_index]
-                    .and_then(|index| entities.get_entity_mut(index))
-                {
-                    let mut dd_player_p = V2::zero();
+        // NOTE: Only the entities around the camera are simulated, anything further away,
+        // including heroes the camera does not follow, stays frozen until the camera gets close.
+        // NOTE: One screen in every direction. Keep it a whole number of tiles, offsetting by half
+        // a tile runs into the rounding bug in `recanonicalize_coord`.
+        let sim_half_dim = tile_map.tile_side_in_meters * V2::new(17.0, 9.0);
+        let mut region = SimRegion::begin(
+            &mut frame_memory,
+            entities,
+            tile_map,
+            self.camera_p,
+            sim_half_dim,
+        );
 
-                    if controller.is_analog > 0 {
-                        dd_player_p =
-                            V2::new(controller.stick_average_x, controller.stick_average_y);
-                    } else {
-                        if controller.move_up.ended_down != 0 {
-                            dd_player_p.y = 1.0;
-                        }
-                        if controller.move_down.ended_down != 0 {
-                            dd_player_p.y = -1.0;
+        let mut footstep_variants = ArenaVec::new();
+        for (controller_index, controller) in input.controllers.iter().enumerate() {
+            let hero_handle = match self.player_for_controller[controller_index] {
+                Some(handle) => handle,
+                None => continue,
+            };
+            let hero_index = match region.find(hero_handle) {
+                Some(index) => index,
+                None => continue,
+            };
+
+            let mut dd_player_p = V2::zero();
+            if controller.is_analog > 0 {
+                dd_player_p = V2::new(controller.stick_average_x, controller.stick_average_y);
+            } else {
+                if controller.move_up.ended_down != 0 {
+                    dd_player_p.y = 1.0;
+                }
+                if controller.move_down.ended_down != 0 {
+                    dd_player_p.y = -1.0;
+                }
+                if controller.move_left.ended_down != 0 {
+                    dd_player_p.x = -1.0;
+                }
+                if controller.move_right.ended_down != 0 {
+                    dd_player_p.x = 1.0;
+                }
+            }
+
+            let mut d_sword = V2::zero();
+            if controller.action_up.ended_down != 0 {
+                d_sword = V2::new(0.0, 1.0);
+            }
+            if controller.action_down.ended_down != 0 {
+                d_sword = V2::new(0.0, -1.0);
+            }
+            if controller.action_left.ended_down != 0 {
+                d_sword = V2::new(-1.0, 0.0);
+            }
+            if controller.action_right.ended_down != 0 {
+                d_sword = V2::new(1.0, 0.0);
+            }
+
+            let entered_tile = move_entity(
+                tile_map,
+                &mut region,
+                hero_index,
+                input.dt,
+                &HERO_MOVE_SPEC,
+                dd_player_p,
+                handle_collision,
+            );
+            let footstep = entered_tile.and_then(|tile_type| tile_type.properties().footstep_sound);
+            if let Some(sound) = footstep {
+                // NOTE: Every footstep picks one of the sounds of its type, so they do not all
+                // sound the same.
+                footstep_variants.clear();
+                for id in assets.of_type(sound) {
+                    footstep_variants.push(&mut frame_memory, id);
+                }
+                if let Some(&id) = random_series.choice(&footstep_variants) {
+                    self.playing_sound = Some(PlayingSound {
+                        id,
+                        samples_played: 0,
+                    });
+                }
+            }
+            let hero = &mut region.entities_mut()[hero_index];
+            let (hero_p, hero_z) = (hero.p, hero.abs_tile_z);
+            let sword = match hero.kind {
+                EntityKind::Hero { sword } => sword,
+                _ => None,
+            };
+
+            if controller.back.ended_down > 0 {
+                hero.removed = true;
+                if let Some(sword) = sword.and_then(|sword| region.find(sword)) {
+                    region.entities_mut()[sword].removed = true;
+                }
+                self.player_for_controller[controller_index] = None;
+                if self.camera_following_entity == Some(hero_handle) {
+                    self.camera_following_entity = None;
+                }
+            } else if d_sword.len_sq() > 0.0 {
+                let sword_in_flight = sword.and_then(|sword| region.get(sword)).is_some();
+                if !sword_in_flight {
+                    let sword_p = region.world_position(tile_map, hero_p, hero_z);
+                    let sword = Entity::sword(sword_p, SWORD_SPEED * d_sword);
+                    if let Ok(sword) =
+                        region.add_entity(&mut frame_memory, world_arena, entities, tile_map, sword)
+                    {
+                        let hero = &mut region.entities_mut()[hero_index];
+                        hero.kind = EntityKind::Hero { sword: Some(sword) };
+                    }
+                }
+            }
+        }
+
+        for index in 0..region.entities().len() {
+            if region.entities()[index].removed {
+                continue;
+            }
+            match region.entities()[index].kind {
+                EntityKind::Familiar { bob_t } => {
+                    let familiar = &region.entities()[index];
+                    let mut closest_hero_delta = None;
+                    let mut closest_hero_distance_sq = FAMILIAR_SIGHT * FAMILIAR_SIGHT;
+                    for hero in region.entities() {
+                        if !hero.is_hero() || hero.removed || hero.abs_tile_z != familiar.abs_tile_z
+                        {
+                            continue;
                         }
-                        if controller.move_left.ended_down != 0 {
-                            dd_player_p.x = -1.0;
+                        let delta = hero.p - familiar.p;
+                        if delta.len_sq() < closest_hero_distance_sq {
+                            closest_hero_distance_sq = delta.len_sq();
+                            closest_hero_delta = Some(delta);
                         }
-                        if controller.move_right.ended_down != 0 {
-                            dd_player_p.x = 1.0;
+                    }
+
+                    let mut ddp = V2::zero();
+                    if let Some(delta) = closest_hero_delta {
+                        let personal_space_sq = FAMILIAR_PERSONAL_SPACE * FAMILIAR_PERSONAL_SPACE;
+                        if closest_hero_distance_sq > personal_space_sq {
+                            ddp = (1.0 / closest_hero_distance_sq.sqrt()) * delta;
                         }
+                    } else {
+                        ddp = FAMILIAR_WANDER
+                            * V2::new(random_series.bilateral(), random_series.bilateral());
                     }
 
-                    move_player(
-                        &self.world.tile_map,
-                        controlling_entity,
+                    move_entity(
+                        tile_map,
+                        &mut region,
+                        index,
                         input.dt,
-                        dd_player_p,
+                        &FAMILIAR_MOVE_SPEC,
+                        ddp,
+                        handle_collision,
                     );
-                } else {
-                    if controller.start.ended_down > 0 {
-                        let entity_index = entities.add_entity();
-                        self.player_index_for_controller[controller_index] = Some(entity_index);
-                        let entity = entities.get_entity_mut(entity_index).unwrap();
-                        initialize_player(
-                            entity,
-                            entity_index,
-                            &mut self.camera_following_entity_index,
-                        );
+                    let familiar = &mut region.entities_mut()[index];
+                    familiar.kind = EntityKind::Familiar {
+                        bob_t: (bob_t + 4.0 * input.dt) % TAU,
+                    };
+                }
+                EntityKind::Sword { distance_remaining } => {
+                    let old_p = region.entities()[index].p;
+                    move_entity(
+                        tile_map,
+                        &mut region,
+                        index,
+                        input.dt,
+                        &SWORD_MOVE_SPEC,
+                        V2::zero(),
+                        handle_collision,
+                    );
+                    let sword = &mut region.entities_mut()[index];
+                    let distance = (sword.p - old_p).len_sq().sqrt();
+
+                    // NOTE: A sword that hit a wall stops, so it is gone right away.
+                    let distance_remaining = distance_remaining - distance;
+                    if distance_remaining <= 0.0 || sword.dp.len_sq() == 0.0 {
+                        sword.removed = true;
+                    } else {
+                        sword.kind = EntityKind::Sword { distance_remaining };
                     }
                 }
+                EntityKind::Wall | EntityKind::Hero { .. } | EntityKind::Monster { .. } => {}
             }
         }
 
-        let ref tile_map = self.world.tile_map;
-
         let tile_side_in_pixels = 60.0;
         let meters_to_pixels = tile_side_in_pixels / tile_map.tile_side_in_meters;
 
         if let Some(entity) = self
-            .camera_following_entity_index
-            .and_then(|index| entities.get_entity(index).cloned())
+            .camera_following_entity
+            .and_then(|handle| region.get(handle))
         {
-            self.camera_p.abs_tile_z = entity.p.abs_tile_z;
+            let entity_p = region.world_position(tile_map, entity.p, entity.abs_tile_z);
+            self.camera_p.abs_tile_z = entity_p.abs_tile_z;
 
-            let diff = tile_map.subtract(entity.p, self.camera_p);
+            let diff = tile_map.subtract(entity_p, self.camera_p);
             if diff.dxy.x > 9.0 * tile_map.tile_side_in_meters {
                 self.camera_p.abs_tile_x += 17;
             } else if diff.dxy.x < -9.0 * tile_map.tile_side_in_meters {
@@ -757,11 +808,18 @@ impl GameState {
             }
         }
 
-        let mut render_buffer: RenderBuffer = offscreen_buffer.into();
+        let mut render_buffer = offscreen_render_buffer(offscreen_buffer);
         // let screen_width = render_buffer.width;
         // let screen_height = render_buffer.height;
 
-        draw_bitmap(&mut render_buffer, &self.backdrop, 0.0, 0.0);
+        // NOTE: Assets that are still streaming in are skipped for this frame.
+        let assets = &mut self.assets;
+        assets.begin_frame();
+        if let Some(id) = assets.first(AssetType::Backdrop) {
+            if let Some(backdrop) = assets.bitmap(id) {
+                draw_bitmap(&mut render_buffer, backdrop.bitmap.view(), 0.0, 0.0);
+            }
+        }
         // draw_rectangle(
         //     &mut render_buffer,
         //     0.0,
@@ -778,201 +836,188 @@ impl GameState {
                 let x = (self.camera_p.abs_tile_x as i32 + rel_x) as u32;
                 let y = (self.camera_p.abs_tile_y as i32 + rel_y) as u32;
 
-                if let Some(tile_value) = tile_map.get_tile_value(x, y, self.camera_p.abs_tile_z) {
-                    if tile_value < 2 {
-                        continue;
-                    }
-                    let mut gray = match tile_value {
-                        2 => 1.0,
-                        3 => 0.25,
-                        4 => 0.1,
-                        _ => 0.5,
+                if let Some(tile_type) = tile_map.get_tile_type(x, y, self.camera_p.abs_tile_z) {
+                    let properties = tile_type.properties();
+                    let (mut r, mut g, mut b) = match properties.color {
+                        Some(color) => color,
+                        None => continue,
                     };
 
                     if x == self.camera_p.abs_tile_x && y == self.camera_p.abs_tile_y {
-                        gray = 0.0;
+                        r = 0.0;
+                        g = 0.0;
+                        b = 0.0;
                     }
 
                     let tile_side = V2::new(tile_side_in_pixels, tile_side_in_pixels);
                     let cen = V2::new(
-                        screen_center_x + rel_x as f32 * tile_side_in_pixels as f32
+                        screen_center_x + rel_x as f32 * tile_side_in_pixels
                             - meters_to_pixels * self.camera_p.offset.x,
-                        screen_center_y - rel_y as f32 * tile_side_in_pixels as f32
+                        screen_center_y - rel_y as f32 * tile_side_in_pixels
                             + meters_to_pixels * self.camera_p.offset.y,
                     );
                     let min = cen - 0.5 * tile_side;
                     let max = min + tile_side;
-                    draw_rectangle(&mut render_buffer, min, max, gray, gray, gray);
+                    let bitmap = match properties
+                        .bitmap
+                        .and_then(|asset_type| assets.first(asset_type))
+                    {
+                        Some(id) => assets.bitmap(id),
+                        None => None,
+                    };
+                    match bitmap {
+                        Some(bitmap) => {
+                            draw_bitmap(&mut render_buffer, bitmap.bitmap.view(), min.x, min.y)
+                        }
+                        None => draw_rectangle(&mut render_buffer, min, max, r, g, b),
+                    }
                 }
             }
         }
 
-        for entity in entities.iter() {
-            let diff = tile_map.subtract(entity.p, self.camera_p);
-
-            let player_r = 1.0;
-            let player_g = 1.0;
-            let player_b = 0.0;
-            let player_ground_point_x = screen_center_x + meters_to_pixels * diff.dxy.x;
-            let player_ground_point_y = screen_center_y - meters_to_pixels * diff.dxy.y;
-            let player_width_height = V2::new(entity.width, entity.height);
-            let player_left_top = V2::new(
-                player_ground_point_x - 0.5 * meters_to_pixels * entity.width,
-                player_ground_point_y - 0.5 * meters_to_pixels * entity.height,
+        // NOTE: The camera may have moved away from the region origin this frame.
+        let camera_rel_p = tile_map.subtract(self.camera_p, region.origin).dxy;
+        for entity in region.entities() {
+            if entity.removed || entity.abs_tile_z != self.camera_p.abs_tile_z {
+                continue;
+            }
+            let diff = entity.p - camera_rel_p;
+
+            let mut ground_point = V2::new(
+                screen_center_x + meters_to_pixels * diff.x,
+                screen_center_y - meters_to_pixels * diff.y,
             );
+            if let EntityKind::Familiar { bob_t } = entity.kind {
+                ground_point.y -= 0.1 * meters_to_pixels * bob_t.sin();
+            }
+            let (r, g, b) = match entity.kind {
+                EntityKind::Wall => (1.0, 1.0, 1.0),
+                EntityKind::Hero { .. } => (1.0, 1.0, 0.0),
+                EntityKind::Familiar { .. } => (0.5, 0.5, 1.0),
+                EntityKind::Monster { .. } => (1.0, 0.25, 0.25),
+                EntityKind::Sword { .. } => (0.9, 0.9, 0.9),
+            };
+            for volume in entity.collision.volumes() {
+                let center =
+                    ground_point + meters_to_pixels * V2::new(volume.offset.x, -volume.offset.y);
+                let left_top = center - 0.5 * meters_to_pixels * volume.dim;
+                let right_bottom = left_top + meters_to_pixels * volume.dim;
+                draw_rectangle(&mut render_buffer, left_top, right_bottom, r, g, b);
+            }
+
+            match entity.kind {
+                EntityKind::Hero { .. } => {
+                    for &asset_type in &[
+                        AssetType::HeroTorso,
+                        AssetType::HeroCape,
+                        AssetType::HeroHead,
+                    ] {
+                        let facing =
+                            TagMatch::new(AssetTagId::FacingDirection, entity.facing_direction);
+                        let hero = match assets.best_match(asset_type, &[facing]) {
+                            Some(id) => assets.bitmap(id),
+                            None => None,
+                        };
+                        if let Some(hero) = hero {
+                            draw_bitmap(
+                                &mut render_buffer,
+                                hero.bitmap.view(),
+                                ground_point.x - hero.align_x as f32,
+                                ground_point.y - hero.align_y as f32,
+                            );
+                        }
+                    }
+                }
+                EntityKind::Monster { hit_points } => {
+                    let pip_side = 0.2 * meters_to_pixels;
+                    let pip_spacing = 1.5 * pip_side;
+                    let pips_width = hit_points as f32 * pip_spacing - (pip_spacing - pip_side);
+                    let total = entity.collision.total;
+                    let bottom =
+                        ground_point.y + meters_to_pixels * (0.5 * total.dim.y - total.offset.y);
+                    let mut pip_left_top =
+                        V2::new(ground_point.x - 0.5 * pips_width, bottom + pip_side);
+                    for _ in 0..hit_points {
+                        let pip_right_bottom = pip_left_top + V2::new(pip_side, pip_side);
+                        draw_rectangle(
+                            &mut render_buffer,
+                            pip_left_top,
+                            pip_right_bottom,
+                            1.0,
+                            0.0,
+                            0.0,
+                        );
+                        pip_left_top.x += pip_spacing;
+                    }
+                }
+                EntityKind::Wall | EntityKind::Familiar { .. } | EntityKind::Sword { .. } => {}
+            }
+        }
+
+        if self.asset_file_error.is_some() {
             draw_rectangle(
                 &mut render_buffer,
-                player_left_top,
-                player_left_top + meters_to_pixels * player_width_height,
-                player_r,
-                player_g,
-                player_b,
-            );
-            let hero_bitmaps = &self.hero_bitmaps[entity.facing_direction];
-            draw_bitmap(
-                &mut render_buffer,
-                &hero_bitmaps.torso,
-                player_ground_point_x - hero_bitmaps.align_x as f32,
-                player_ground_point_y - hero_bitmaps.align_y as f32,
-            );
-            draw_bitmap(
-                &mut render_buffer,
-                &hero_bitmaps.cape,
-                player_ground_point_x - hero_bitmaps.align_x as f32,
-                player_ground_point_y - hero_bitmaps.align_y as f32,
-            );
-            draw_bitmap(
-                &mut render_buffer,
-                &hero_bitmaps.head,
-                player_ground_point_x - hero_bitmaps.align_x as f32,
-                player_ground_point_y - hero_bitmaps.align_y as f32,
+                V2::new(0.0, 0.0),
+                V2::new(16.0, 16.0),
+                1.0,
+                0.0,
+                0.0,
             );
         }
-    }
-
-    pub fn get_sound_samples(&mut self, sound_buffer: &mut GameSoundBuffer) {
-        self.game_output_sound(sound_buffer, 400);
-    }
 
-    fn game_output_sound(&mut self, _buffer: &mut GameSoundBuffer, _tone_hz: u32) {
-        // let tone_volume = 3000;
-        // let wave_period = buffer.samples_per_second / tone_hz;
-        //
-        // let mut sample_out = buffer.samples as *mut i16;
-        //
-        // for _ in 0..buffer.sample_count {
-        //     unsafe {
-        //         let sine_value = self.t_sine.sin();
-        //         let sample_value = (sine_value * tone_volume as f32) as i16;
-        //         (*sample_out) = sample_value;
-        //         sample_out = sample_out.add(1);
-        //         (*sample_out) = sample_value;
-        //         sample_out = sample_out.add(1);
-        //
-        //         self.t_sine += (1.0 / wave_period as f32) * 2.0 * std::f32::consts::PI;
-        //         if self.t_sine > 2.0 * std::f32::consts::PI {
-        //             self.t_sine -= 2.0 * std::f32::consts::PI;
-        //         }
-        //     }
-        // }
+        region.end(world_arena, entities, tile_map);
     }
-}
-
-impl<'a> From<&'a mut GameOffscreenBuffer> for RenderBuffer<'a> {
-    fn from(buffer: &'a mut GameOffscreenBuffer) -> Self {
-        assert!(buffer.pitch >= buffer.width * buffer.bytes_per_pixel);
-        RenderBuffer {
-            bytes: unsafe {
-                core::slice::from_raw_parts_mut(
-                    buffer.memory as *mut u8,
-                    (buffer.pitch * buffer.height) as usize,
-                )
-            },
-            width: buffer.width as usize,
-            height: buffer.height as usize,
-            pitch: buffer.pitch as usize,
-            bytes_per_pixel: buffer.bytes_per_pixel as usize,
-        }
-    }
-}
 
-#[repr(C, packed(1))]
-struct BitmapHeader {
-    file_type: u16,
-    file_size: u32,
-    reserved1: u16,
-    reserved2: u16,
-    bitmap_offset: u32,
-    size: u32,
-    width: i32,
-    height: i32,
-    planes: u16,
-    bits_per_pixel: u16,
-    compression: u32,
-    size_of_bitmap: u32,
-    horz_resolution: i32,
-    vert_resolution: i32,
-    colors_used: u32,
-    colors_important: u32,
-    red_mask: u32,
-    green_mask: u32,
-    blue_mask: u32,
-}
-
-// TODO: use intrinsics _BitScanForward
-fn find_least_significant_set_bit(value: u32) -> Option<u32> {
-    for i in 0..32 {
-        if value & (1 << i) != 0 {
-            return Some(i);
+    pub fn get_sound_samples(&mut self, sound_buffer: &mut GameSoundBuffer) {
+        // NOTE: The output is interleaved 16 bit stereo.
+        let output = unsafe {
+            core::slice::from_raw_parts_mut(
+                sound_buffer.samples as *mut i16,
+                2 * sound_buffer.sample_count as usize,
+            )
+        };
+        for sample in output.iter_mut() {
+            *sample = 0;
         }
-    }
 
-    None
-}
-
-unsafe fn debug_load_bmp(file_name: *const i8) -> Option<LoadedBitmap> {
-    let result = debug_platform_read_entire_file(file_name);
-    if result.content_size > 0 {
-        let base = result.contents as *mut u8;
-        let header = &*(base as *mut BitmapHeader);
-        assert_eq!(header.compression, 3);
-
-        let mut bitmap = LoadedBitmap {
-            pixels: base.offset(header.bitmap_offset as isize) as *mut u32,
-            width: header.width as usize,
-            height: header.height as usize,
+        let playing = match self.playing_sound {
+            Some(ref mut playing) => playing,
+            None => return,
         };
-
-        let red_mask = header.red_mask;
-        let green_mask = header.green_mask;
-        let blue_mask = header.blue_mask;
-        let alpha_mask = !(red_mask | green_mask | blue_mask);
-        let red_shift = find_least_significant_set_bit(red_mask).unwrap();
-        let green_shift = find_least_significant_set_bit(green_mask).unwrap();
-        let blue_shift = find_least_significant_set_bit(blue_mask).unwrap();
-        let alpha_shift = find_least_significant_set_bit(alpha_mask).unwrap();
-
-        let width = bitmap.width as usize;
-        for row in bitmap.pixels_mut().chunks_exact_mut(width) {
-            for pixel in row {
-                let val = *pixel;
-                *pixel = ((val >> alpha_shift) << 24)
-                    | ((val >> red_shift) << 16)
-                    | ((val >> green_shift) << 8)
-                    | ((val >> blue_shift) << 0);
-            }
+        // NOTE: A sound that is still loading starts late rather than being skipped.
+        // TODO: Resample sounds that were not recorded at the output sample rate.
+        let sound = match self.assets.sound(playing.id) {
+            Some(sound) => sound,
+            None => return,
+        };
+        let channel_count = sound.channel_count as usize;
+        let sound_frames =
+            sound.samples[playing.samples_played * channel_count..].chunks_exact(channel_count);
+        let mut frames_written = 0;
+        for (output_frame, sound_frame) in output.chunks_exact_mut(2).zip(sound_frames) {
+            output_frame[0] = sound_frame[0];
+            output_frame[1] = sound_frame[channel_count.min(2) - 1];
+            frames_written += 1;
+        }
+        playing.samples_played += frames_written;
+        if playing.samples_played * channel_count >= sound.samples.len() {
+            self.playing_sound = None;
         }
-
-        return Some(bitmap);
     }
-
-    None
 }
 
-struct HeroBitmaps {
-    pub align_x: u32,
-    pub align_y: u32,
-    pub head: LoadedBitmap,
-    pub cape: LoadedBitmap,
-    pub torso: LoadedBitmap,
+fn offscreen_render_buffer(buffer: &mut GameOffscreenBuffer) -> RenderBuffer<'_> {
+    assert!(buffer.pitch >= buffer.width * buffer.bytes_per_pixel);
+    RenderBuffer {
+        bytes: unsafe {
+            core::slice::from_raw_parts_mut(
+                buffer.memory as *mut u8,
+                (buffer.pitch * buffer.height) as usize,
+            )
+        },
+        width: buffer.width as usize,
+        height: buffer.height as usize,
+        pitch: buffer.pitch as usize,
+        bytes_per_pixel: buffer.bytes_per_pixel as usize,
+    }
 }
@@ -1,106 +1,138 @@
 #![no_std]
 
-extern crate libc;
+#[cfg(test)]
+#[macro_use]
+extern crate std;
 
 extern crate base;
+extern crate handmade_asset;
+extern crate handmade_platform;
 extern crate software_renderer;
 
-use core::ffi::c_void;
 use core::ptr::null_mut;
-use libc::{c_char, c_int};
 
+mod asset;
+mod collections;
+mod collision;
+mod entity;
 mod game;
+mod memory;
+mod pool;
 mod random;
+mod sim_region;
 mod tile_map;
+mod world_file;
+mod world_gen;
 
-use game::{GameState, MemoryArena};
+use game::GameState;
+use memory::MemoryArena;
 
-#[repr(C)]
-pub struct DebugReadFileResult {
-    content_size: u32,
-    contents: *mut c_void,
-}
-
-type DebugPlatformReadEntireFile = extern "C" fn(file_name: *const c_char) -> DebugReadFileResult;
-type DebugPlatformFreeFileMemory = extern "C" fn(memory: *mut c_void);
-type DebugPlatformWriteEntireFile =
-    extern "C" fn(file_name: *const c_char, memory_size: u32, memory: *const c_void) -> i32;
+pub use handmade_platform::*;
 
 pub fn debug_platform_read_entire_file(file_name: *const i8) -> DebugReadFileResult {
     unsafe { ((*GAME_MEMORY).debug_platform_read_entire_file)(file_name) }
 }
 
-#[repr(C)]
-pub struct GameMemory {
-    is_initialized: c_int,
-    permanent_storage_size: usize,
-    permanent_storage: *mut c_void,
-    transient_storage_size: usize,
-    transient_storage: *mut c_void,
-
-    debug_platform_read_entire_file: DebugPlatformReadEntireFile,
-    debug_platform_free_file_memory: DebugPlatformFreeFileMemory,
-    debug_platform_write_entire_file: DebugPlatformWriteEntireFile,
+pub fn debug_platform_free_file_memory(memory: *mut core::ffi::c_void) {
+    unsafe { ((*GAME_MEMORY).debug_platform_free_file_memory)(memory) }
 }
 
-static mut GAME_MEMORY: *mut GameMemory = null_mut();
-
-#[repr(C)]
-pub struct GameOffscreenBuffer {
-    pub memory: *mut c_void,
-    pub width: c_int,
-    pub height: c_int,
-    pub pitch: c_int,
-    pub bytes_per_pixel: c_int,
+/// Returns false if the file could not be written.
+pub fn debug_platform_write_entire_file(file_name: *const i8, bytes: &[u8]) -> bool {
+    let write_entire_file = unsafe { (*GAME_MEMORY).debug_platform_write_entire_file };
+    let memory = bytes.as_ptr() as *const core::ffi::c_void;
+    write_entire_file(file_name, bytes.len() as u32, memory) != 0
 }
 
-#[repr(C)]
-pub struct GameSoundBuffer {
-    pub samples: *mut c_void,
-    pub sample_count: u32,
-    pub samples_per_second: u32,
+pub fn platform_add_entry(callback: PlatformWorkQueueCallback, data: *mut core::ffi::c_void) {
+    unsafe {
+        let memory = &*GAME_MEMORY;
+        match memory.platform_add_entry {
+            Some(add_entry) if !memory.work_queue.is_null() => {
+                add_entry(memory.work_queue, callback, data)
+            }
+            // NOTE: Without a work queue the work is done right away.
+            _ => callback(memory.work_queue, data),
+        }
+    }
 }
 
-#[repr(C)]
-pub struct GameButtonState {
-    pub half_transition_count: c_int,
-    pub ended_down: c_int,
+/// A file opened by the platform, see `platform_open_file`.
+#[derive(Clone, Copy)]
+pub struct PlatformFileHandle(*mut PlatformFile);
+
+/// Opens a file to read ranges of it with `platform_read_file_range`. Returns None if the file
+/// could not be opened or the platform has no file handles.
+pub fn platform_open_file(file_name: *const i8) -> Option<PlatformFileHandle> {
+    unsafe {
+        let memory = &*GAME_MEMORY;
+        match (
+            memory.platform_open_file,
+            memory.platform_read_data_from_file,
+        ) {
+            (Some(open_file), Some(_)) => {
+                let file = open_file(file_name);
+                if file.is_null() {
+                    None
+                } else {
+                    Some(PlatformFileHandle(file))
+                }
+            }
+            _ => None,
+        }
+    }
 }
 
-#[repr(C)]
-pub struct GameControllerInput {
-    pub is_connected: c_int,
-    pub is_analog: c_int,
-    pub stick_average_x: f32,
-    pub stick_average_y: f32,
-
-    pub move_up: GameButtonState,
-    pub move_down: GameButtonState,
-    pub move_left: GameButtonState,
-    pub move_right: GameButtonState,
-
-    pub action_up: GameButtonState,
-    pub action_down: GameButtonState,
-    pub action_left: GameButtonState,
-    pub action_right: GameButtonState,
-
-    pub left_shoulder: GameButtonState,
-    pub right_shoulder: GameButtonState,
-
-    pub back: GameButtonState,
-    pub start: GameButtonState,
+/// Closes a file opened with `platform_open_file`.
+pub fn platform_close_file(file: PlatformFileHandle) {
+    unsafe {
+        if let Some(close_file) = (*GAME_MEMORY).platform_close_file {
+            close_file(file.0);
+        }
+    }
 }
 
-#[repr(C)]
-pub struct GameInput {
-    pub mouse_buttons: [GameButtonState; 5],
-    pub mouse_x: i32,
-    pub mouse_y: i32,
-    pub mouse_z: i32,
-    pub dt: f32,
-    pub controllers: [GameControllerInput; 4],
+/// Reads `dest.len()` bytes at `offset` of a file. Without a handle the whole file is read
+/// through `debug_platform_read_entire_file`.
+pub fn platform_read_file_range(
+    file: Option<PlatformFileHandle>,
+    file_name: *const i8,
+    offset: u64,
+    dest: &mut [u8],
+) -> bool {
+    unsafe {
+        let memory = &*GAME_MEMORY;
+        if let (Some(PlatformFileHandle(file)), Some(read_data_from_file)) =
+            (file, memory.platform_read_data_from_file)
+        {
+            let dest_ptr = dest.as_mut_ptr() as *mut core::ffi::c_void;
+            return read_data_from_file(file, offset, dest.len() as u64, dest_ptr) != 0;
+        }
+
+        let result = debug_platform_read_entire_file(file_name);
+        if result.content_size == 0 {
+            return false;
+        }
+        let contents =
+            core::slice::from_raw_parts(result.contents as *const u8, result.content_size as usize);
+        let end = offset
+            .checked_add(dest.len() as u64)
+            .filter(|&end| end <= contents.len() as u64);
+        if let Some(end) = end {
+            dest.copy_from_slice(&contents[offset as usize..end as usize]);
+        }
+        debug_platform_free_file_memory(result.contents);
+        end.is_some()
+    }
 }
 
+static mut GAME_MEMORY: *mut GameMemory = null_mut();
+
+/// # Safety
+///
+/// All pointers have to be valid and not aliased for the duration of the call. The storage in
+/// `memory` has to be as big as the sizes next to it say, and has to be handed back unchanged on
+/// every call, since the game state lives in it.
 #[no_mangle]
 pub unsafe extern "C" fn game_update_and_render(
     memory: *mut GameMemory,
@@ -111,18 +143,32 @@ pub unsafe extern "C" fn game_update_and_render(
 
     let memory = &mut *memory;
     let mut permanent_storage = MemoryArena::from_raw_parts(
+        "permanent",
         memory.permanent_storage as *mut u8,
         memory.permanent_storage_size,
     );
+    let mut transient_storage = MemoryArena::from_raw_parts(
+        "transient",
+        memory.transient_storage as *mut u8,
+        memory.transient_storage_size,
+    );
     let mut game_state = permanent_storage.alloc_uninit();
     if memory.is_initialized == 0 {
-        *game_state = GameState::new(&mut permanent_storage);
+        // NOTE: The storage is uninitialized, so the old value must not be dropped.
+        core::ptr::write(
+            &mut *game_state,
+            GameState::new(&mut permanent_storage, &mut transient_storage),
+        );
         memory.is_initialized = 1;
     }
 
     game_state.update_and_render(&*input, &mut *offscreen_buffer);
 }
 
+/// # Safety
+///
+/// Same as for `game_update_and_render`, which also has to have been called on `memory` before,
+/// so the game state is initialized.
 #[no_mangle]
 pub unsafe extern "C" fn game_get_sound_samples(
     memory: *mut GameMemory,
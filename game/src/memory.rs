@@ -0,0 +1,695 @@
+use core::fmt;
+use core::ops::{Deref, DerefMut};
+use core::panic::Location;
+use core::ptr::null_mut;
+
+/// Alignment of sub-arenas handed out by `reserve`, enough for any SIMD type the game uses.
+pub const DEFAULT_ALIGNMENT: usize = 16;
+
+/// Number of distinct call sites an `ArenaStats` can tally.
+pub const MAX_ALLOCATION_SITES: usize = 32;
+
+/// Longest arena name that is kept, longer ones are cut off.
+pub const MAX_ARENA_NAME_LEN: usize = 16;
+
+/// Longest source file path of an allocation site that is kept, longer ones lose their start.
+pub const MAX_SITE_FILE_LEN: usize = 64;
+
+/// A short string stored by value.
+///
+/// NOTE: Arenas and their statistics live in permanent storage, which survives reloading the game
+/// library. Strings from the library, like arena names and caller locations, go away with it, so
+/// they are copied instead of pointed to.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct InlineStr<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+}
+
+pub struct MemoryArena {
+    name: InlineStr<MAX_ARENA_NAME_LEN>,
+    base: *mut u8,
+    size: usize,
+    used: usize,
+    temporary_count: usize,
+    stats: Option<ArenaObject<ArenaStats>>,
+}
+
+/// An allocation that did not fit into its arena.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AllocError {
+    pub arena: InlineStr<MAX_ARENA_NAME_LEN>,
+    /// Requested size in bytes, `usize::MAX` if the size itself overflowed.
+    pub requested: usize,
+    pub alignment: usize,
+    /// Bytes left in the arena after padding to `alignment`.
+    pub remaining: usize,
+}
+
+#[derive(Clone, Copy)]
+pub struct AllocationSite {
+    pub file: InlineStr<MAX_SITE_FILE_LEN>,
+    pub line: u32,
+    pub count: usize,
+    pub size: usize,
+}
+
+/// Usage statistics an arena keeps once `track_stats` is called on it.
+pub struct ArenaStats {
+    pub high_water_mark: usize,
+    sites: [Option<AllocationSite>; MAX_ALLOCATION_SITES],
+    /// Allocations from call sites that did not fit into the table any more.
+    pub untracked_count: usize,
+}
+
+/// Scope of scratch allocations in an arena, everything allocated through it is released when it
+/// is dropped. Scopes have to end in the reverse order they were begun in.
+pub struct TemporaryMemory<'a> {
+    arena: &'a mut MemoryArena,
+    used: usize,
+    depth: usize,
+}
+
+pub struct ArenaArray<T> {
+    ptr: *mut T,
+    len: usize,
+}
+
+impl<T> ArenaArray<T> {
+    pub fn empty() -> ArenaArray<T> {
+        ArenaArray {
+            ptr: null_mut(),
+            len: 0,
+        }
+    }
+
+    pub fn from_raw_parts(ptr: *mut T, len: usize) -> ArenaArray<T> {
+        ArenaArray { ptr, len }
+    }
+
+    pub fn as_mut_ptr(&mut self) -> *mut T {
+        self.ptr
+    }
+
+    /// The caller has to make sure the arena memory outlives the returned slice.
+    pub unsafe fn into_slice_mut<'a>(self) -> &'a mut [T] {
+        if self.len == 0 {
+            return &mut [];
+        }
+        core::slice::from_raw_parts_mut(self.ptr, self.len)
+    }
+}
+
+impl<T> Deref for ArenaArray<T> {
+    type Target = [T];
+
+    fn deref(&self) -> &Self::Target {
+        if self.len == 0 {
+            return &[];
+        }
+        unsafe { core::slice::from_raw_parts(self.ptr, self.len) }
+    }
+}
+
+impl<T> DerefMut for ArenaArray<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        if self.len == 0 {
+            return &mut [];
+        }
+        unsafe { core::slice::from_raw_parts_mut(self.ptr, self.len) }
+    }
+}
+
+pub struct ArenaObject<T: ?Sized> {
+    ptr: *mut T,
+}
+
+impl<T> ArenaObject<T> {
+    pub fn from_raw(ptr: *mut T) -> ArenaObject<T> {
+        ArenaObject { ptr }
+    }
+
+    pub fn as_ptr(&self) -> *mut T {
+        self.ptr
+    }
+}
+
+impl<T> AsRef<T> for ArenaObject<T> {
+    fn as_ref(&self) -> &T {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<T> AsMut<T> for ArenaObject<T> {
+    fn as_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.ptr }
+    }
+}
+
+impl<T> Deref for ArenaObject<T> {
+    type Target = T;
+
+    fn deref(&self) -> &Self::Target {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<T> DerefMut for ArenaObject<T> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        unsafe { &mut *self.ptr }
+    }
+}
+
+impl<const N: usize> InlineStr<N> {
+    /// Keeps the first `N` bytes of `s`, cut at a character boundary.
+    pub fn new(s: &str) -> InlineStr<N> {
+        let mut len = s.len().min(N);
+        while !s.is_char_boundary(len) {
+            len -= 1;
+        }
+        InlineStr::from_bytes(&s.as_bytes()[..len])
+    }
+
+    /// Keeps the last `N` bytes of `s`, cut at a character boundary.
+    pub fn new_tail(s: &str) -> InlineStr<N> {
+        let mut start = s.len().saturating_sub(N);
+        while !s.is_char_boundary(start) {
+            start += 1;
+        }
+        InlineStr::from_bytes(&s.as_bytes()[start..])
+    }
+
+    fn from_bytes(bytes: &[u8]) -> InlineStr<N> {
+        let mut result = InlineStr {
+            bytes: [0; N],
+            len: bytes.len(),
+        };
+        result.bytes[..bytes.len()].copy_from_slice(bytes);
+        result
+    }
+
+    pub fn as_str(&self) -> &str {
+        // NOTE: Only ever filled from a `str` cut at a character boundary.
+        unsafe { core::str::from_utf8_unchecked(&self.bytes[..self.len]) }
+    }
+}
+
+impl<const N: usize> fmt::Debug for InlineStr<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        fmt::Debug::fmt(self.as_str(), f)
+    }
+}
+
+impl<const N: usize> fmt::Display for InlineStr<N> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl fmt::Display for AllocError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "arena \"{}\" is out of memory: requested {} bytes aligned to {}, {} bytes remaining",
+            self.arena, self.requested, self.alignment, self.remaining
+        )
+    }
+}
+
+impl ArenaStats {
+    pub fn new() -> ArenaStats {
+        ArenaStats {
+            high_water_mark: 0,
+            sites: [None; MAX_ALLOCATION_SITES],
+            untracked_count: 0,
+        }
+    }
+
+    pub fn sites(&self) -> impl Iterator<Item = &AllocationSite> {
+        self.sites.iter().map_while(|site| site.as_ref())
+    }
+
+    fn record(&mut self, location: &'static Location<'static>, size: usize, used: usize) {
+        self.high_water_mark = self.high_water_mark.max(used);
+
+        let file = InlineStr::new_tail(location.file());
+        let line = location.line();
+        for slot in self.sites.iter_mut() {
+            match slot {
+                Some(site) if site.file == file && site.line == line => {
+                    site.count += 1;
+                    site.size += size;
+                    return;
+                }
+                Some(_) => {}
+                None => {
+                    *slot = Some(AllocationSite {
+                        file,
+                        line,
+                        count: 1,
+                        size,
+                    });
+                    return;
+                }
+            }
+        }
+        self.untracked_count += 1;
+    }
+}
+
+impl fmt::Display for ArenaStats {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "high water mark: {} bytes", self.high_water_mark)?;
+        for site in self.sites() {
+            write!(
+                f,
+                "\n  {}:{}: {} allocations, {} bytes",
+                site.file, site.line, site.count, site.size
+            )?;
+        }
+        if self.untracked_count > 0 {
+            write!(f, "\n  {} untracked allocations", self.untracked_count)?;
+        }
+        Ok(())
+    }
+}
+
+impl MemoryArena {
+    pub fn from_raw_parts(name: &str, base: *mut u8, size: usize) -> MemoryArena {
+        MemoryArena {
+            name: InlineStr::new(name),
+            base,
+            size,
+            used: 0,
+            temporary_count: 0,
+            stats: None,
+        }
+    }
+
+    /// Starts recording the high water mark and a per call site tally of allocations into `stats`.
+    pub fn track_stats(&mut self, stats: ArenaObject<ArenaStats>) {
+        self.stats = Some(stats);
+    }
+
+    pub fn stats(&self) -> Option<&ArenaStats> {
+        self.stats.as_ref().map(|stats| stats.as_ref())
+    }
+
+    #[track_caller]
+    pub fn alloc<T>(&mut self, val: T) -> ArenaObject<T> {
+        match self.try_alloc(val) {
+            Ok(result) => result,
+            Err(err) => self.out_of_memory(err),
+        }
+    }
+
+    #[track_caller]
+    pub fn try_alloc<T>(&mut self, val: T) -> Result<ArenaObject<T>, AllocError> {
+        unsafe {
+            let mut result = self.try_alloc_uninit::<T>()?;
+            core::ptr::write(&mut *result, val);
+            Ok(result)
+        }
+    }
+
+    #[track_caller]
+    pub unsafe fn alloc_uninit<T>(&mut self) -> ArenaObject<T> {
+        match self.try_alloc_uninit() {
+            Ok(result) => result,
+            Err(err) => self.out_of_memory(err),
+        }
+    }
+
+    #[track_caller]
+    pub unsafe fn try_alloc_uninit<T>(&mut self) -> Result<ArenaObject<T>, AllocError> {
+        let size = core::mem::size_of::<T>();
+        let memory = self.try_alloc_size(size, core::mem::align_of::<T>())?;
+        Ok(ArenaObject::from_raw(memory as *mut T))
+    }
+
+    #[track_caller]
+    pub fn alloc_array<T: Clone>(&mut self, val: T, len: usize) -> ArenaArray<T> {
+        match self.try_alloc_array(val, len) {
+            Ok(result) => result,
+            Err(err) => self.out_of_memory(err),
+        }
+    }
+
+    #[track_caller]
+    pub fn try_alloc_array<T: Clone>(
+        &mut self,
+        val: T,
+        len: usize,
+    ) -> Result<ArenaArray<T>, AllocError> {
+        let mut array = unsafe { self.try_alloc_array_uninit::<T>(len)? };
+        for e in array.iter_mut() {
+            unsafe { core::ptr::write(e, val.clone()) };
+        }
+        Ok(array)
+    }
+
+    #[track_caller]
+    pub unsafe fn try_alloc_array_uninit<T>(
+        &mut self,
+        len: usize,
+    ) -> Result<ArenaArray<T>, AllocError> {
+        self.try_alloc_array_uninit_aligned::<T>(len, core::mem::align_of::<T>())
+    }
+
+    /// Like `try_alloc_array_uninit`, but the array starts on an `alignment` boundary, which has
+    /// to be a power of two. The alignment of `T` is still honoured if it is larger.
+    #[track_caller]
+    pub unsafe fn alloc_array_uninit_aligned<T>(
+        &mut self,
+        len: usize,
+        alignment: usize,
+    ) -> ArenaArray<T> {
+        match self.try_alloc_array_uninit_aligned(len, alignment) {
+            Ok(result) => result,
+            Err(err) => self.out_of_memory(err),
+        }
+    }
+
+    #[track_caller]
+    pub unsafe fn try_alloc_array_uninit_aligned<T>(
+        &mut self,
+        len: usize,
+        alignment: usize,
+    ) -> Result<ArenaArray<T>, AllocError> {
+        let alignment = alignment.max(core::mem::align_of::<T>());
+        let size = core::mem::size_of::<T>()
+            .checked_mul(len)
+            .ok_or_else(|| self.alloc_error(usize::MAX, alignment))?;
+        let memory = self.try_alloc_size(size, alignment)?;
+        Ok(ArenaArray::from_raw_parts(memory as *mut T, len))
+    }
+
+    fn alignment_offset(&self, alignment: usize) -> usize {
+        assert!(alignment.is_power_of_two());
+        let address = self.base as usize + self.used;
+        address.wrapping_neg() & (alignment - 1)
+    }
+
+    #[track_caller]
+    unsafe fn try_alloc_size(
+        &mut self,
+        size: usize,
+        alignment: usize,
+    ) -> Result<*mut u8, AllocError> {
+        let offset = self.alignment_offset(alignment);
+        // NOTE: The padding alone can be more than what is left, even for an empty allocation.
+        if offset
+            .checked_add(size)
+            .is_none_or(|end| end > self.size - self.used)
+        {
+            return Err(self.alloc_error(size, alignment));
+        }
+
+        let memory = self.base.add(self.used + offset);
+        self.used += offset + size;
+        if let Some(ref mut stats) = self.stats {
+            stats.record(Location::caller(), size, self.used);
+        }
+        Ok(memory)
+    }
+
+    fn alloc_error(&self, requested: usize, alignment: usize) -> AllocError {
+        AllocError {
+            arena: self.name,
+            requested,
+            alignment,
+            remaining: self.remaining_aligned(alignment),
+        }
+    }
+
+    /// Grows the allocation at `memory` from `size` to `new_size` bytes, which only works if it is
+    /// the last allocation in the arena and the arena has room left.
+    #[track_caller]
+    pub fn try_extend(&mut self, memory: *mut u8, size: usize, new_size: usize) -> bool {
+        let end = self.base as usize + self.used;
+        if memory as usize + size != end
+            || new_size < size
+            || new_size - size > self.size - self.used
+        {
+            return false;
+        }
+
+        self.used += new_size - size;
+        if let Some(ref mut stats) = self.stats {
+            stats.record(Location::caller(), new_size - size, self.used);
+        }
+        true
+    }
+
+    #[cold]
+    #[track_caller]
+    pub fn out_of_memory(&self, err: AllocError) -> ! {
+        match self.stats() {
+            Some(stats) => panic!("{}\n{}", err, stats),
+            None => panic!("{}", err),
+        }
+    }
+
+    /// Splits off the next `size` bytes as a separate arena called `name`, starting on a
+    /// `DEFAULT_ALIGNMENT` boundary.
+    #[track_caller]
+    pub fn reserve(&mut self, name: &str, size: usize) -> MemoryArena {
+        match self.try_reserve(name, size) {
+            Ok(result) => result,
+            Err(err) => self.out_of_memory(err),
+        }
+    }
+
+    #[track_caller]
+    pub fn try_reserve(&mut self, name: &str, size: usize) -> Result<MemoryArena, AllocError> {
+        let base = unsafe { self.try_alloc_size(size, DEFAULT_ALIGNMENT)? };
+        Ok(MemoryArena::from_raw_parts(name, base, size))
+    }
+
+    /// Largest allocation that still fits at `DEFAULT_ALIGNMENT`, so `reserve(remaining())` always
+    /// succeeds.
+    pub fn remaining(&self) -> usize {
+        self.remaining_aligned(DEFAULT_ALIGNMENT)
+    }
+
+    /// Largest allocation that still fits after padding the next one to `alignment`.
+    pub fn remaining_aligned(&self, alignment: usize) -> usize {
+        (self.size - self.used).saturating_sub(self.alignment_offset(alignment))
+    }
+
+    /// Releases everything allocated from the arena. Statistics are no longer tracked afterwards,
+    /// they usually lived in the arena as well.
+    pub fn clear(&mut self) {
+        assert!(
+            self.temporary_count == 0,
+            "an arena can not be cleared while temporary memory is in use"
+        );
+        self.used = 0;
+        self.stats = None;
+    }
+
+    pub fn begin_temporary_memory(&mut self) -> TemporaryMemory<'_> {
+        self.temporary_count += 1;
+        TemporaryMemory {
+            used: self.used,
+            depth: self.temporary_count,
+            arena: self,
+        }
+    }
+}
+
+impl<'a> Deref for TemporaryMemory<'a> {
+    type Target = MemoryArena;
+
+    fn deref(&self) -> &Self::Target {
+        self.arena
+    }
+}
+
+impl<'a> DerefMut for TemporaryMemory<'a> {
+    fn deref_mut(&mut self) -> &mut Self::Target {
+        self.arena
+    }
+}
+
+impl<'a> Drop for TemporaryMemory<'a> {
+    fn drop(&mut self) {
+        // NOTE: Only a scope that was forgotten instead of dropped can trip these.
+        debug_assert!(
+            self.arena.temporary_count == self.depth,
+            "temporary memory has to end in the reverse order it was begun in"
+        );
+        debug_assert!(self.arena.used >= self.used);
+
+        self.arena.used = self.used;
+        self.arena.temporary_count -= 1;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::borrow::ToOwned;
+    use std::vec::Vec;
+
+    use super::*;
+
+    #[repr(align(16))]
+    struct Buffer([u8; 256]);
+
+    fn arena_over(buffer: &mut Buffer) -> MemoryArena {
+        MemoryArena::from_raw_parts("test", buffer.0.as_mut_ptr(), buffer.0.len())
+    }
+
+    #[test]
+    fn allocations_are_aligned() {
+        let mut buffer = Buffer([0; 256]);
+        let mut arena = arena_over(&mut buffer);
+        arena.alloc(0u8);
+        let value = arena.alloc(0u64);
+        assert_eq!(value.as_ptr() as usize % 8, 0);
+        assert_eq!(arena.used, 16);
+
+        arena.alloc(0u8);
+        let array = unsafe { arena.alloc_array_uninit_aligned::<u8>(3, 64) };
+        assert_eq!(array.as_ptr() as usize % 64, 0);
+        assert_eq!(
+            arena.used,
+            array.as_ptr() as usize - buffer.0.as_ptr() as usize + 3
+        );
+    }
+
+    #[test]
+    fn allocations_fit_exactly() {
+        let mut buffer = Buffer([0; 256]);
+        let mut arena = arena_over(&mut buffer);
+        arena.alloc(0u8);
+        // NOTE: 3 bytes of padding and 63 aligned values fill the arena.
+        assert_eq!(arena.remaining_aligned(4), 252);
+        assert!(arena.try_alloc_array(0u32, 63).is_ok());
+        assert_eq!(arena.remaining_aligned(1), 0);
+        assert!(arena.try_alloc(0u8).is_err());
+    }
+
+    #[test]
+    fn allocations_one_byte_too_big_fail() {
+        let mut buffer = Buffer([0; 256]);
+        let mut arena = arena_over(&mut buffer);
+        assert!(arena.try_alloc_array(0u8, 257).is_err());
+        assert_eq!(arena.used, 0);
+
+        arena.alloc(0u8);
+        assert!(arena.try_alloc_array(0u32, 64).is_err());
+        assert_eq!(arena.used, 1);
+    }
+
+    #[test]
+    fn padding_past_the_end_fails_even_for_empty_allocations() {
+        let mut buffer = Buffer([0; 256]);
+        let mut arena = MemoryArena::from_raw_parts("test", buffer.0.as_mut_ptr(), 250);
+        arena.alloc_array(0u8, 249);
+        let err = unsafe { arena.try_alloc_array_uninit_aligned::<u8>(0, 16) }
+            .err()
+            .unwrap();
+        assert_eq!((err.requested, err.alignment, err.remaining), (0, 16, 0));
+        assert_eq!(arena.used, 249);
+        assert_eq!(arena.remaining_aligned(1), 1);
+    }
+
+    #[test]
+    fn alloc_error_reports_the_failed_request() {
+        let mut buffer = Buffer([0; 256]);
+        let mut arena = arena_over(&mut buffer);
+        arena.alloc_array(0u8, 250);
+
+        let err = arena.try_alloc(0u64).err().unwrap();
+        assert_eq!(
+            err,
+            AllocError {
+                arena: InlineStr::new("test"),
+                requested: 8,
+                alignment: 8,
+                remaining: 0,
+            }
+        );
+        let err = arena.try_alloc_array(0u16, 4).err().unwrap();
+        assert_eq!((err.requested, err.alignment, err.remaining), (8, 2, 6));
+        let err = arena.try_alloc_array(0u64, usize::MAX).err().unwrap();
+        assert_eq!(err.requested, usize::MAX);
+        assert_eq!(arena.used, 250);
+    }
+
+    #[test]
+    fn temporary_memory_releases_its_allocations() {
+        let mut buffer = Buffer([0; 256]);
+        let mut arena = arena_over(&mut buffer);
+        arena.alloc(0u32);
+        {
+            let mut outer = arena.begin_temporary_memory();
+            outer.alloc(0u64);
+            assert_eq!(outer.arena.used, 16);
+            {
+                let mut inner = outer.begin_temporary_memory();
+                inner.alloc_array(0u8, 100);
+                assert_eq!(inner.arena.used, 116);
+            }
+            assert_eq!(outer.arena.used, 16);
+            outer.alloc(0u8);
+            assert_eq!(outer.arena.used, 17);
+        }
+        assert_eq!(arena.used, 4);
+        assert_eq!(arena.temporary_count, 0);
+    }
+
+    #[test]
+    #[cfg(debug_assertions)]
+    #[should_panic(expected = "reverse order")]
+    fn temporary_memory_has_to_end_in_reverse_order() {
+        let mut buffer = Buffer([0; 256]);
+        let mut arena = arena_over(&mut buffer);
+        let mut outer = arena.begin_temporary_memory();
+        // NOTE: Forgetting the inner scope is the only way to end the outer one first.
+        core::mem::forget(outer.begin_temporary_memory());
+        drop(outer);
+    }
+
+    #[test]
+    fn alloc_error_keeps_a_copy_of_the_arena_name() {
+        let mut buffer = Buffer([0; 256]);
+        let err = {
+            let name = "a name that does not fit".to_owned();
+            let mut arena =
+                MemoryArena::from_raw_parts(&name, buffer.0.as_mut_ptr(), buffer.0.len());
+            arena.try_reserve("too big", 1024).err().unwrap()
+        };
+        assert_eq!(err.arena.as_str(), "a name that does");
+    }
+
+    #[test]
+    fn inline_str_cuts_at_character_boundaries() {
+        assert_eq!(InlineStr::<4>::new("abcdef").as_str(), "abcd");
+        assert_eq!(InlineStr::<4>::new("ab\u{e9}f").as_str(), "ab\u{e9}");
+        assert_eq!(InlineStr::<3>::new("ab\u{e9}f").as_str(), "ab");
+        assert_eq!(InlineStr::<4>::new_tail("abcdef").as_str(), "cdef");
+        assert_eq!(InlineStr::<2>::new_tail("a\u{e9}f").as_str(), "f");
+    }
+
+    #[test]
+    fn stats_tally_allocations_by_file_and_line() {
+        let mut buffer = Buffer([0; 256]);
+        let mut arena = arena_over(&mut buffer);
+        let mut stats = ArenaStats::new();
+        arena.track_stats(ArenaObject::from_raw(&mut stats));
+        for _ in 0..3 {
+            arena.alloc(0u32);
+        }
+        arena.alloc(0u64);
+
+        let stats = arena.stats().unwrap();
+        let sites: Vec<_> = stats.sites().collect();
+        assert_eq!(sites.len(), 2);
+        assert!(sites[0].file.as_str().ends_with("memory.rs"));
+        assert_eq!((sites[0].count, sites[0].size), (3, 12));
+        assert_eq!((sites[1].count, sites[1].size), (1, 8));
+        assert_eq!(sites[1].line, sites[0].line + 2);
+    }
+}
@@ -0,0 +1,185 @@
+//! Fixed-size block allocator on top of a `MemoryArena`.
+//!
+//! Freed blocks go onto a free list and are handed out again before the pool takes more memory
+//! from the arena, so objects can be destroyed and recreated over a long session without the
+//! arena running out. The arena never gets the memory back.
+
+use core::mem::ManuallyDrop;
+use core::ptr::null_mut;
+
+use memory::{AllocError, ArenaObject, MemoryArena};
+
+/// Byte pattern freed blocks are filled with in debug builds.
+const POOL_POISON: u8 = 0xdd;
+
+#[repr(C)]
+union PoolBlock<T> {
+    value: ManuallyDrop<T>,
+    next_free: *mut PoolBlock<T>,
+}
+
+pub struct ArenaPool<T> {
+    first_free: *mut PoolBlock<T>,
+}
+
+impl<T> ArenaPool<T> {
+    pub fn new() -> ArenaPool<T> {
+        ArenaPool {
+            first_free: null_mut(),
+        }
+    }
+
+    /// Reuses a freed block if there is one, otherwise takes a new block from `arena`.
+    #[track_caller]
+    pub fn try_alloc(
+        &mut self,
+        arena: &mut MemoryArena,
+        value: T,
+    ) -> Result<ArenaObject<T>, AllocError> {
+        let block = if self.first_free.is_null() {
+            let block = unsafe { arena.try_alloc_uninit::<PoolBlock<T>>()? };
+            block.as_ptr()
+        } else {
+            unsafe { self.pop_free() }
+        };
+
+        let value_ptr = block as *mut T;
+        unsafe { core::ptr::write(value_ptr, value) };
+        Ok(ArenaObject::from_raw(value_ptr))
+    }
+
+    /// Drops the object and puts its block on the free list. The caller has to make sure the
+    /// object came from this pool and that nothing refers to it any more.
+    pub unsafe fn free(&mut self, object: ArenaObject<T>) {
+        let block = object.as_ptr() as *mut PoolBlock<T>;
+        core::ptr::drop_in_place(object.as_ptr());
+        self.push_free(block);
+    }
+
+    unsafe fn push_free(&mut self, block: *mut PoolBlock<T>) {
+        if cfg!(debug_assertions) {
+            core::ptr::write_bytes(
+                block as *mut u8,
+                POOL_POISON,
+                core::mem::size_of::<PoolBlock<T>>(),
+            );
+        }
+        (*block).next_free = self.first_free;
+        self.first_free = block;
+    }
+
+    unsafe fn pop_free(&mut self) -> *mut PoolBlock<T> {
+        let block = self.first_free;
+        self.first_free = (*block).next_free;
+
+        // NOTE: Anything but poison behind the link means the block was written to while it was
+        // free, most likely through a stale pointer.
+        if cfg!(debug_assertions) {
+            let link_size = core::mem::size_of::<*mut PoolBlock<T>>();
+            let bytes = core::slice::from_raw_parts(
+                (block as *const u8).add(link_size),
+                core::mem::size_of::<PoolBlock<T>>() - link_size,
+            );
+            debug_assert!(
+                bytes.iter().all(|&byte| byte == POOL_POISON),
+                "pool block was written to after it was freed"
+            );
+        }
+        block
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::cell::Cell;
+
+    use super::*;
+
+    #[repr(align(16))]
+    struct Buffer([u8; 256]);
+
+    fn arena_over(buffer: &mut Buffer) -> MemoryArena {
+        MemoryArena::from_raw_parts("test", buffer.0.as_mut_ptr(), buffer.0.len())
+    }
+
+    struct DropCounter<'a>(&'a Cell<u32>);
+
+    impl<'a> Drop for DropCounter<'a> {
+        fn drop(&mut self) {
+            self.0.set(self.0.get() + 1);
+        }
+    }
+
+    #[test]
+    fn freed_blocks_are_reused_before_taking_new_ones() {
+        let mut buffer = Buffer([0; 256]);
+        let mut arena = arena_over(&mut buffer);
+        let mut pool = ArenaPool::new();
+        let a = pool.try_alloc(&mut arena, [1u64; 4]).unwrap();
+        let b = pool.try_alloc(&mut arena, [2u64; 4]).unwrap();
+        let (a_ptr, b_ptr) = (a.as_ptr(), b.as_ptr());
+        let used = arena.remaining();
+
+        unsafe {
+            pool.free(a);
+            pool.free(b);
+        }
+        // NOTE: The free list hands out the block freed last first.
+        let c = pool.try_alloc(&mut arena, [3u64; 4]).unwrap();
+        let d = pool.try_alloc(&mut arena, [4u64; 4]).unwrap();
+        assert_eq!(c.as_ptr(), b_ptr);
+        assert_eq!(d.as_ptr(), a_ptr);
+        assert_eq!(*c, [3; 4]);
+        assert_eq!(*d, [4; 4]);
+        assert_eq!(arena.remaining(), used);
+
+        let e = pool.try_alloc(&mut arena, [5u64; 4]).unwrap();
+        assert!(arena.remaining() < used);
+        assert_ne!(e.as_ptr(), a_ptr);
+        assert_ne!(e.as_ptr(), b_ptr);
+    }
+
+    #[test]
+    fn freeing_drops_the_object() {
+        let mut buffer = Buffer([0; 256]);
+        let mut arena = arena_over(&mut buffer);
+        let drops = Cell::new(0);
+        let mut pool = ArenaPool::new();
+        let object = pool.try_alloc(&mut arena, DropCounter(&drops)).unwrap();
+        assert_eq!(drops.get(), 0);
+        unsafe { pool.free(object) };
+        assert_eq!(drops.get(), 1);
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    fn freed_blocks_are_poisoned() {
+        let mut buffer = Buffer([0; 256]);
+        let mut arena = arena_over(&mut buffer);
+        let mut pool = ArenaPool::new();
+        let object = pool.try_alloc(&mut arena, [7u8; 32]).unwrap();
+        let ptr = object.as_ptr() as *const u8;
+        unsafe { pool.free(object) };
+
+        // NOTE: The first bytes hold the free list link.
+        let link_size = core::mem::size_of::<*mut PoolBlock<[u8; 32]>>();
+        let bytes = unsafe { core::slice::from_raw_parts(ptr, 32) };
+        assert!(bytes[link_size..].iter().all(|&byte| byte == POOL_POISON));
+    }
+
+    #[cfg(debug_assertions)]
+    #[test]
+    #[should_panic(expected = "written to after it was freed")]
+    fn writes_to_freed_blocks_are_caught() {
+        let mut buffer = Buffer([0; 256]);
+        let mut arena = arena_over(&mut buffer);
+        let mut pool = ArenaPool::new();
+        let object = pool.try_alloc(&mut arena, [7u8; 32]).unwrap();
+        let ptr = object.as_ptr() as *mut u8;
+        unsafe {
+            pool.free(object);
+            *ptr.add(31) = 0;
+        }
+        let _ = pool.try_alloc(&mut arena, [8u8; 32]);
+    }
+}
@@ -1,4 +1,89 @@
-pub static RANDOM_NUMBER_TABLE: [u32; 4096] = [
+//! Random numbers.
+//!
+//! Every `RandomSeries` walks `RANDOM_NUMBER_TABLE` on its own, so separate uses of randomness do
+//! not change each other's numbers. A series wraps around at the end of the table.
+
+/// The largest number in `RANDOM_NUMBER_TABLE`.
+const RANDOM_NUMBER_MAX: u32 = 0x5f5c21f;
+
+#[derive(Clone, Copy)]
+pub struct RandomSeries {
+    index: u32,
+}
+
+impl RandomSeries {
+    /// Seeding a series with the `state` of another one continues where that one left off.
+    pub fn seed(seed: u32) -> RandomSeries {
+        RandomSeries {
+            index: seed % RANDOM_NUMBER_TABLE.len() as u32,
+        }
+    }
+
+    /// Everything needed to pick the series up again later, see `seed`.
+    pub fn state(&self) -> u32 {
+        self.index
+    }
+
+    /// Between 0 and `RANDOM_NUMBER_MAX`.
+    pub fn next_u32(&mut self) -> u32 {
+        let value = RANDOM_NUMBER_TABLE[self.index as usize];
+        self.index = (self.index + 1) % RANDOM_NUMBER_TABLE.len() as u32;
+        value
+    }
+
+    /// Between 0 and 1.
+    pub fn unilateral(&mut self) -> f32 {
+        self.next_u32() as f32 / RANDOM_NUMBER_MAX as f32
+    }
+
+    /// Between -1 and 1.
+    pub fn bilateral(&mut self) -> f32 {
+        2.0 * self.unilateral() - 1.0
+    }
+
+    /// Between `min` and `max`, both included. The table only has `RANDOM_NUMBER_MAX + 1`
+    /// different numbers, so the range can not be any wider than that.
+    pub fn range_u32(&mut self, min: u32, max: u32) -> u32 {
+        assert!(min <= max, "empty range {}..={}", min, max);
+        assert!(
+            max - min <= RANDOM_NUMBER_MAX,
+            "range {}..={} is too wide",
+            min,
+            max
+        );
+        // NOTE: Numbers past the last whole multiple of the range size would make the lower
+        // values of the range more likely, so they are skipped.
+        let count = max - min + 1;
+        let limit = (RANDOM_NUMBER_MAX + 1) / count * count;
+        loop {
+            let value = self.next_u32();
+            if value < limit {
+                return min + value % count;
+            }
+        }
+    }
+
+    pub fn range_f32(&mut self, min: f32, max: f32) -> f32 {
+        min + (max - min) * self.unilateral()
+    }
+
+    pub fn choice<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+        if items.is_empty() {
+            return None;
+        }
+        let index = self.range_u32(0, items.len() as u32 - 1);
+        items.get(index as usize)
+    }
+
+    pub fn shuffle<T>(&mut self, items: &mut [T]) {
+        for index in (1..items.len()).rev() {
+            let other = self.range_u32(0, index as u32);
+            items.swap(index, other as usize);
+        }
+    }
+}
+
+static RANDOM_NUMBER_TABLE: [u32; 4096] = [
     0x4f0143b, 0x3402005, 0x26f2b01, 0x22796b6, 0x57343bb, 0x2d9954e, 0x06f9425, 0x1789180,
     0x57d8fab, 0x5365d9c, 0x0e9ec55, 0x2a623e0, 0x366e05d, 0x3759f45, 0x1b4d151, 0x35a5411,
     0x59e734b, 0x211c9e4, 0x1b0df4d, 0x50d423d, 0x3b18f5b, 0x5066bed, 0x0aa03be, 0x2b66c73,
@@ -512,3 +597,104 @@ pub static RANDOM_NUMBER_TABLE: [u32; 4096] = [
     0x1d46fff, 0x146703c, 0x07dc71f, 0x05a6b46, 0x53660a3, 0x3b4b5c9, 0x4ec4cbb, 0x248ae53,
     0x0d5d155, 0x4363005, 0x2cbd064, 0x5c18f03, 0x214bedd, 0x42ef202, 0x41827cd, 0x27a8fe9,
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn seeding_with_the_state_continues_the_series() {
+        let mut series = RandomSeries::seed(1234);
+        for _ in 0..10 {
+            series.next_u32();
+        }
+        let mut continued = RandomSeries::seed(series.state());
+        for _ in 0..10 {
+            assert_eq!(continued.next_u32(), series.next_u32());
+        }
+    }
+
+    #[test]
+    fn series_wrap_around_at_the_end_of_the_table() {
+        let table_len = RANDOM_NUMBER_TABLE.len() as u32;
+        let mut series = RandomSeries::seed(table_len - 1);
+        assert_eq!(
+            series.next_u32(),
+            RANDOM_NUMBER_TABLE[table_len as usize - 1]
+        );
+        assert_eq!(series.state(), 0);
+        assert_eq!(RandomSeries::seed(table_len + 5).state(), 5);
+    }
+
+    #[test]
+    fn ranges_stay_within_their_bounds() {
+        let mut series = RandomSeries::seed(0);
+        let mut seen = [false; 7];
+        for _ in 0..1000 {
+            let value = series.range_u32(10, 16);
+            assert!((10..=16).contains(&value));
+            seen[(value - 10) as usize] = true;
+        }
+        assert!(seen.iter().all(|&seen| seen));
+
+        assert_eq!(series.range_u32(3, 3), 3);
+        for _ in 0..1000 {
+            let value = series.range_u32(u32::MAX - RANDOM_NUMBER_MAX, u32::MAX);
+            assert!(value >= u32::MAX - RANDOM_NUMBER_MAX);
+        }
+    }
+
+    #[test]
+    #[should_panic(expected = "too wide")]
+    fn ranges_wider_than_the_table_panic() {
+        RandomSeries::seed(0).range_u32(0, u32::MAX);
+    }
+
+    #[test]
+    fn floats_stay_within_their_bounds() {
+        let mut series = RandomSeries::seed(7);
+        for _ in 0..1000 {
+            let unilateral = series.unilateral();
+            assert!((0.0..=1.0).contains(&unilateral));
+            let bilateral = series.bilateral();
+            assert!((-1.0..=1.0).contains(&bilateral));
+            let value = series.range_f32(-2.5, 4.0);
+            assert!((-2.5..=4.0).contains(&value));
+        }
+        assert_eq!(RandomSeries::seed(7).range_f32(1.5, 1.5), 1.5);
+    }
+
+    #[test]
+    fn choices_pick_every_item() {
+        let mut series = RandomSeries::seed(42);
+        let empty: [u32; 0] = [];
+        assert!(series.choice(&empty).is_none());
+
+        let items = [10, 20, 30, 40];
+        let mut seen = [false; 4];
+        for _ in 0..100 {
+            let item = *series.choice(&items).unwrap();
+            seen[(item / 10 - 1) as usize] = true;
+        }
+        assert!(seen.iter().all(|&seen| seen));
+    }
+
+    #[test]
+    fn shuffles_are_permutations() {
+        let mut series = RandomSeries::seed(99);
+        for len in 0..20 {
+            let mut items = [0; 20];
+            for (index, item) in items.iter_mut().enumerate() {
+                *item = index;
+            }
+            let items = &mut items[..len];
+            series.shuffle(items);
+            let mut seen = [false; 20];
+            for &item in items.iter() {
+                assert!(!seen[item]);
+                seen[item] = true;
+            }
+            assert!(seen[..len].iter().all(|&seen| seen));
+        }
+    }
+}
@@ -0,0 +1,169 @@
+//! Simulation regions.
+//!
+//! Every frame the entities close to the camera are copied out of the world into a region, where
+//! they are positioned in meters relative to the region origin instead of in tiles. Moving,
+//! colliding and thinking all happen on the region, which is written back into the world at the
+//! end of the frame. Entities outside of it are not touched at all.
+
+use base::math::V2;
+
+use collections::ArenaVec;
+use collision::CollisionVolumeGroup;
+use entity::*;
+use memory::{AllocError, MemoryArena};
+use tile_map::{TileMap, TileMapPosition};
+
+pub struct SimEntity {
+    pub handle: EntityHandle,
+    pub kind: EntityKind,
+    /// Meters relative to the region origin.
+    pub p: V2,
+    pub abs_tile_z: u32,
+    pub dp: V2,
+    /// Radians counterclockwise from the positive x axis.
+    pub facing_direction: f32,
+    pub collision: CollisionVolumeGroup,
+    pub collidable: bool,
+    /// Takes the entity out of the world when the region ends.
+    pub removed: bool,
+}
+
+impl SimEntity {
+    pub fn is_hero(&self) -> bool {
+        matches!(self.kind, EntityKind::Hero { .. })
+    }
+}
+
+pub struct SimRegion {
+    pub origin: TileMapPosition,
+    entities: ArenaVec<SimEntity>,
+}
+
+impl SimRegion {
+    /// Pulls every entity on the level of `origin` that is at most `half_dim` meters away from it
+    /// on both axes into a new region, which lives in `arena`.
+    pub fn begin(
+        arena: &mut MemoryArena,
+        entities: &EntityCollection,
+        tile_map: &TileMap,
+        origin: TileMapPosition,
+        half_dim: V2,
+    ) -> SimRegion {
+        let mut region = SimRegion {
+            origin,
+            entities: ArenaVec::new(),
+        };
+
+        let min_chunk = EntityChunkKey::containing(&tile_map.offset(origin, -half_dim));
+        let max_chunk = EntityChunkKey::containing(&tile_map.offset(origin, half_dim));
+        // NOTE: Tile coordinates wrap around, so count the chunks instead of comparing them. Chunk
+        // coordinates have fewer bits than tile coordinates and wrap around earlier.
+        let chunk_mask = u32::MAX >> ENTITY_CHUNK_SHIFT;
+        let chunk_count_x = (max_chunk.chunk_x.wrapping_sub(min_chunk.chunk_x) & chunk_mask) + 1;
+        let chunk_count_y = (max_chunk.chunk_y.wrapping_sub(min_chunk.chunk_y) & chunk_mask) + 1;
+        for chunk_offset_y in 0..chunk_count_y {
+            for chunk_offset_x in 0..chunk_count_x {
+                let chunk_key = EntityChunkKey {
+                    chunk_x: (min_chunk.chunk_x + chunk_offset_x) & chunk_mask,
+                    chunk_y: (min_chunk.chunk_y + chunk_offset_y) & chunk_mask,
+                    chunk_z: origin.abs_tile_z,
+                };
+                for &handle in entities.chunk_entities(chunk_key) {
+                    let entity = entities.get_entity(handle).unwrap();
+                    let p = tile_map.subtract(entity.p, origin).dxy;
+                    if p.x.abs() <= half_dim.x && p.y.abs() <= half_dim.y {
+                        region.push(arena, handle, entity, p);
+                    }
+                }
+            }
+        }
+
+        region
+    }
+
+    pub fn entities(&self) -> &[SimEntity] {
+        &self.entities
+    }
+
+    pub fn entities_mut(&mut self) -> &mut [SimEntity] {
+        &mut self.entities
+    }
+
+    /// Index of the entity in `entities`, if it is part of the region.
+    pub fn find(&self, handle: EntityHandle) -> Option<usize> {
+        self.entities
+            .iter()
+            .position(|entity| entity.handle == handle)
+    }
+
+    pub fn get(&self, handle: EntityHandle) -> Option<&SimEntity> {
+        self.find(handle).map(|index| &self.entities[index])
+    }
+
+    /// The world position of a point `p` meters away from the region origin.
+    pub fn world_position(&self, tile_map: &TileMap, p: V2, abs_tile_z: u32) -> TileMapPosition {
+        let origin = TileMapPosition {
+            abs_tile_z,
+            ..self.origin
+        };
+        tile_map.offset(origin, p)
+    }
+
+    /// Adds `entity` to the world and simulates it from now on. The region itself grows in
+    /// `arena`, the entity is stored in `world_arena`.
+    pub fn add_entity(
+        &mut self,
+        arena: &mut MemoryArena,
+        world_arena: &mut MemoryArena,
+        entities: &mut EntityCollection,
+        tile_map: &TileMap,
+        entity: Entity,
+    ) -> Result<EntityHandle, AllocError> {
+        let p = tile_map.subtract(entity.p, self.origin).dxy;
+        let handle = entities.add_entity(world_arena, entity)?;
+        let entity = entities.get_entity(handle).unwrap();
+        self.push(arena, handle, entity, p);
+        Ok(handle)
+    }
+
+    /// Writes the simulated entities back into the world and removes the ones that were marked
+    /// as removed.
+    pub fn end(
+        self,
+        world_arena: &mut MemoryArena,
+        entities: &mut EntityCollection,
+        tile_map: &TileMap,
+    ) {
+        for sim_entity in self.entities.iter() {
+            if sim_entity.removed {
+                entities.remove_entity(world_arena, sim_entity.handle);
+                continue;
+            }
+
+            let p = self.world_position(tile_map, sim_entity.p, sim_entity.abs_tile_z);
+            entities.set_entity_position(world_arena, sim_entity.handle, p);
+            if let Some(entity) = entities.get_entity_mut(sim_entity.handle) {
+                entity.kind = sim_entity.kind;
+                entity.dp = sim_entity.dp;
+                entity.facing_direction = sim_entity.facing_direction;
+                entity.collision = sim_entity.collision;
+                entity.collidable = sim_entity.collidable;
+            }
+        }
+    }
+
+    fn push(&mut self, arena: &mut MemoryArena, handle: EntityHandle, entity: &Entity, p: V2) {
+        let sim_entity = SimEntity {
+            handle,
+            kind: entity.kind,
+            p,
+            abs_tile_z: entity.p.abs_tile_z,
+            dp: entity.dp,
+            facing_direction: entity.facing_direction,
+            collision: entity.collision,
+            collidable: entity.collidable,
+            removed: false,
+        };
+        self.entities.push(arena, sim_entity);
+    }
+}
@@ -1,8 +1,101 @@
 use core::num::Wrapping;
 
 use base::math::V2;
+use handmade_asset::AssetType;
 
-use game::{ArenaArray, MemoryArena};
+use collections::ArenaHashMap;
+use collision::{CollisionVolume, CollisionVolumeGroup};
+use memory::{ArenaArray, MemoryArena};
+
+/// What a tile is. Everything that depends on the kind of a tile is looked up in its
+/// `TileProperties`, so a new kind of tile only needs a variant and its properties.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[repr(u8)]
+pub enum TileType {
+    /// Tiles in a chunk that were never set.
+    Void = 0,
+    Floor = 1,
+    Wall = 2,
+    StairsUp = 3,
+    StairsDown = 4,
+}
+
+/// What movers run into on a tile.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TileShape {
+    /// Nothing, the tile can be walked over freely.
+    Open,
+    /// The whole tile.
+    Solid,
+    /// Rails along the left and the right edge, the tile is walked onto from the top or the
+    /// bottom.
+    SideRails,
+}
+
+pub struct TileProperties {
+    pub shape: TileShape,
+    /// Levels an entity moves up by when it steps onto the tile, negative to move down.
+    pub level_change: i32,
+    /// Drawn when there is no bitmap or it is not loaded yet. Tiles without a color are not
+    /// drawn at all.
+    pub color: Option<(f32, f32, f32)>,
+    pub bitmap: Option<AssetType>,
+    /// Played when a hero steps onto the tile.
+    pub footstep_sound: Option<AssetType>,
+}
+
+impl TileType {
+    pub fn from_u8(value: u8) -> Option<TileType> {
+        match value {
+            0 => Some(TileType::Void),
+            1 => Some(TileType::Floor),
+            2 => Some(TileType::Wall),
+            3 => Some(TileType::StairsUp),
+            4 => Some(TileType::StairsDown),
+            _ => None,
+        }
+    }
+
+    pub fn properties(self) -> &'static TileProperties {
+        match self {
+            TileType::Void => &TileProperties {
+                shape: TileShape::Solid,
+                level_change: 0,
+                color: None,
+                bitmap: None,
+                footstep_sound: None,
+            },
+            TileType::Floor => &TileProperties {
+                shape: TileShape::Open,
+                level_change: 0,
+                color: None,
+                bitmap: None,
+                footstep_sound: Some(AssetType::FootstepFloor),
+            },
+            TileType::Wall => &TileProperties {
+                shape: TileShape::Solid,
+                level_change: 0,
+                color: Some((1.0, 1.0, 1.0)),
+                bitmap: None,
+                footstep_sound: None,
+            },
+            TileType::StairsUp => &TileProperties {
+                shape: TileShape::SideRails,
+                level_change: 1,
+                color: Some((0.25, 0.25, 0.25)),
+                bitmap: None,
+                footstep_sound: Some(AssetType::FootstepStairs),
+            },
+            TileType::StairsDown => &TileProperties {
+                shape: TileShape::SideRails,
+                level_change: -1,
+                color: Some((0.1, 0.1, 0.1)),
+                bitmap: None,
+                footstep_sound: Some(AssetType::FootstepStairs),
+            },
+        }
+    }
+}
 
 #[derive(Copy, Clone, Default)]
 pub struct TileMapPosition {
@@ -36,43 +129,57 @@ pub struct TileMap {
     pub chunk_mask: u32,
     pub chunk_dim: u32,
 
-    pub tile_chunk_count_x: u32,
-    pub tile_chunk_count_y: u32,
-    pub tile_chunk_count_z: u32,
-    pub tile_chunks: ArenaArray<TileChunk>,
+    /// Only the chunks that had a tile set exist, every other tile reads as `None`.
+    tile_chunks: ArenaHashMap<TileChunkKey, TileChunk>,
 }
 
 impl TileMap {
+    pub fn new(tile_side_in_meters: f32, chunk_shift: u32) -> TileMap {
+        TileMap {
+            tile_side_in_meters,
+            chunk_shift,
+            chunk_mask: (1 << chunk_shift) - 1,
+            chunk_dim: 1 << chunk_shift,
+            tile_chunks: ArenaHashMap::new(),
+        }
+    }
+
     pub fn get_tile_chunk(
         &self,
         tile_chunk_x: u32,
         tile_chunk_y: u32,
         tile_chunk_z: u32,
     ) -> Option<&TileChunk> {
-        self.tile_chunks.get(
-            (Wrapping(tile_chunk_z)
-                * Wrapping(self.tile_chunk_count_y)
-                * Wrapping(self.tile_chunk_count_x)
-                + Wrapping(tile_chunk_y) * Wrapping(self.tile_chunk_count_x)
-                + Wrapping(tile_chunk_x))
-            .0 as usize,
-        )
+        self.tile_chunks.get(&TileChunkKey {
+            tile_chunk_x,
+            tile_chunk_y,
+            tile_chunk_z,
+        })
     }
 
-    pub fn get_tile_chunk_mut(
+    /// Returns the chunk, creating an empty one in `arena` first if there is none yet.
+    pub fn get_or_create_tile_chunk(
         &mut self,
+        arena: &mut MemoryArena,
         tile_chunk_x: u32,
         tile_chunk_y: u32,
         tile_chunk_z: u32,
-    ) -> Option<&mut TileChunk> {
-        self.tile_chunks.get_mut(
-            (Wrapping(tile_chunk_z)
-                * Wrapping(self.tile_chunk_count_y)
-                * Wrapping(self.tile_chunk_count_x)
-                + Wrapping(tile_chunk_y) * Wrapping(self.tile_chunk_count_x)
-                + Wrapping(tile_chunk_x))
-            .0 as usize,
-        )
+    ) -> &mut TileChunk {
+        let key = TileChunkKey {
+            tile_chunk_x,
+            tile_chunk_y,
+            tile_chunk_z,
+        };
+        let chunk_dim = self.chunk_dim;
+        self.tile_chunks
+            .get_or_insert_with(arena, key, || TileChunk {
+                tiles: ArenaArray::empty(),
+                chunk_dim,
+            })
+    }
+
+    pub fn tile_chunks(&self) -> impl Iterator<Item = (&TileChunkKey, &TileChunk)> {
+        self.tile_chunks.iter()
     }
 
     fn recanonicalize_coord(&self, tile: &mut u32, tile_rel: &mut f32) {
@@ -118,7 +225,12 @@ impl TileMap {
         }
     }
 
-    pub fn get_tile_value(&self, abs_tile_x: u32, abs_tile_y: u32, abs_tile_z: u32) -> Option<i32> {
+    pub fn get_tile_type(
+        &self,
+        abs_tile_x: u32,
+        abs_tile_y: u32,
+        abs_tile_z: u32,
+    ) -> Option<TileType> {
         let chunk_pos = self.get_chunk_position(abs_tile_x, abs_tile_y, abs_tile_z);
         self.get_tile_chunk(
             chunk_pos.tile_chunk_x,
@@ -127,42 +239,47 @@ impl TileMap {
         )
         .and_then(|tile_chunk| {
             tile_chunk
-                .get_tile_value(chunk_pos.rel_tile_x, chunk_pos.rel_tile_y)
-                .map(|x| *x)
+                .get_tile_type(chunk_pos.rel_tile_x, chunk_pos.rel_tile_y)
+                .copied()
         })
     }
 
-    pub fn is_point_empty(&self, pos: TileMapPosition) -> bool {
-        if let Some(tile_value) =
-            self.get_tile_value(pos.abs_tile_x, pos.abs_tile_y, pos.abs_tile_z)
-        {
-            return tile_value == 1 || tile_value == 3 || tile_value == 4;
+    /// What movers collide with on the tile at `pos`, relative to the tile center. `None` if
+    /// nothing on it is in the way.
+    pub fn get_tile_collision(&self, pos: TileMapPosition) -> Option<CollisionVolumeGroup> {
+        let tile_type = self.get_tile_type(pos.abs_tile_x, pos.abs_tile_y, pos.abs_tile_z)?;
+        let side = self.tile_side_in_meters;
+        match tile_type.properties().shape {
+            TileShape::Open => None,
+            TileShape::Solid => Some(CollisionVolumeGroup::single(V2::new(side, side))),
+            TileShape::SideRails => {
+                let rail_thickness = 0.1 * side;
+                let rail_dim = V2::new(rail_thickness, side);
+                let rail_offset_x = 0.5 * (side - rail_thickness);
+                Some(CollisionVolumeGroup::new(&[
+                    CollisionVolume::new(V2::new(-rail_offset_x, 0.0), rail_dim),
+                    CollisionVolume::new(V2::new(rail_offset_x, 0.0), rail_dim),
+                ]))
+            }
         }
-        true
     }
 
-    pub fn set_tile_value(
+    pub fn set_tile_type(
         &mut self,
         arena: &mut MemoryArena,
         abs_tile_x: u32,
         abs_tile_y: u32,
         abs_tile_z: u32,
-        tile_value: i32,
+        tile_type: TileType,
     ) {
         let chunk_pos = self.get_chunk_position(abs_tile_x, abs_tile_y, abs_tile_z);
-        let tile_chunk = self
-            .get_tile_chunk_mut(
-                chunk_pos.tile_chunk_x,
-                chunk_pos.tile_chunk_y,
-                chunk_pos.tile_chunk_z,
-            )
-            .unwrap();
-        tile_chunk.set_tile_value(
+        let tile_chunk = self.get_or_create_tile_chunk(
             arena,
-            chunk_pos.rel_tile_x,
-            chunk_pos.rel_tile_y,
-            tile_value,
+            chunk_pos.tile_chunk_x,
+            chunk_pos.tile_chunk_y,
+            chunk_pos.tile_chunk_z,
         );
+        tile_chunk.set_tile_type(arena, chunk_pos.rel_tile_x, chunk_pos.rel_tile_y, tile_type);
     }
 
     pub fn subtract(&self, a: TileMapPosition, b: TileMapPosition) -> TileMapDifference {
@@ -170,10 +287,8 @@ impl TileMap {
             a.abs_tile_x as f32 - b.abs_tile_x as f32,
             a.abs_tile_y as f32 - b.abs_tile_y as f32,
         );
-        let d_tile_z = a.abs_tile_z as f32 - b.abs_tile_z as f32;
         TileMapDifference {
             dxy: self.tile_side_in_meters * d_tile_xy + (a.offset - b.offset),
-            dz: self.tile_side_in_meters * d_tile_z,
         }
     }
 
@@ -184,35 +299,43 @@ impl TileMap {
 }
 
 pub struct TileChunk {
-    pub tiles: ArenaArray<i32>,
+    pub tiles: ArenaArray<TileType>,
     pub chunk_dim: u32,
 }
 
 impl TileChunk {
-    pub fn get_tile_value(&self, tile_x: u32, tile_y: u32) -> Option<&i32> {
+    pub fn get_tile_type(&self, tile_x: u32, tile_y: u32) -> Option<&TileType> {
         self.tiles.get((tile_y * self.chunk_dim + tile_x) as usize)
     }
 
-    pub fn set_tile_value(
+    pub fn set_tile_type(
         &mut self,
         arena: &mut MemoryArena,
         tile_x: u32,
         tile_y: u32,
-        tile_value: i32,
+        tile_type: TileType,
     ) {
-        if self.tiles.len() == 0 {
-            self.tiles = arena.alloc_array(0, (self.chunk_dim * self.chunk_dim) as usize);
+        if self.tiles.is_empty() {
+            self.tiles =
+                arena.alloc_array(TileType::Void, (self.chunk_dim * self.chunk_dim) as usize);
         }
 
         if let Some(tile) = self
             .tiles
             .get_mut((tile_y * self.chunk_dim + tile_x) as usize)
         {
-            *tile = tile_value;
+            *tile = tile_type;
         }
     }
 }
 
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct TileChunkKey {
+    pub tile_chunk_x: u32,
+    pub tile_chunk_y: u32,
+    pub tile_chunk_z: u32,
+}
+
 struct TileChunkPosition {
     tile_chunk_x: u32,
     tile_chunk_y: u32,
@@ -223,5 +346,4 @@ struct TileChunkPosition {
 
 pub struct TileMapDifference {
     pub dxy: V2,
-    pub dz: f32,
 }
@@ -0,0 +1,859 @@
+//! Saving and loading the world.
+//!
+//! A world file starts with a header, followed by sections that each carry an id and their size:
+//!
+//! ```text
+//! header:   magic "HHWF", version: u32, section_count: u32
+//! section:  id: u32, size: u64, size bytes of contents
+//! ```
+//!
+//! Everything is little endian. Readers skip sections they do not know and ignore bytes at the end
+//! of sections and entity records they do not understand, so newer versions can add to the format
+//! without breaking older files. Every section that version 1 writes is required. Entities refer to
+//! each other by their index in the entity section.
+
+use base::math::V2;
+
+use collections::{ArenaHashMap, ArenaVec};
+use collision::{CollisionVolume, CollisionVolumeGroup, MAX_COLLISION_VOLUMES};
+use entity::*;
+use memory::{AllocError, MemoryArena};
+use random::RandomSeries;
+use tile_map::{TileMap, TileMapPosition, TileType};
+
+pub const WORLD_FILE_MAGIC: [u8; 4] = *b"HHWF";
+pub const WORLD_FILE_VERSION: u32 = 1;
+
+const SECTION_TILE_MAP: u32 = 1;
+const SECTION_ENTITIES: u32 = 2;
+const SECTION_CAMERA: u32 = 3;
+const SECTION_PLAYERS: u32 = 4;
+const SECTION_HERO_START: u32 = 5;
+const SECTION_RANDOM: u32 = 6;
+
+const ENTITY_KIND_WALL: u32 = 1;
+const ENTITY_KIND_HERO: u32 = 2;
+const ENTITY_KIND_FAMILIAR: u32 = 3;
+const ENTITY_KIND_MONSTER: u32 = 4;
+const ENTITY_KIND_SWORD: u32 = 5;
+
+/// Stands in for an entity reference that refers to no entity.
+const NO_ENTITY: u32 = u32::MAX;
+
+/// Chunks larger than this are not a tile map this game ever made.
+const MAX_CHUNK_SHIFT: u32 = 8;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum WorldFileError {
+    BadMagic,
+    UnsupportedVersion(u32),
+    /// A section or record lies outside of the file.
+    Truncated,
+    MissingSection(u32),
+    InvalidChunkShift(u32),
+    /// The tile side is not a positive number of meters.
+    InvalidTileSize,
+    /// A position is further than half a tile from the center of its tile, or not a number.
+    InvalidPosition,
+    /// A velocity, direction or size is NaN or infinite.
+    NonFiniteNumber,
+    InvalidTileType(u8),
+    InvalidEntityKind(u32),
+    /// An entity refers to an entity index that is not in the file.
+    InvalidEntityReference(u32),
+    InvalidCollisionVolumeCount(u32),
+    OutOfMemory(AllocError),
+}
+
+/// The parts of the game that go into a world file.
+pub struct SavedWorld<'a> {
+    pub tile_map: &'a TileMap,
+    pub entities: &'a EntityCollection,
+    pub camera_p: TileMapPosition,
+    pub hero_start_p: TileMapPosition,
+    pub camera_following_entity: Option<EntityHandle>,
+    pub player_for_controller: &'a [Option<EntityHandle>],
+    pub random_series: RandomSeries,
+}
+
+/// Everything a world file holds, with the entity references turned into handles.
+pub struct LoadedWorld {
+    pub tile_map: TileMap,
+    pub entities: EntityCollection,
+    pub camera_p: TileMapPosition,
+    pub hero_start_p: TileMapPosition,
+    pub camera_following_entity: Option<EntityHandle>,
+    pub player_for_controller: [Option<EntityHandle>; 5],
+    pub random_series: RandomSeries,
+}
+
+/// Encodes the world into a new buffer in `arena`.
+pub fn write_world(
+    arena: &mut MemoryArena,
+    world: &SavedWorld,
+) -> Result<ArenaVec<u8>, AllocError> {
+    let SavedWorld {
+        tile_map,
+        entities,
+        camera_p,
+        hero_start_p,
+        camera_following_entity,
+        player_for_controller,
+        random_series,
+    } = *world;
+
+    // NOTE: The file index of an entity is its position in `EntityCollection::iter`.
+    let mut file_indices = ArenaHashMap::new();
+    for (file_index, (handle, _)) in entities.iter().enumerate() {
+        file_indices.try_insert(arena, handle, file_index as u32)?;
+    }
+    let entity_ref = |handle: Option<EntityHandle>| {
+        handle
+            .and_then(|handle| file_indices.get(&handle).cloned())
+            .unwrap_or(NO_ENTITY)
+    };
+
+    let mut writer = ByteWriter::new(arena);
+    writer.write_bytes(&WORLD_FILE_MAGIC);
+    writer.write_u32(WORLD_FILE_VERSION);
+    // NOTE: The section count is filled in by `finish`.
+    writer.write_u32(0);
+
+    let section = writer.begin_section(SECTION_TILE_MAP);
+    writer.write_f32(tile_map.tile_side_in_meters);
+    writer.write_u32(tile_map.chunk_shift);
+    writer.write_u32(tile_map.tile_chunks().count() as u32);
+    for (key, chunk) in tile_map.tile_chunks() {
+        writer.write_u32(key.tile_chunk_x);
+        writer.write_u32(key.tile_chunk_y);
+        writer.write_u32(key.tile_chunk_z);
+        for tile_y in 0..chunk.chunk_dim {
+            for tile_x in 0..chunk.chunk_dim {
+                // NOTE: Chunks that were created but never written to have no tiles yet.
+                let tile_type = chunk
+                    .get_tile_type(tile_x, tile_y)
+                    .cloned()
+                    .unwrap_or(TileType::Void);
+                writer.write_bytes(&[tile_type as u8]);
+            }
+        }
+    }
+    writer.end_section(section);
+
+    let section = writer.begin_section(SECTION_ENTITIES);
+    writer.write_u32(entities.iter().count() as u32);
+    for (_, entity) in entities.iter() {
+        let record = writer.begin_record();
+        let (kind, kind_data) = match entity.kind {
+            EntityKind::Wall => (ENTITY_KIND_WALL, 0),
+            EntityKind::Hero { sword } => (ENTITY_KIND_HERO, entity_ref(sword)),
+            EntityKind::Familiar { bob_t } => (ENTITY_KIND_FAMILIAR, bob_t.to_bits()),
+            EntityKind::Monster { hit_points } => (ENTITY_KIND_MONSTER, hit_points),
+            EntityKind::Sword { distance_remaining } => {
+                (ENTITY_KIND_SWORD, distance_remaining.to_bits())
+            }
+        };
+        writer.write_u32(kind);
+        writer.write_u32(kind_data);
+        writer.write_position(entity.p);
+        writer.write_v2(entity.dp);
+        writer.write_f32(entity.facing_direction);
+        writer.write_u32(entity.collidable as u32);
+        let volumes = entity.collision.volumes();
+        writer.write_u32(volumes.len() as u32);
+        for volume in volumes {
+            writer.write_v2(volume.offset);
+            writer.write_v2(volume.dim);
+        }
+        writer.end_record(record);
+    }
+    writer.end_section(section);
+
+    let section = writer.begin_section(SECTION_CAMERA);
+    writer.write_position(camera_p);
+    writer.end_section(section);
+
+    let section = writer.begin_section(SECTION_HERO_START);
+    writer.write_position(hero_start_p);
+    writer.end_section(section);
+
+    let section = writer.begin_section(SECTION_RANDOM);
+    writer.write_u32(random_series.state());
+    writer.end_section(section);
+
+    let section = writer.begin_section(SECTION_PLAYERS);
+    writer.write_u32(entity_ref(camera_following_entity));
+    writer.write_u32(player_for_controller.len() as u32);
+    for &player in player_for_controller {
+        writer.write_u32(entity_ref(player));
+    }
+    writer.end_section(section);
+
+    writer.finish()
+}
+
+/// Decodes a world file, the tile map and the entities are stored in `arena`.
+pub fn read_world(bytes: &[u8], arena: &mut MemoryArena) -> Result<LoadedWorld, WorldFileError> {
+    let mut reader = ByteReader::new(bytes);
+    if reader.read_bytes(WORLD_FILE_MAGIC.len())? != WORLD_FILE_MAGIC {
+        return Err(WorldFileError::BadMagic);
+    }
+    let version = reader.read_u32()?;
+    if version == 0 || version > WORLD_FILE_VERSION {
+        return Err(WorldFileError::UnsupportedVersion(version));
+    }
+
+    let mut tile_map_section = None;
+    let mut entities_section = None;
+    let mut camera_section = None;
+    let mut players_section = None;
+    let mut hero_start_section = None;
+    let mut random_section = None;
+    let section_count = reader.read_u32()?;
+    for _ in 0..section_count {
+        let id = reader.read_u32()?;
+        let size = reader.read_u64()?;
+        if size > reader.remaining() as u64 {
+            return Err(WorldFileError::Truncated);
+        }
+        let contents = reader.read_bytes(size as usize)?;
+        match id {
+            SECTION_TILE_MAP => tile_map_section = Some(contents),
+            SECTION_ENTITIES => entities_section = Some(contents),
+            SECTION_CAMERA => camera_section = Some(contents),
+            SECTION_PLAYERS => players_section = Some(contents),
+            SECTION_HERO_START => hero_start_section = Some(contents),
+            SECTION_RANDOM => random_section = Some(contents),
+            _ => {}
+        }
+    }
+    let tile_map = read_tile_map(
+        &mut section_reader(tile_map_section, SECTION_TILE_MAP)?,
+        arena,
+    )?;
+
+    let mut entities = EntityCollection::new();
+    let mut handles = ArenaVec::new();
+    let mut pending_swords = ArenaVec::new();
+    let mut reader = section_reader(entities_section, SECTION_ENTITIES)?;
+    let entity_count = reader.read_u32()?;
+    for _ in 0..entity_count {
+        let record_size = reader.read_u32()?;
+        let mut record = ByteReader::new(reader.read_bytes(record_size as usize)?);
+        let (entity, sword) = read_entity(&mut record, &tile_map)?;
+        let handle = entities
+            .add_entity(arena, entity)
+            .map_err(WorldFileError::OutOfMemory)?;
+        handles
+            .try_push(arena, handle)
+            .map_err(WorldFileError::OutOfMemory)?;
+        if sword != NO_ENTITY {
+            pending_swords
+                .try_push(arena, (handle, sword))
+                .map_err(WorldFileError::OutOfMemory)?;
+        }
+    }
+    // NOTE: A hero can be saved before its sword, so the swords are resolved once every entity
+    // is in.
+    for &(hero, sword) in pending_swords.iter() {
+        let sword = resolve_entity_ref(&handles, sword)?;
+        if let Some(Entity {
+            kind: EntityKind::Hero { sword: hero_sword },
+            ..
+        }) = entities.get_entity_mut(hero)
+        {
+            *hero_sword = sword;
+        }
+    }
+
+    let camera_p = section_reader(camera_section, SECTION_CAMERA)?.read_position(&tile_map)?;
+    let hero_start_p =
+        section_reader(hero_start_section, SECTION_HERO_START)?.read_position(&tile_map)?;
+    let random_series =
+        RandomSeries::seed(section_reader(random_section, SECTION_RANDOM)?.read_u32()?);
+
+    let mut reader = section_reader(players_section, SECTION_PLAYERS)?;
+    let camera_following_entity = resolve_entity_ref(&handles, reader.read_u32()?)?;
+    let mut player_for_controller = [None; 5];
+    let controller_count = reader.read_u32()?;
+    for controller_index in 0..controller_count as usize {
+        let player = resolve_entity_ref(&handles, reader.read_u32()?)?;
+        // NOTE: Players of controllers this build does not have stay in the world unplayed.
+        if let Some(slot) = player_for_controller.get_mut(controller_index) {
+            *slot = player;
+        }
+    }
+
+    Ok(LoadedWorld {
+        tile_map,
+        entities,
+        camera_p,
+        hero_start_p,
+        camera_following_entity,
+        player_for_controller,
+        random_series,
+    })
+}
+
+fn section_reader(contents: Option<&[u8]>, id: u32) -> Result<ByteReader<'_>, WorldFileError> {
+    match contents {
+        Some(contents) => Ok(ByteReader::new(contents)),
+        None => Err(WorldFileError::MissingSection(id)),
+    }
+}
+
+fn read_tile_map(
+    reader: &mut ByteReader,
+    arena: &mut MemoryArena,
+) -> Result<TileMap, WorldFileError> {
+    // NOTE: Positions are recanonicalized with the tile side, which only works for sides that
+    // are finite and positive.
+    let tile_side_in_meters = f32::from_bits(reader.read_u32()?);
+    if !(tile_side_in_meters.is_finite() && tile_side_in_meters > 0.0) {
+        return Err(WorldFileError::InvalidTileSize);
+    }
+    let chunk_shift = reader.read_u32()?;
+    if chunk_shift > MAX_CHUNK_SHIFT {
+        return Err(WorldFileError::InvalidChunkShift(chunk_shift));
+    }
+
+    let mut tile_map = TileMap::new(tile_side_in_meters, chunk_shift);
+    let chunk_dim = tile_map.chunk_dim;
+    let chunk_count = reader.read_u32()?;
+    for _ in 0..chunk_count {
+        let tile_chunk_x = reader.read_u32()?;
+        let tile_chunk_y = reader.read_u32()?;
+        let tile_chunk_z = reader.read_u32()?;
+        let tiles = reader.read_bytes((chunk_dim * chunk_dim) as usize)?;
+        let chunk =
+            tile_map.get_or_create_tile_chunk(arena, tile_chunk_x, tile_chunk_y, tile_chunk_z);
+        for (tile_index, &tile) in tiles.iter().enumerate() {
+            let tile_type = TileType::from_u8(tile).ok_or(WorldFileError::InvalidTileType(tile))?;
+            // NOTE: Void is what a chunk is filled with anyway, so all void chunks stay without
+            // tiles just like they were saved.
+            if tile_type != TileType::Void {
+                let tile_index = tile_index as u32;
+                chunk.set_tile_type(
+                    arena,
+                    tile_index % chunk_dim,
+                    tile_index / chunk_dim,
+                    tile_type,
+                );
+            }
+        }
+    }
+
+    Ok(tile_map)
+}
+
+/// Reads an entity record, the sword reference of a hero is returned separately since its
+/// entity may not be loaded yet.
+fn read_entity(
+    reader: &mut ByteReader,
+    tile_map: &TileMap,
+) -> Result<(Entity, u32), WorldFileError> {
+    let kind = reader.read_u32()?;
+    let kind_data = reader.read_u32()?;
+    let mut sword = NO_ENTITY;
+    let kind = match kind {
+        ENTITY_KIND_WALL => EntityKind::Wall,
+        ENTITY_KIND_HERO => {
+            sword = kind_data;
+            EntityKind::Hero { sword: None }
+        }
+        ENTITY_KIND_FAMILIAR => EntityKind::Familiar {
+            bob_t: finite(f32::from_bits(kind_data))?,
+        },
+        ENTITY_KIND_MONSTER => EntityKind::Monster {
+            hit_points: kind_data,
+        },
+        ENTITY_KIND_SWORD => EntityKind::Sword {
+            distance_remaining: finite(f32::from_bits(kind_data))?,
+        },
+        _ => return Err(WorldFileError::InvalidEntityKind(kind)),
+    };
+
+    let p = reader.read_position(tile_map)?;
+    let dp = reader.read_v2()?;
+    let facing_direction = reader.read_f32()?;
+    let collidable = reader.read_u32()? != 0;
+
+    let volume_count = reader.read_u32()?;
+    if volume_count == 0 || volume_count as usize > MAX_COLLISION_VOLUMES {
+        return Err(WorldFileError::InvalidCollisionVolumeCount(volume_count));
+    }
+    let mut volumes = [CollisionVolume::new(V2::zero(), V2::zero()); MAX_COLLISION_VOLUMES];
+    for volume in volumes[..volume_count as usize].iter_mut() {
+        let offset = reader.read_v2()?;
+        let dim = reader.read_v2()?;
+        *volume = CollisionVolume::new(offset, dim);
+    }
+
+    let entity = Entity {
+        kind,
+        p,
+        dp,
+        facing_direction,
+        collision: CollisionVolumeGroup::new(&volumes[..volume_count as usize]),
+        collidable,
+    };
+    Ok((entity, sword))
+}
+
+fn finite(value: f32) -> Result<f32, WorldFileError> {
+    if value.is_finite() {
+        Ok(value)
+    } else {
+        Err(WorldFileError::NonFiniteNumber)
+    }
+}
+
+fn resolve_entity_ref(
+    handles: &[EntityHandle],
+    file_index: u32,
+) -> Result<Option<EntityHandle>, WorldFileError> {
+    if file_index == NO_ENTITY {
+        return Ok(None);
+    }
+    match handles.get(file_index as usize) {
+        Some(&handle) => Ok(Some(handle)),
+        None => Err(WorldFileError::InvalidEntityReference(file_index)),
+    }
+}
+
+/// Appends to a buffer in an arena. The first allocation failure sticks and is reported by
+/// `finish`, so the encoding does not have to check every write.
+struct ByteWriter<'a> {
+    arena: &'a mut MemoryArena,
+    bytes: ArenaVec<u8>,
+    section_count: u32,
+    error: Option<AllocError>,
+}
+
+impl<'a> ByteWriter<'a> {
+    fn new(arena: &'a mut MemoryArena) -> ByteWriter<'a> {
+        ByteWriter {
+            arena,
+            bytes: ArenaVec::new(),
+            section_count: 0,
+            error: None,
+        }
+    }
+
+    fn finish(mut self) -> Result<ArenaVec<u8>, AllocError> {
+        let section_count = self.section_count;
+        self.patch(WORLD_FILE_MAGIC.len() + 4, &section_count.to_le_bytes());
+        match self.error {
+            Some(err) => Err(err),
+            None => Ok(self.bytes),
+        }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        if self.error.is_some() {
+            return;
+        }
+        if let Err(err) = self.bytes.try_reserve(self.arena, bytes.len()) {
+            self.error = Some(err);
+            return;
+        }
+        for &byte in bytes {
+            self.bytes.push(self.arena, byte);
+        }
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    fn write_f32(&mut self, value: f32) {
+        self.write_u32(value.to_bits());
+    }
+
+    fn write_v2(&mut self, value: V2) {
+        self.write_f32(value.x);
+        self.write_f32(value.y);
+    }
+
+    fn write_position(&mut self, p: TileMapPosition) {
+        self.write_u32(p.abs_tile_x);
+        self.write_u32(p.abs_tile_y);
+        self.write_u32(p.abs_tile_z);
+        self.write_v2(p.offset);
+    }
+
+    /// Writes the section header with a placeholder size, returns where the contents start.
+    fn begin_section(&mut self, id: u32) -> usize {
+        self.section_count += 1;
+        self.write_u32(id);
+        self.write_u64(0);
+        self.bytes.len()
+    }
+
+    fn end_section(&mut self, contents_start: usize) {
+        let size = (self.bytes.len() - contents_start) as u64;
+        self.patch(contents_start - 8, &size.to_le_bytes());
+    }
+
+    fn begin_record(&mut self) -> usize {
+        self.write_u32(0);
+        self.bytes.len()
+    }
+
+    fn end_record(&mut self, contents_start: usize) {
+        let size = (self.bytes.len() - contents_start) as u32;
+        self.patch(contents_start - 4, &size.to_le_bytes());
+    }
+
+    fn patch(&mut self, at: usize, bytes: &[u8]) {
+        if self.error.is_none() {
+            self.bytes[at..at + bytes.len()].copy_from_slice(bytes);
+        }
+    }
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(bytes: &'a [u8]) -> ByteReader<'a> {
+        ByteReader { bytes, at: 0 }
+    }
+
+    fn remaining(&self) -> usize {
+        self.bytes.len() - self.at
+    }
+
+    fn read_bytes(&mut self, count: usize) -> Result<&'a [u8], WorldFileError> {
+        if count > self.remaining() {
+            return Err(WorldFileError::Truncated);
+        }
+        let bytes = &self.bytes[self.at..self.at + count];
+        self.at += count;
+        Ok(bytes)
+    }
+
+    fn read_array<const N: usize>(&mut self) -> Result<[u8; N], WorldFileError> {
+        let mut result = [0; N];
+        result.copy_from_slice(self.read_bytes(N)?);
+        Ok(result)
+    }
+
+    fn read_u32(&mut self) -> Result<u32, WorldFileError> {
+        Ok(u32::from_le_bytes(self.read_array()?))
+    }
+
+    fn read_u64(&mut self) -> Result<u64, WorldFileError> {
+        Ok(u64::from_le_bytes(self.read_array()?))
+    }
+
+    fn read_f32(&mut self) -> Result<f32, WorldFileError> {
+        finite(f32::from_bits(self.read_u32()?))
+    }
+
+    fn read_v2(&mut self) -> Result<V2, WorldFileError> {
+        let x = self.read_f32()?;
+        let y = self.read_f32()?;
+        Ok(V2::new(x, y))
+    }
+
+    /// Reads a position, which has to be canonical in `tile_map`.
+    fn read_position(&mut self, tile_map: &TileMap) -> Result<TileMapPosition, WorldFileError> {
+        let abs_tile_x = self.read_u32()?;
+        let abs_tile_y = self.read_u32()?;
+        let abs_tile_z = self.read_u32()?;
+        let offset = V2::new(
+            f32::from_bits(self.read_u32()?),
+            f32::from_bits(self.read_u32()?),
+        );
+        let half_tile = 0.5 * tile_map.tile_side_in_meters;
+        // NOTE: Written this way round so NaN offsets fail the test too.
+        if !(offset.x.abs() <= half_tile && offset.y.abs() <= half_tile) {
+            return Err(WorldFileError::InvalidPosition);
+        }
+        Ok(TileMapPosition {
+            abs_tile_x,
+            abs_tile_y,
+            abs_tile_z,
+            offset,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::convert::TryInto;
+    use std::vec::Vec;
+
+    use super::*;
+
+    const HEADER_SIZE: usize = 12;
+    const SECTION_HEADER_SIZE: usize = 12;
+
+    struct TestWorld {
+        tile_map: TileMap,
+        entities: EntityCollection,
+        hero: EntityHandle,
+    }
+
+    fn xy(v: V2) -> (f32, f32) {
+        (v.x, v.y)
+    }
+
+    fn arena_over(buffer: &mut [u64]) -> MemoryArena {
+        MemoryArena::from_raw_parts("test", buffer.as_mut_ptr() as *mut u8, 8 * buffer.len())
+    }
+
+    fn test_world(arena: &mut MemoryArena) -> TestWorld {
+        let mut tile_map = TileMap::new(1.4, 4);
+        for tile_x in 0..20 {
+            tile_map.set_tile_type(arena, tile_x, 3, 0, TileType::Floor);
+        }
+        tile_map.set_tile_type(arena, 19, 3, 0, TileType::StairsUp);
+        tile_map.set_tile_type(arena, 19, 3, 1, TileType::StairsDown);
+        tile_map.set_tile_type(arena, 0, 4, 0, TileType::Wall);
+
+        let mut entities = EntityCollection::new();
+        let mut hero = Entity::hero(TileMapPosition::centered(2, 3, 0));
+        hero.dp = V2::new(1.5, -0.5);
+        let hero = entities.add_entity(arena, hero).unwrap();
+        let sword = Entity::sword(TileMapPosition::centered(3, 3, 0), V2::new(5.0, 0.0));
+        let sword = entities.add_entity(arena, sword).unwrap();
+        entities.get_entity_mut(hero).unwrap().kind = EntityKind::Hero { sword: Some(sword) };
+        entities
+            .add_entity(arena, Entity::monster(TileMapPosition::centered(10, 3, 0)))
+            .unwrap();
+        let wall = Entity {
+            kind: EntityKind::Wall,
+            p: TileMapPosition::centered(0, 4, 0),
+            dp: V2::zero(),
+            facing_direction: 0.0,
+            collision: CollisionVolumeGroup::single(V2::new(1.4, 1.4)),
+            collidable: false,
+        };
+        entities.add_entity(arena, wall).unwrap();
+        TestWorld {
+            tile_map,
+            entities,
+            hero,
+        }
+    }
+
+    fn write_test_world(arena: &mut MemoryArena) -> Vec<u8> {
+        let world = test_world(arena);
+        let mut random_series = RandomSeries::seed(7);
+        random_series.next_u32();
+        let mut camera_p = TileMapPosition::centered(8, 3, 0);
+        camera_p.offset = V2::new(0.25, -0.5);
+        let saved = SavedWorld {
+            tile_map: &world.tile_map,
+            entities: &world.entities,
+            camera_p,
+            hero_start_p: TileMapPosition::centered(2, 3, 0),
+            camera_following_entity: Some(world.hero),
+            player_for_controller: &[None, Some(world.hero), None, None, None],
+            random_series,
+        };
+        let bytes = write_world(arena, &saved).unwrap();
+        bytes.iter().cloned().collect()
+    }
+
+    /// Where the contents of the first section with `id` start.
+    fn section_start(bytes: &[u8], id: u32) -> usize {
+        let mut at = HEADER_SIZE;
+        loop {
+            let section_id = u32::from_le_bytes(bytes[at..at + 4].try_into().unwrap());
+            let size = u64::from_le_bytes(bytes[at + 4..at + 12].try_into().unwrap()) as usize;
+            if section_id == id {
+                return at + SECTION_HEADER_SIZE;
+            }
+            at += SECTION_HEADER_SIZE + size;
+        }
+    }
+
+    fn without_section(bytes: &[u8], id: u32) -> Vec<u8> {
+        let start = section_start(bytes, id) - SECTION_HEADER_SIZE;
+        let size = u64::from_le_bytes(bytes[start + 4..start + 12].try_into().unwrap()) as usize;
+        let mut result = Vec::new();
+        result.extend_from_slice(&bytes[..start]);
+        result.extend_from_slice(&bytes[start + SECTION_HEADER_SIZE + size..]);
+        let section_count = u32::from_le_bytes(bytes[8..12].try_into().unwrap()) - 1;
+        result[8..12].copy_from_slice(&section_count.to_le_bytes());
+        result
+    }
+
+    fn read_error(bytes: &[u8]) -> WorldFileError {
+        let mut buffer = std::vec![0u64; 1 << 13];
+        let mut arena = arena_over(&mut buffer);
+        read_world(bytes, &mut arena).err().unwrap()
+    }
+
+    #[test]
+    fn a_written_world_reads_back_the_same() {
+        let mut buffer = std::vec![0u64; 1 << 13];
+        let mut arena = arena_over(&mut buffer);
+        let bytes = write_test_world(&mut arena);
+        let world = read_world(&bytes, &mut arena).unwrap();
+        let original = test_world(&mut arena);
+
+        assert_eq!(world.tile_map.tile_side_in_meters, 1.4);
+        assert_eq!(world.tile_map.chunk_shift, 4);
+        for abs_tile_z in 0..2 {
+            for abs_tile_y in 0..8 {
+                for abs_tile_x in 0..24 {
+                    assert_eq!(
+                        world
+                            .tile_map
+                            .get_tile_type(abs_tile_x, abs_tile_y, abs_tile_z),
+                        original
+                            .tile_map
+                            .get_tile_type(abs_tile_x, abs_tile_y, abs_tile_z),
+                    );
+                }
+            }
+        }
+
+        let loaded: Vec<_> = world.entities.iter().map(|(_, entity)| entity).collect();
+        let saved: Vec<_> = original.entities.iter().map(|(_, entity)| entity).collect();
+        assert_eq!(loaded.len(), saved.len());
+        for (loaded, saved) in loaded.iter().zip(saved.iter()) {
+            assert!(loaded.p.is_on_same_tile(&saved.p));
+            assert_eq!(xy(loaded.p.offset), xy(saved.p.offset));
+            assert_eq!(xy(loaded.dp), xy(saved.dp));
+            assert_eq!(loaded.facing_direction, saved.facing_direction);
+            assert_eq!(loaded.collidable, saved.collidable);
+            assert_eq!(
+                loaded.collision.volumes().len(),
+                saved.collision.volumes().len()
+            );
+        }
+
+        // NOTE: The hero is the first entity and refers to its sword, the second one.
+        let (hero, _) = world.entities.iter().next().unwrap();
+        let (sword, _) = world.entities.iter().nth(1).unwrap();
+        assert_eq!(
+            world.entities.get_entity(hero).unwrap().kind,
+            EntityKind::Hero { sword: Some(sword) }
+        );
+        assert_eq!(world.camera_following_entity, Some(hero));
+        assert_eq!(
+            world.player_for_controller,
+            [None, Some(hero), None, None, None]
+        );
+        assert!(world
+            .camera_p
+            .is_on_same_tile(&TileMapPosition::centered(8, 3, 0)));
+        assert_eq!(xy(world.camera_p.offset), (0.25, -0.5));
+        assert!(world
+            .hero_start_p
+            .is_on_same_tile(&TileMapPosition::centered(2, 3, 0)));
+
+        let mut expected_series = RandomSeries::seed(7);
+        expected_series.next_u32();
+        assert_eq!(world.random_series.state(), expected_series.state());
+    }
+
+    #[test]
+    fn truncated_worlds_are_rejected() {
+        let mut buffer = std::vec![0u64; 1 << 13];
+        let mut arena = arena_over(&mut buffer);
+        let bytes = write_test_world(&mut arena);
+        for len in 0..bytes.len() {
+            assert_eq!(read_error(&bytes[..len]), WorldFileError::Truncated);
+        }
+    }
+
+    #[test]
+    fn corrupt_worlds_are_rejected() {
+        let mut buffer = std::vec![0u64; 1 << 13];
+        let mut arena = arena_over(&mut buffer);
+        let bytes = write_test_world(&mut arena);
+        let corrupted = |at: usize, value: &[u8]| {
+            let mut bytes = bytes.clone();
+            bytes[at..at + value.len()].copy_from_slice(value);
+            read_error(&bytes)
+        };
+
+        assert_eq!(corrupted(0, b"HHWX"), WorldFileError::BadMagic);
+        assert_eq!(
+            corrupted(4, &2u32.to_le_bytes()),
+            WorldFileError::UnsupportedVersion(2)
+        );
+
+        let tile_map = section_start(&bytes, SECTION_TILE_MAP);
+        for &tile_side in &[f32::NAN, f32::INFINITY, 0.0, -1.4] {
+            assert_eq!(
+                corrupted(tile_map, &tile_side.to_le_bytes()),
+                WorldFileError::InvalidTileSize
+            );
+        }
+        assert_eq!(
+            corrupted(tile_map + 4, &9u32.to_le_bytes()),
+            WorldFileError::InvalidChunkShift(9)
+        );
+        // NOTE: The first chunk starts after the tile side, chunk shift, chunk count and its key.
+        assert_eq!(
+            corrupted(tile_map + 24, &[17]),
+            WorldFileError::InvalidTileType(17)
+        );
+
+        // NOTE: The offset of a position follows its three tile coordinates.
+        let camera = section_start(&bytes, SECTION_CAMERA);
+        for &offset in &[f32::NAN, f32::INFINITY, 0.71] {
+            assert_eq!(
+                corrupted(camera + 12, &offset.to_le_bytes()),
+                WorldFileError::InvalidPosition
+            );
+        }
+
+        // NOTE: The first entity record starts after the entity count and the record size, its
+        // velocity after its kind, the kind data and its position.
+        let hero = section_start(&bytes, SECTION_ENTITIES) + 8;
+        assert_eq!(
+            corrupted(hero, &99u32.to_le_bytes()),
+            WorldFileError::InvalidEntityKind(99)
+        );
+        assert_eq!(
+            corrupted(hero + 4, &50u32.to_le_bytes()),
+            WorldFileError::InvalidEntityReference(50)
+        );
+        assert_eq!(
+            corrupted(hero + 20, &f32::NAN.to_le_bytes()),
+            WorldFileError::InvalidPosition
+        );
+        assert_eq!(
+            corrupted(hero + 28, &f32::NEG_INFINITY.to_le_bytes()),
+            WorldFileError::NonFiniteNumber
+        );
+        assert_eq!(
+            corrupted(hero + 44, &0u32.to_le_bytes()),
+            WorldFileError::InvalidCollisionVolumeCount(0)
+        );
+    }
+
+    #[test]
+    fn worlds_missing_a_section_are_rejected() {
+        let mut buffer = std::vec![0u64; 1 << 13];
+        let mut arena = arena_over(&mut buffer);
+        let bytes = write_test_world(&mut arena);
+        for &section in &[
+            SECTION_TILE_MAP,
+            SECTION_ENTITIES,
+            SECTION_CAMERA,
+            SECTION_PLAYERS,
+            SECTION_HERO_START,
+            SECTION_RANDOM,
+        ] {
+            assert_eq!(
+                read_error(&without_section(&bytes, section)),
+                WorldFileError::MissingSection(section)
+            );
+        }
+    }
+}
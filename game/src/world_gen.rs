@@ -0,0 +1,578 @@
+//! Procedural world generation.
+//!
+//! The world is a grid of cells the size of a screen, stacked on `floor_count` floors. Every room
+//! sits inside a cell of its own. Starting from one room, new rooms are grown into free cells next
+//! to existing ones, either through a corridor to a cell on the same floor or through stairs to
+//! the cell right above or below. Every room is grown out of a room that already exists, so all of
+//! them can be reached from the start room. Walls are put around everything that was carved out
+//! once all rooms are in.
+
+use collections::{ArenaHashMap, ArenaVec};
+use memory::MemoryArena;
+use random::RandomSeries;
+use tile_map::{TileMap, TileMapPosition, TileType};
+
+/// Tiles of a cell, the same as a screen so the camera shows one room at a time.
+pub const CELL_TILES_X: u32 = 17;
+pub const CELL_TILES_Y: u32 = 9;
+
+/// The largest room interior that leaves room for its walls inside a cell.
+pub const MAX_ROOM_WIDTH: u32 = CELL_TILES_X - 2;
+pub const MAX_ROOM_HEIGHT: u32 = CELL_TILES_Y - 2;
+
+/// Times a room tries to find a tile for stairs that is not taken yet.
+const STAIRS_ATTEMPTS: u32 = 8;
+
+pub struct WorldGenParams {
+    pub seed: u32,
+    pub room_count: u32,
+    /// Interior size of the rooms in tiles, walls not included.
+    pub min_room_width: u32,
+    pub max_room_width: u32,
+    pub min_room_height: u32,
+    pub max_room_height: u32,
+    pub floor_count: u32,
+    /// Most rooms that are grown out of a single room.
+    pub branching_factor: u32,
+}
+
+/// The floor of a room, in absolute tiles, both corners included.
+#[derive(Clone, Copy)]
+pub struct Room {
+    pub min_tile_x: u32,
+    pub min_tile_y: u32,
+    pub max_tile_x: u32,
+    pub max_tile_y: u32,
+    pub abs_tile_z: u32,
+}
+
+impl Room {
+    pub fn center(&self) -> TileMapPosition {
+        TileMapPosition::centered(
+            (self.min_tile_x + self.max_tile_x) / 2,
+            (self.min_tile_y + self.max_tile_y) / 2,
+            self.abs_tile_z,
+        )
+    }
+}
+
+pub struct GeneratedWorld {
+    /// Every other room can be reached from it.
+    pub start_room: Room,
+    /// The center of the screen the start room is on.
+    pub camera_p: TileMapPosition,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+struct Cell {
+    x: u32,
+    y: u32,
+    z: u32,
+}
+
+struct GenRoom {
+    cell: Cell,
+    room: Room,
+    child_count: u32,
+}
+
+/// Neighbouring cells a room can grow into.
+#[derive(Clone, Copy)]
+enum Direction {
+    Left,
+    Right,
+    Down,
+    Up,
+    FloorBelow,
+    FloorAbove,
+}
+
+const DIRECTIONS: [Direction; 6] = [
+    Direction::Left,
+    Direction::Right,
+    Direction::Down,
+    Direction::Up,
+    Direction::FloorBelow,
+    Direction::FloorAbove,
+];
+
+struct Generator<'a> {
+    params: &'a WorldGenParams,
+    series: RandomSeries,
+}
+
+impl<'a> Generator<'a> {
+    /// A room of random size placed anywhere inside `cell`.
+    fn place_room(&mut self, cell: Cell) -> Room {
+        let width = self
+            .series
+            .range_u32(self.params.min_room_width, self.params.max_room_width);
+        let height = self
+            .series
+            .range_u32(self.params.min_room_height, self.params.max_room_height);
+        let (lo_x, hi_x, lo_y, hi_y) = cell_interior(cell);
+        let min_tile_x = self.series.range_u32(lo_x, hi_x + 1 - width);
+        let min_tile_y = self.series.range_u32(lo_y, hi_y + 1 - height);
+        Room {
+            min_tile_x,
+            min_tile_y,
+            max_tile_x: min_tile_x + width - 1,
+            max_tile_y: min_tile_y + height - 1,
+            abs_tile_z: cell.z,
+        }
+    }
+
+    /// A room of random size inside `cell` that has `(tile_x, tile_y)` off its edges.
+    fn place_room_around(&mut self, cell: Cell, tile_x: u32, tile_y: u32) -> Room {
+        let width = self
+            .series
+            .range_u32(self.params.min_room_width, self.params.max_room_width);
+        let height = self
+            .series
+            .range_u32(self.params.min_room_height, self.params.max_room_height);
+        let (lo_x, hi_x, lo_y, hi_y) = cell_interior(cell);
+        // NOTE: Rooms are at least 3 tiles wide and the tile is off the edges of the cell
+        // interior, so both ranges are never empty.
+        let min_tile_x = self.series.range_u32(
+            lo_x.max(tile_x + 2 - width),
+            (tile_x - 1).min(hi_x + 1 - width),
+        );
+        let min_tile_y = self.series.range_u32(
+            lo_y.max(tile_y + 2 - height),
+            (tile_y - 1).min(hi_y + 1 - height),
+        );
+        Room {
+            min_tile_x,
+            min_tile_y,
+            max_tile_x: min_tile_x + width - 1,
+            max_tile_y: min_tile_y + height - 1,
+            abs_tile_z: cell.z,
+        }
+    }
+
+    fn random_tile_in(&mut self, room: &Room) -> (u32, u32) {
+        (
+            self.series.range_u32(room.min_tile_x, room.max_tile_x),
+            self.series.range_u32(room.min_tile_y, room.max_tile_y),
+        )
+    }
+
+    /// A floor tile of `room` for stairs, off its edges so the rails of the stairs do not close
+    /// off the room, and away from its center where heroes start.
+    fn find_stairs_tile(&mut self, tile_map: &TileMap, room: &Room) -> Option<(u32, u32)> {
+        let inner = Room {
+            min_tile_x: room.min_tile_x + 1,
+            min_tile_y: room.min_tile_y + 1,
+            max_tile_x: room.max_tile_x - 1,
+            max_tile_y: room.max_tile_y - 1,
+            abs_tile_z: room.abs_tile_z,
+        };
+        let center = room.center();
+        for _ in 0..STAIRS_ATTEMPTS {
+            let (tile_x, tile_y) = self.random_tile_in(&inner);
+            let is_center = tile_x == center.abs_tile_x && tile_y == center.abs_tile_y;
+            let tile_type = tile_map.get_tile_type(tile_x, tile_y, room.abs_tile_z);
+            if !is_center && tile_type == Some(TileType::Floor) {
+                return Some((tile_x, tile_y));
+            }
+        }
+        None
+    }
+}
+
+/// Generates the tiles of a world into `tile_map`. The tile map lives in `arena`, bookkeeping only
+/// needed while generating in `scratch`. There are fewer rooms than `room_count` if they run out
+/// of free cells to grow into.
+pub fn generate_world(
+    params: &WorldGenParams,
+    arena: &mut MemoryArena,
+    scratch: &mut MemoryArena,
+    tile_map: &mut TileMap,
+) -> GeneratedWorld {
+    assert!(params.room_count > 0, "a world needs at least one room");
+    assert!(params.floor_count > 0, "a world needs at least one floor");
+    assert!(
+        params.branching_factor > 0,
+        "rooms need to grow at least one room to reach the room count"
+    );
+    // NOTE: Stairs stay off the edges of rooms, which needs rooms of at least 3 by 3 tiles.
+    assert!(
+        3 <= params.min_room_width
+            && params.min_room_width <= params.max_room_width
+            && params.max_room_width <= MAX_ROOM_WIDTH,
+        "room widths have to be between 3 and {}",
+        MAX_ROOM_WIDTH
+    );
+    assert!(
+        3 <= params.min_room_height
+            && params.min_room_height <= params.max_room_height
+            && params.max_room_height <= MAX_ROOM_HEIGHT,
+        "room heights have to be between 3 and {}",
+        MAX_ROOM_HEIGHT
+    );
+
+    let mut generator = Generator {
+        params,
+        series: RandomSeries::seed(params.seed),
+    };
+
+    // NOTE: No room is more than `room_count` cells away from the start, so starting that far
+    // from zero keeps every cell coordinate from wrapping around.
+    let start_cell = Cell {
+        x: params.room_count,
+        y: params.room_count,
+        z: 0,
+    };
+    let start_room = generator.place_room(start_cell);
+    carve_room(arena, tile_map, &start_room);
+
+    // NOTE: Only the rooms that may still grow are kept around, the others are done with.
+    let mut growing_rooms = ArenaVec::with_capacity(scratch, params.room_count as usize);
+    let mut taken_cells = ArenaHashMap::new();
+    growing_rooms.push(
+        scratch,
+        GenRoom {
+            cell: start_cell,
+            room: start_room,
+            child_count: 0,
+        },
+    );
+    taken_cells.insert(scratch, start_cell, ());
+
+    let mut room_count = 1;
+    while room_count < params.room_count && !growing_rooms.is_empty() {
+        let parent_index = generator
+            .series
+            .range_u32(0, growing_rooms.len() as u32 - 1) as usize;
+
+        let mut directions = DIRECTIONS;
+        generator.series.shuffle(&mut directions);
+        let mut child = None;
+        for &direction in directions.iter() {
+            let parent = &growing_rooms[parent_index];
+            let cell = match neighbour_cell(parent.cell, direction, params.floor_count) {
+                Some(cell) if !taken_cells.contains_key(&cell) => cell,
+                _ => continue,
+            };
+
+            let parent_room = parent.room;
+            let room = match direction {
+                Direction::FloorBelow | Direction::FloorAbove => {
+                    let (tile_x, tile_y) = match generator.find_stairs_tile(tile_map, &parent_room)
+                    {
+                        Some(tile) => tile,
+                        None => continue,
+                    };
+                    let room = generator.place_room_around(cell, tile_x, tile_y);
+                    carve_room(arena, tile_map, &room);
+                    let (parent_stairs, child_stairs) = match direction {
+                        Direction::FloorAbove => (TileType::StairsUp, TileType::StairsDown),
+                        _ => (TileType::StairsDown, TileType::StairsUp),
+                    };
+                    tile_map.set_tile_type(
+                        arena,
+                        tile_x,
+                        tile_y,
+                        parent_room.abs_tile_z,
+                        parent_stairs,
+                    );
+                    tile_map.set_tile_type(arena, tile_x, tile_y, room.abs_tile_z, child_stairs);
+                    room
+                }
+                _ => {
+                    let room = generator.place_room(cell);
+                    carve_room(arena, tile_map, &room);
+                    let from = generator.random_tile_in(&parent_room);
+                    let to = generator.random_tile_in(&room);
+                    carve_corridor(arena, tile_map, from, to, cell.z);
+                    room
+                }
+            };
+            child = Some(GenRoom {
+                cell,
+                room,
+                child_count: 0,
+            });
+            break;
+        }
+
+        match child {
+            Some(child) => {
+                taken_cells.insert(scratch, child.cell, ());
+                growing_rooms.push(scratch, child);
+                room_count += 1;
+                growing_rooms[parent_index].child_count += 1;
+                if growing_rooms[parent_index].child_count >= params.branching_factor {
+                    growing_rooms.remove(parent_index);
+                }
+            }
+            None => {
+                growing_rooms.remove(parent_index);
+            }
+        }
+    }
+
+    build_walls(arena, scratch, tile_map);
+
+    GeneratedWorld {
+        start_room,
+        camera_p: TileMapPosition::centered(
+            start_cell.x * CELL_TILES_X + CELL_TILES_X / 2,
+            start_cell.y * CELL_TILES_Y + CELL_TILES_Y / 2,
+            start_cell.z,
+        ),
+    }
+}
+
+/// The tiles of `cell` that room floors can go on, both corners included.
+fn cell_interior(cell: Cell) -> (u32, u32, u32, u32) {
+    let min_tile_x = cell.x * CELL_TILES_X + 1;
+    let min_tile_y = cell.y * CELL_TILES_Y + 1;
+    (
+        min_tile_x,
+        min_tile_x + MAX_ROOM_WIDTH - 1,
+        min_tile_y,
+        min_tile_y + MAX_ROOM_HEIGHT - 1,
+    )
+}
+
+fn neighbour_cell(cell: Cell, direction: Direction, floor_count: u32) -> Option<Cell> {
+    let Cell { x, y, z } = cell;
+    match direction {
+        Direction::Left => Some(Cell { x: x - 1, y, z }),
+        Direction::Right => Some(Cell { x: x + 1, y, z }),
+        Direction::Down => Some(Cell { x, y: y - 1, z }),
+        Direction::Up => Some(Cell { x, y: y + 1, z }),
+        Direction::FloorBelow if z > 0 => Some(Cell { x, y, z: z - 1 }),
+        Direction::FloorAbove if z + 1 < floor_count => Some(Cell { x, y, z: z + 1 }),
+        _ => None,
+    }
+}
+
+fn carve_room(arena: &mut MemoryArena, tile_map: &mut TileMap, room: &Room) {
+    for abs_tile_y in room.min_tile_y..=room.max_tile_y {
+        for abs_tile_x in room.min_tile_x..=room.max_tile_x {
+            tile_map.set_tile_type(
+                arena,
+                abs_tile_x,
+                abs_tile_y,
+                room.abs_tile_z,
+                TileType::Floor,
+            );
+        }
+    }
+}
+
+/// Carves a path one tile wide from `from` to `to`, first along x, then along y.
+fn carve_corridor(
+    arena: &mut MemoryArena,
+    tile_map: &mut TileMap,
+    from: (u32, u32),
+    to: (u32, u32),
+    abs_tile_z: u32,
+) {
+    let (from_x, from_y) = from;
+    let (to_x, to_y) = to;
+    let row = (from_x.min(to_x)..=from_x.max(to_x)).map(|abs_tile_x| (abs_tile_x, from_y));
+    let column = (from_y.min(to_y)..=from_y.max(to_y)).map(|abs_tile_y| (to_x, abs_tile_y));
+    for (abs_tile_x, abs_tile_y) in row.chain(column) {
+        // NOTE: Corridors run through rooms as well, where they must not cover up stairs.
+        match tile_map.get_tile_type(abs_tile_x, abs_tile_y, abs_tile_z) {
+            None | Some(TileType::Void) => {
+                tile_map.set_tile_type(arena, abs_tile_x, abs_tile_y, abs_tile_z, TileType::Floor)
+            }
+            Some(_) => {}
+        }
+    }
+}
+
+/// Turns every empty tile next to a carved one into a wall, diagonals included.
+fn build_walls(arena: &mut MemoryArena, scratch: &mut MemoryArena, tile_map: &mut TileMap) {
+    let mut walls = ArenaVec::new();
+    let chunk_shift = tile_map.chunk_shift;
+    for (key, chunk) in tile_map.tile_chunks() {
+        for tile_y in 0..chunk.chunk_dim {
+            for tile_x in 0..chunk.chunk_dim {
+                match chunk.get_tile_type(tile_x, tile_y) {
+                    None | Some(&TileType::Void) | Some(&TileType::Wall) => continue,
+                    Some(_) => {}
+                }
+
+                let abs_tile_x = (key.tile_chunk_x << chunk_shift) | tile_x;
+                let abs_tile_y = (key.tile_chunk_y << chunk_shift) | tile_y;
+                for offset_y in 0..3 {
+                    for offset_x in 0..3 {
+                        let x = abs_tile_x.wrapping_add(offset_x).wrapping_sub(1);
+                        let y = abs_tile_y.wrapping_add(offset_y).wrapping_sub(1);
+                        match tile_map.get_tile_type(x, y, key.tile_chunk_z) {
+                            None | Some(TileType::Void) => walls
+                                .push(scratch, TileMapPosition::centered(x, y, key.tile_chunk_z)),
+                            Some(_) => {}
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    // NOTE: Tiles next to several carved tiles show up more than once, which does no harm.
+    for p in walls.iter() {
+        tile_map.set_tile_type(
+            arena,
+            p.abs_tile_x,
+            p.abs_tile_y,
+            p.abs_tile_z,
+            TileType::Wall,
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+    use std::vec::Vec;
+
+    use super::*;
+    use tile_map::TileShape;
+
+    const TEST_PARAMS: WorldGenParams = WorldGenParams {
+        seed: 7,
+        room_count: 40,
+        min_room_width: 3,
+        max_room_width: MAX_ROOM_WIDTH,
+        min_room_height: 3,
+        max_room_height: MAX_ROOM_HEIGHT,
+        floor_count: 3,
+        branching_factor: 3,
+    };
+
+    fn arena_over(buffer: &mut [u64]) -> MemoryArena {
+        MemoryArena::from_raw_parts("test", buffer.as_mut_ptr() as *mut u8, 8 * buffer.len())
+    }
+
+    /// NOTE: The tile map points into `buffer`, which has to outlive it.
+    fn generate(params: &WorldGenParams, buffer: &mut [u64]) -> (TileMap, GeneratedWorld) {
+        let (world_buffer, scratch_buffer) = buffer.split_at_mut(buffer.len() / 2);
+        let mut arena = arena_over(world_buffer);
+        let mut scratch = arena_over(scratch_buffer);
+        let mut tile_map = TileMap::new(1.4, 4);
+        let generated = generate_world(params, &mut arena, &mut scratch, &mut tile_map);
+        (tile_map, generated)
+    }
+
+    /// Every tile that is not void, in absolute tiles.
+    fn tiles(tile_map: &TileMap) -> Vec<(u32, u32, u32, TileType)> {
+        let mut result = Vec::new();
+        for (key, chunk) in tile_map.tile_chunks() {
+            for tile_y in 0..chunk.chunk_dim {
+                for tile_x in 0..chunk.chunk_dim {
+                    match chunk.get_tile_type(tile_x, tile_y) {
+                        None | Some(&TileType::Void) => {}
+                        Some(&tile_type) => result.push((
+                            (key.tile_chunk_x << tile_map.chunk_shift) | tile_x,
+                            (key.tile_chunk_y << tile_map.chunk_shift) | tile_y,
+                            key.tile_chunk_z,
+                            tile_type,
+                        )),
+                    }
+                }
+            }
+        }
+        result.sort_by_key(|&(x, y, z, tile_type)| (z, y, x, tile_type as u8));
+        result
+    }
+
+    fn room_bounds(room: &Room) -> (u32, u32, u32, u32, u32) {
+        (
+            room.min_tile_x,
+            room.min_tile_y,
+            room.max_tile_x,
+            room.max_tile_y,
+            room.abs_tile_z,
+        )
+    }
+
+    #[test]
+    fn the_same_seed_gives_the_same_world() {
+        let mut buffer_a = vec![0u64; 128 * 1024];
+        let mut buffer_b = vec![0u64; 128 * 1024];
+        let (tile_map_a, generated_a) = generate(&TEST_PARAMS, &mut buffer_a);
+        let (tile_map_b, generated_b) = generate(&TEST_PARAMS, &mut buffer_b);
+        assert_eq!(tiles(&tile_map_a), tiles(&tile_map_b));
+        assert_eq!(
+            room_bounds(&generated_a.start_room),
+            room_bounds(&generated_b.start_room)
+        );
+
+        let mut buffer_c = vec![0u64; 128 * 1024];
+        let params = WorldGenParams {
+            seed: TEST_PARAMS.seed + 1,
+            ..TEST_PARAMS
+        };
+        let (tile_map_c, _) = generate(&params, &mut buffer_c);
+        assert_ne!(tiles(&tile_map_a), tiles(&tile_map_c));
+    }
+
+    #[test]
+    fn everything_can_be_reached_from_the_start_room() {
+        let mut buffer = vec![0u64; 128 * 1024];
+        let (tile_map, generated) = generate(&TEST_PARAMS, &mut buffer);
+        let tile_type_at =
+            |(x, y, z): (u32, u32, u32)| tile_map.get_tile_type(x, y, z).unwrap_or(TileType::Void);
+
+        // NOTE: Walks the tiles the way movers can, stairs are only entered from the top or the
+        // bottom and take whoever steps onto them to the other end.
+        let start = generated.start_room.center();
+        let start = (start.abs_tile_x, start.abs_tile_y, start.abs_tile_z);
+        let mut reached = HashSet::new();
+        let mut open = vec![start];
+        reached.insert(start);
+        while let Some((x, y, z)) = open.pop() {
+            let properties = tile_type_at((x, y, z)).properties();
+            let mut next = vec![(x, y - 1, z), (x, y + 1, z)];
+            if properties.shape != TileShape::SideRails {
+                next.push((x - 1, y, z));
+                next.push((x + 1, y, z));
+            }
+            if properties.level_change != 0 {
+                next.push((x, y, z.wrapping_add(properties.level_change as u32)));
+            }
+            for p in next {
+                let shape = tile_type_at(p).properties().shape;
+                let enters_from_side = p.1 == y && p.2 == z;
+                let blocked = match shape {
+                    TileShape::Solid => true,
+                    TileShape::SideRails => enters_from_side,
+                    TileShape::Open => false,
+                };
+                if !blocked && reached.insert(p) {
+                    open.push(p);
+                }
+            }
+        }
+
+        let tiles = tiles(&tile_map);
+        let floor_count = tiles
+            .iter()
+            .filter(|&&(_, _, _, tile_type)| tile_type == TileType::Floor)
+            .count();
+        assert!(floor_count >= TEST_PARAMS.room_count as usize * 9);
+        for &(x, y, z, tile_type) in tiles.iter() {
+            match tile_type {
+                TileType::Floor => assert!(reached.contains(&(x, y, z))),
+                TileType::StairsUp | TileType::StairsDown => {
+                    assert!(reached.contains(&(x, y, z)));
+                    let level_change = tile_type.properties().level_change;
+                    let other_end = (x, y, z.wrapping_add(level_change as u32));
+                    let other_end_type = match tile_type {
+                        TileType::StairsUp => TileType::StairsDown,
+                        _ => TileType::StairsUp,
+                    };
+                    assert_eq!(tile_type_at(other_end), other_end_type);
+                    assert!(reached.contains(&other_end));
+                }
+                TileType::Wall | TileType::Void => {}
+            }
+        }
+    }
+}
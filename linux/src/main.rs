@@ -0,0 +1,882 @@
+extern crate handmade_platform;
+extern crate libc;
+extern crate software_renderer;
+
+use std::collections::VecDeque;
+use std::env;
+use std::ffi::{CStr, CString, OsStr};
+use std::fs::{self, File};
+use std::io::{self, BufWriter, Seek, SeekFrom, Write};
+use std::os::raw::{c_char, c_int, c_void};
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::ptr::{self, null_mut};
+use std::slice;
+use std::sync::{Condvar, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use handmade_platform::replay::*;
+use handmade_platform::*;
+use software_renderer::{encode_png, png_encode_scratch_size, RenderBuffer};
+
+fn kilobytes(value: usize) -> usize {
+    value * 1024
+}
+
+fn megabytes(value: usize) -> usize {
+    kilobytes(value) * 1024
+}
+
+fn gigabytes(value: usize) -> usize {
+    megabytes(value) * 1024
+}
+
+fn terabytes(value: usize) -> usize {
+    gigabytes(value) * 1024
+}
+
+extern "C" fn debug_platform_free_file_memory(memory: *mut c_void) {
+    if !memory.is_null() {
+        unsafe { libc::free(memory) };
+    }
+}
+
+extern "C" fn debug_platform_read_entire_file(file_name: *const c_char) -> DebugReadFileResult {
+    let mut result = DebugReadFileResult {
+        content_size: 0,
+        contents: null_mut(),
+    };
+
+    let file_name = unsafe { CStr::from_ptr(file_name) };
+    if let Ok(contents) = fs::read(OsStr::from_bytes(file_name.to_bytes())) {
+        if !contents.is_empty() && contents.len() <= u32::MAX as usize {
+            unsafe {
+                let memory = libc::malloc(contents.len());
+                if !memory.is_null() {
+                    ptr::copy_nonoverlapping(contents.as_ptr(), memory as *mut u8, contents.len());
+                    result.contents = memory;
+                    result.content_size = contents.len() as u32;
+                }
+            }
+        }
+    }
+
+    result
+}
+
+extern "C" fn debug_platform_write_entire_file(
+    file_name: *const c_char,
+    memory_size: u32,
+    memory: *const c_void,
+) -> i32 {
+    let file_name = unsafe { CStr::from_ptr(file_name) };
+    let contents = unsafe { std::slice::from_raw_parts(memory as *const u8, memory_size as usize) };
+    fs::write(OsStr::from_bytes(file_name.to_bytes()), contents).is_ok() as i32
+}
+
+struct LinuxWorkQueueEntry {
+    callback: PlatformWorkQueueCallback,
+    // NOTE: Kept as an address so entries can be handed to the worker threads.
+    data: usize,
+}
+
+struct LinuxWorkQueueState {
+    entries: VecDeque<LinuxWorkQueueEntry>,
+    /// Entries that were added but have not finished yet, including the running ones.
+    pending_count: usize,
+}
+
+struct LinuxWorkQueue {
+    state: Mutex<LinuxWorkQueueState>,
+    work_available: Condvar,
+    work_done: Condvar,
+}
+
+impl LinuxWorkQueue {
+    fn as_platform_queue(&'static self) -> *mut PlatformWorkQueue {
+        self as *const LinuxWorkQueue as *mut PlatformWorkQueue
+    }
+
+    fn run_entry(&'static self, entry: LinuxWorkQueueEntry) {
+        (entry.callback)(self.as_platform_queue(), entry.data as *mut c_void);
+
+        let mut state = self.state.lock().unwrap();
+        state.pending_count -= 1;
+        if state.pending_count == 0 {
+            self.work_done.notify_all();
+        }
+    }
+}
+
+fn linux_make_work_queue(thread_count: usize) -> &'static LinuxWorkQueue {
+    // NOTE: The queue lives as long as the process, the game memory keeps pointers to it.
+    let queue: &'static LinuxWorkQueue = Box::leak(Box::new(LinuxWorkQueue {
+        state: Mutex::new(LinuxWorkQueueState {
+            entries: VecDeque::new(),
+            pending_count: 0,
+        }),
+        work_available: Condvar::new(),
+        work_done: Condvar::new(),
+    }));
+
+    for _ in 0..thread_count {
+        thread::spawn(move || loop {
+            let entry = {
+                let mut state = queue.state.lock().unwrap();
+                loop {
+                    match state.entries.pop_front() {
+                        Some(entry) => break entry,
+                        None => state = queue.work_available.wait(state).unwrap(),
+                    }
+                }
+            };
+            queue.run_entry(entry);
+        });
+    }
+
+    queue
+}
+
+extern "C" fn linux_add_entry(
+    queue: *mut PlatformWorkQueue,
+    callback: PlatformWorkQueueCallback,
+    data: *mut c_void,
+) {
+    let queue = unsafe { &*(queue as *const LinuxWorkQueue) };
+    let mut state = queue.state.lock().unwrap();
+    state.entries.push_back(LinuxWorkQueueEntry {
+        callback,
+        data: data as usize,
+    });
+    state.pending_count += 1;
+    queue.work_available.notify_one();
+}
+
+extern "C" fn linux_complete_all_work(queue: *mut PlatformWorkQueue) {
+    let queue: &'static LinuxWorkQueue = unsafe { &*(queue as *const LinuxWorkQueue) };
+    loop {
+        // NOTE: The calling thread works through the queue too instead of just waiting.
+        let entry = queue.state.lock().unwrap().entries.pop_front();
+        match entry {
+            Some(entry) => queue.run_entry(entry),
+            None => break,
+        }
+    }
+
+    let mut state = queue.state.lock().unwrap();
+    while state.pending_count > 0 {
+        state = queue.work_done.wait(state).unwrap();
+    }
+}
+
+/// Addresses of the files the game has open. Restoring game memory that was never initialized
+/// throws away the game state that refers to them, so they are closed along with it.
+static OPEN_FILES: Mutex<Vec<usize>> = Mutex::new(Vec::new());
+
+extern "C" fn linux_open_file(file_name: *const c_char) -> *mut PlatformFile {
+    let file_name = unsafe { CStr::from_ptr(file_name) };
+    match File::open(OsStr::from_bytes(file_name.to_bytes())) {
+        Ok(file) => {
+            let file = Box::into_raw(Box::new(file));
+            OPEN_FILES.lock().unwrap().push(file as usize);
+            file as *mut PlatformFile
+        }
+        Err(_) => null_mut(),
+    }
+}
+
+extern "C" fn linux_read_data_from_file(
+    file: *mut PlatformFile,
+    offset: u64,
+    size: u64,
+    dest: *mut c_void,
+) -> c_int {
+    let file = unsafe { &*(file as *const File) };
+    let dest = unsafe { slice::from_raw_parts_mut(dest as *mut u8, size as usize) };
+    file.read_exact_at(dest, offset).is_ok() as c_int
+}
+
+extern "C" fn linux_close_file(file: *mut PlatformFile) {
+    if !file.is_null() {
+        OPEN_FILES
+            .lock()
+            .unwrap()
+            .retain(|&open_file| open_file != file as usize);
+        drop(unsafe { Box::from_raw(file as *mut File) });
+    }
+}
+
+fn linux_close_all_files() {
+    for file in OPEN_FILES.lock().unwrap().drain(..) {
+        drop(unsafe { Box::from_raw(file as *mut File) });
+    }
+}
+
+struct LinuxGameCode {
+    library: *mut c_void,
+    library_last_write_time: Option<SystemTime>,
+    game_update_and_render: Option<GameUpdateAndRender>,
+    game_get_sound_samples: Option<GameGetSoundSamples>,
+}
+
+fn linux_get_last_write_time(file_name: &Path) -> Option<SystemTime> {
+    fs::metadata(file_name)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+unsafe fn linux_load_symbol(library: *mut c_void, name: &str) -> *mut c_void {
+    let name = CString::new(name).unwrap();
+    libc::dlsym(library, name.as_ptr())
+}
+
+fn linux_dl_error() -> String {
+    unsafe {
+        let error = libc::dlerror();
+        if error.is_null() {
+            String::from("unknown error")
+        } else {
+            CStr::from_ptr(error).to_string_lossy().into_owned()
+        }
+    }
+}
+
+// NOTE: The library is loaded from a copy so the build can overwrite the original while the
+// game is running. Every load uses a fresh name, because the dynamic loader hands back the
+// already loaded object for a name it has seen before if the old copy could not be unloaded.
+fn linux_load_game_code(
+    source_library_path: &Path,
+    temp_library_path: &Path,
+) -> Result<LinuxGameCode, String> {
+    let library_last_write_time = linux_get_last_write_time(source_library_path);
+    fs::copy(source_library_path, temp_library_path).map_err(|err| {
+        format!(
+            "failed to copy {} to {}: {}",
+            source_library_path.display(),
+            temp_library_path.display(),
+            err
+        )
+    })?;
+
+    let temp_library_path_c = CString::new(temp_library_path.as_os_str().as_bytes()).unwrap();
+    let result = unsafe {
+        let library = libc::dlopen(
+            temp_library_path_c.as_ptr(),
+            libc::RTLD_NOW | libc::RTLD_LOCAL,
+        );
+        if library.is_null() {
+            Err(format!(
+                "failed to load {}: {}",
+                source_library_path.display(),
+                linux_dl_error()
+            ))
+        } else {
+            let game_update_and_render = linux_load_symbol(library, "game_update_and_render");
+            let game_get_sound_samples = linux_load_symbol(library, "game_get_sound_samples");
+            if game_update_and_render.is_null() || game_get_sound_samples.is_null() {
+                libc::dlclose(library);
+                Err(format!(
+                    "{} does not export the game entry points",
+                    source_library_path.display()
+                ))
+            } else {
+                Ok(LinuxGameCode {
+                    library,
+                    library_last_write_time,
+                    game_update_and_render: Some(std::mem::transmute::<
+                        *mut c_void,
+                        GameUpdateAndRender,
+                    >(game_update_and_render)),
+                    game_get_sound_samples: Some(std::mem::transmute::<
+                        *mut c_void,
+                        GameGetSoundSamples,
+                    >(game_get_sound_samples)),
+                })
+            }
+        }
+    };
+
+    // NOTE: The loader keeps its own mapping of the library, so the copy is not needed anymore.
+    let _ = fs::remove_file(temp_library_path);
+
+    result
+}
+
+fn linux_unload_game_code(game_code: &mut LinuxGameCode) {
+    if !game_code.library.is_null() {
+        unsafe { libc::dlclose(game_code.library) };
+        game_code.library = null_mut();
+    }
+
+    game_code.game_update_and_render = None;
+    game_code.game_get_sound_samples = None;
+}
+
+fn linux_temp_game_code_path(load_index: u32) -> PathBuf {
+    env::temp_dir().join(format!(
+        "libhandmade_temp_{}_{}.so",
+        process::id(),
+        load_index
+    ))
+}
+
+unsafe fn linux_allocate_memory(base_address: usize, size: usize) -> *mut c_void {
+    let memory = libc::mmap(
+        base_address as *mut c_void,
+        size,
+        libc::PROT_READ | libc::PROT_WRITE,
+        libc::MAP_PRIVATE | libc::MAP_ANONYMOUS | libc::MAP_NORESERVE,
+        -1,
+        0,
+    );
+    if memory == libc::MAP_FAILED {
+        null_mut()
+    } else {
+        memory
+    }
+}
+
+struct LinuxOffscreenBuffer {
+    memory: Vec<u32>,
+    width: c_int,
+    height: c_int,
+    pitch: c_int,
+    bytes_per_pixel: c_int,
+}
+
+impl LinuxOffscreenBuffer {
+    fn new(width: c_int, height: c_int) -> LinuxOffscreenBuffer {
+        let bytes_per_pixel = 4;
+        LinuxOffscreenBuffer {
+            memory: vec![0; (width * height) as usize],
+            width,
+            height,
+            pitch: width * bytes_per_pixel,
+            bytes_per_pixel,
+        }
+    }
+
+    fn render_buffer(&mut self) -> RenderBuffer<'_> {
+        RenderBuffer {
+            bytes: unsafe {
+                slice::from_raw_parts_mut(
+                    self.memory.as_mut_ptr() as *mut u8,
+                    self.memory.len() * 4,
+                )
+            },
+            width: self.width as usize,
+            height: self.height as usize,
+            pitch: self.pitch as usize,
+            bytes_per_pixel: self.bytes_per_pixel as usize,
+        }
+    }
+
+    fn as_game_offscreen_buffer(&mut self) -> GameOffscreenBuffer {
+        GameOffscreenBuffer {
+            memory: self.memory.as_mut_ptr() as *mut c_void,
+            width: self.width,
+            height: self.height,
+            pitch: self.pitch,
+            bytes_per_pixel: self.bytes_per_pixel,
+        }
+    }
+}
+
+fn linux_write_frame(buffer: &mut LinuxOffscreenBuffer, path: &Path) -> io::Result<()> {
+    let render_buffer = buffer.render_buffer();
+    let mut scratch = vec![0; png_encode_scratch_size(render_buffer.width, render_buffer.height)];
+    let mut file = BufWriter::new(File::create(path)?);
+    let mut result = Ok(());
+    encode_png(&render_buffer, &mut scratch, |bytes| {
+        if result.is_ok() {
+            result = file.write_all(bytes);
+        }
+    })
+    .map_err(|err| io::Error::new(io::ErrorKind::InvalidInput, format!("{:?}", err)))?;
+    result?;
+    file.flush()
+}
+
+fn linux_process_keyboard_message(new_state: &mut GameButtonState, is_down: bool) {
+    let is_down = is_down as c_int;
+    if new_state.ended_down != is_down {
+        new_state.ended_down = is_down;
+        new_state.half_transition_count += 1;
+    }
+}
+
+// NOTE: There is no keyboard on a headless machine, so the keyboard controller is driven by a
+// fixed script: press start to spawn a player, then walk right, up, left and down in turn.
+fn linux_synthesize_keyboard_input(
+    frame_index: u32,
+    keyboard_controller: &mut GameControllerInput,
+) {
+    let frames_per_direction = 60;
+    let walking = frame_index >= 2;
+    let direction = (frame_index / frames_per_direction) % 4;
+
+    linux_process_keyboard_message(&mut keyboard_controller.start, frame_index == 1);
+    linux_process_keyboard_message(
+        &mut keyboard_controller.move_right,
+        walking && direction == 0,
+    );
+    linux_process_keyboard_message(&mut keyboard_controller.move_up, walking && direction == 1);
+    linux_process_keyboard_message(
+        &mut keyboard_controller.move_left,
+        walking && direction == 2,
+    );
+    linux_process_keyboard_message(
+        &mut keyboard_controller.move_down,
+        walking && direction == 3,
+    );
+}
+
+struct LinuxInputRecording {
+    file: BufWriter<File>,
+    header: ReplayHeader,
+}
+
+fn linux_begin_input_recording(
+    file_name: &Path,
+    game_memory: &GameMemory,
+) -> io::Result<LinuxInputRecording> {
+    let header = ReplayHeader::new(
+        game_memory.is_initialized != 0,
+        game_memory.permanent_storage as u64,
+        game_memory.permanent_storage_size as u64,
+        game_memory.transient_storage_size as u64,
+    );
+    let mut header_bytes = [0; REPLAY_HEADER_SIZE];
+    header.write(&mut header_bytes);
+
+    let mut file = BufWriter::new(File::create(file_name)?);
+    file.write_all(&header_bytes)?;
+    file.write_all(unsafe {
+        std::slice::from_raw_parts(
+            game_memory.permanent_storage as *const u8,
+            game_memory.permanent_storage_size,
+        )
+    })?;
+
+    Ok(LinuxInputRecording { file, header })
+}
+
+fn linux_record_input(
+    recording: &mut LinuxInputRecording,
+    new_input: &GameInput,
+) -> io::Result<()> {
+    let mut input_bytes = [0; ENCODED_GAME_INPUT_SIZE];
+    encode_game_input(new_input, &mut input_bytes);
+    recording.file.write_all(&input_bytes)?;
+    recording.header.frame_count += 1;
+    Ok(())
+}
+
+fn linux_end_input_recording(recording: LinuxInputRecording) -> io::Result<()> {
+    let mut header_bytes = [0; REPLAY_HEADER_SIZE];
+    recording.header.write(&mut header_bytes);
+
+    let mut file = recording
+        .file
+        .into_inner()
+        .map_err(|err| err.into_error())?;
+    file.seek(SeekFrom::Start(0))?;
+    file.write_all(&header_bytes)
+}
+
+struct LinuxInputPlayback {
+    header: ReplayHeader,
+    snapshot: Vec<u8>,
+    frames: Vec<GameInput>,
+    frame_index: usize,
+}
+
+fn linux_load_input_playback(file_name: &Path) -> Result<LinuxInputPlayback, String> {
+    let contents = fs::read(file_name)
+        .map_err(|err| format!("failed to read {}: {}", file_name.display(), err))?;
+    let header = ReplayHeader::read(&contents).map_err(|err| {
+        format!(
+            "{} is not a valid recording: {:?}",
+            file_name.display(),
+            err
+        )
+    })?;
+
+    let frame_count = header
+        .playable_frame_count(contents.len() as u64)
+        .map_err(|_| format!("{} is truncated", file_name.display()))?;
+    let frames_offset = header.frames_offset() as usize;
+    let frames: Vec<GameInput> = contents[frames_offset..]
+        .chunks_exact(ENCODED_GAME_INPUT_SIZE)
+        .take(frame_count as usize)
+        .map(|bytes| {
+            let mut input_bytes = [0; ENCODED_GAME_INPUT_SIZE];
+            input_bytes.copy_from_slice(bytes);
+            decode_game_input(&input_bytes)
+        })
+        .collect();
+    if frames.is_empty() {
+        return Err(format!(
+            "{} does not contain any frames",
+            file_name.display()
+        ));
+    }
+
+    Ok(LinuxInputPlayback {
+        header,
+        snapshot: contents[REPLAY_HEADER_SIZE..frames_offset].to_vec(),
+        frames,
+        frame_index: 0,
+    })
+}
+
+fn linux_begin_input_playback(playback: &mut LinuxInputPlayback, game_memory: &mut GameMemory) {
+    // NOTE: Work that is still running would write into the memory that is about to be restored.
+    linux_complete_all_work(game_memory.work_queue);
+
+    playback.frame_index = 0;
+    game_memory.is_initialized = playback.header.memory_is_initialized as c_int;
+    if game_memory.is_initialized == 0 {
+        linux_close_all_files();
+    }
+    unsafe {
+        ptr::copy_nonoverlapping(
+            playback.snapshot.as_ptr(),
+            game_memory.permanent_storage as *mut u8,
+            playback.snapshot.len(),
+        );
+    }
+}
+
+fn linux_playback_input(
+    playback: &mut LinuxInputPlayback,
+    game_memory: &mut GameMemory,
+    new_input: &mut GameInput,
+) {
+    if playback.frame_index == playback.frames.len() {
+        linux_begin_input_playback(playback, game_memory);
+    }
+
+    *new_input = playback.frames[playback.frame_index];
+    playback.frame_index += 1;
+}
+
+struct LinuxOptions {
+    game_code_path: PathBuf,
+    data_path: Option<PathBuf>,
+    output_path: Option<PathBuf>,
+    recording_path: Option<PathBuf>,
+    playback_path: Option<PathBuf>,
+    frame_count: u32,
+    dump_every: u32,
+    realtime: bool,
+    width: c_int,
+    height: c_int,
+}
+
+const USAGE: &str = "usage: linux_handmade [options]
+
+options:
+    --game <path>       game library to load (default: libhandmade.so next to the executable)
+    --data <dir>        directory the game reads its files from (default: current directory)
+    --out <dir>         directory to dump frames to (default: no frames are dumped)
+    --record <file>     record the game memory and every frame of input to a file
+    --playback <file>   loop the input of a recording instead of the built-in input script
+    --frames <count>    number of frames to run, 0 runs until interrupted (default: 300)
+    --dump-every <n>    dump every n-th frame (default: 1)
+    --realtime          pace frames to the game update rate instead of running flat out
+    --size <w>x<h>      size of the offscreen buffer (default: 960x540)";
+
+fn linux_parse_options() -> Result<LinuxOptions, String> {
+    let exe_path = env::current_exe().map_err(|err| err.to_string())?;
+    let mut options = LinuxOptions {
+        game_code_path: exe_path.with_file_name("libhandmade.so"),
+        data_path: None,
+        output_path: None,
+        recording_path: None,
+        playback_path: None,
+        frame_count: 300,
+        dump_every: 1,
+        realtime: false,
+        width: 960,
+        height: 540,
+    };
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        let mut value = || {
+            args.next()
+                .ok_or_else(|| format!("missing value for {}", arg))
+        };
+        match arg.as_str() {
+            "--game" => options.game_code_path = PathBuf::from(value()?),
+            "--data" => options.data_path = Some(PathBuf::from(value()?)),
+            "--out" => options.output_path = Some(PathBuf::from(value()?)),
+            "--record" => options.recording_path = Some(PathBuf::from(value()?)),
+            "--playback" => options.playback_path = Some(PathBuf::from(value()?)),
+            "--frames" => {
+                options.frame_count = value()?
+                    .parse()
+                    .map_err(|_| String::from("--frames expects a number"))?
+            }
+            "--dump-every" => {
+                options.dump_every = value()?
+                    .parse()
+                    .map_err(|_| String::from("--dump-every expects a number"))?;
+                if options.dump_every == 0 {
+                    return Err(String::from("--dump-every must be at least 1"));
+                }
+            }
+            "--realtime" => options.realtime = true,
+            "--size" => {
+                let size = value()?;
+                let mut parts = size.split('x').map(|part| part.parse::<c_int>());
+                match (parts.next(), parts.next(), parts.next()) {
+                    (Some(Ok(width)), Some(Ok(height)), None) if width > 0 && height > 0 => {
+                        options.width = width;
+                        options.height = height;
+                    }
+                    _ => return Err(format!("invalid size {}", size)),
+                }
+            }
+            "--help" | "-h" => {
+                println!("{}", USAGE);
+                process::exit(0);
+            }
+            _ => return Err(format!("unknown option {}", arg)),
+        }
+    }
+
+    if options.recording_path.is_some() && options.playback_path.is_some() {
+        return Err(String::from(
+            "--record and --playback cannot be used together",
+        ));
+    }
+
+    Ok(options)
+}
+
+fn main() {
+    let options = match linux_parse_options() {
+        Ok(options) => options,
+        Err(err) => {
+            eprintln!("{}\n\n{}", err, USAGE);
+            process::exit(2);
+        }
+    };
+
+    let source_game_code_path = fs::canonicalize(&options.game_code_path).unwrap_or_else(|err| {
+        eprintln!(
+            "failed to find {}: {}",
+            options.game_code_path.display(),
+            err
+        );
+        process::exit(1);
+    });
+    let mut game_code_load_index = 0;
+    let mut game = match linux_load_game_code(
+        &source_game_code_path,
+        &linux_temp_game_code_path(game_code_load_index),
+    ) {
+        Ok(game) => game,
+        Err(err) => {
+            eprintln!("{}", err);
+            process::exit(1);
+        }
+    };
+    let mut failed_library_write_time = None;
+
+    let mut playback = options.playback_path.as_ref().map(|path| {
+        linux_load_input_playback(path).unwrap_or_else(|err| {
+            eprintln!("{}", err);
+            process::exit(1);
+        })
+    });
+
+    // NOTE: Resolve the output directory before switching to the data directory, so relative
+    // paths on the command line mean what the user expects.
+    let output_path = options.output_path.as_ref().map(|path| {
+        fs::create_dir_all(path).unwrap_or_else(|err| {
+            eprintln!("failed to create {}: {}", path.display(), err);
+            process::exit(1);
+        });
+        fs::canonicalize(path).unwrap()
+    });
+
+    let game_update_hz = 30.0;
+    let target_seconds_per_frame = 1.0 / game_update_hz;
+    let samples_per_second = 48000;
+
+    // NOTE: A recording snapshots the permanent storage including the pointers into it, so it
+    // has to be played back with the game memory at the address it was recorded at.
+    let (base_address, permanent_storage_size, transient_storage_size) = match playback {
+        Some(ref playback) => (
+            playback.header.permanent_storage_base as usize,
+            playback.header.permanent_storage_size as usize,
+            playback.header.transient_storage_size as usize,
+        ),
+        // NOTE: Every build maps the game memory at the same address, so a recording made by one
+        // can be played back by the other.
+        None => (terabytes(2), megabytes(64), gigabytes(1)),
+    };
+    let total_size = permanent_storage_size + transient_storage_size;
+    let game_memory_block = unsafe { linux_allocate_memory(base_address, total_size) };
+    if game_memory_block.is_null() {
+        eprintln!("failed to allocate {} bytes of game memory", total_size);
+        process::exit(1);
+    }
+    if playback.is_some() && game_memory_block as usize != base_address {
+        eprintln!(
+            "failed to map the game memory at {:#x} for playback",
+            base_address
+        );
+        process::exit(1);
+    }
+
+    // NOTE: One core is left to the main thread.
+    let work_queue_thread_count =
+        thread::available_parallelism().map_or(1, |count| count.get() - 1);
+    let work_queue = linux_make_work_queue(work_queue_thread_count.max(1));
+
+    let mut game_memory = GameMemory {
+        is_initialized: 0,
+        permanent_storage_size,
+        permanent_storage: game_memory_block,
+        transient_storage_size,
+        transient_storage: unsafe { (game_memory_block as *mut u8).add(permanent_storage_size) }
+            as *mut c_void,
+        debug_platform_read_entire_file,
+        debug_platform_free_file_memory,
+        debug_platform_write_entire_file,
+        work_queue: work_queue.as_platform_queue(),
+        platform_add_entry: Some(linux_add_entry),
+        platform_complete_all_work: Some(linux_complete_all_work),
+        platform_open_file: Some(linux_open_file),
+        platform_read_data_from_file: Some(linux_read_data_from_file),
+        platform_close_file: Some(linux_close_file),
+    };
+
+    let mut recording = options.recording_path.as_ref().map(|path| {
+        linux_begin_input_recording(path, &game_memory).unwrap_or_else(|err| {
+            eprintln!("failed to record to {}: {}", path.display(), err);
+            process::exit(1);
+        })
+    });
+    if let Some(ref mut playback) = playback {
+        linux_begin_input_playback(playback, &mut game_memory);
+    }
+
+    if let Some(ref data_path) = options.data_path {
+        if let Err(err) = env::set_current_dir(data_path) {
+            eprintln!("failed to enter {}: {}", data_path.display(), err);
+            process::exit(1);
+        }
+    }
+
+    let mut back_buffer = LinuxOffscreenBuffer::new(options.width, options.height);
+    let sample_count = (samples_per_second as f32 / game_update_hz) as u32;
+    let mut samples = vec![0i16; 2 * sample_count as usize];
+
+    let mut input = [GameInput::default(), GameInput::default()];
+    let (mut new_index, mut old_index) = (0, 1);
+
+    let mut last_counter = Instant::now();
+    let mut frame_index = 0;
+    while options.frame_count == 0 || frame_index < options.frame_count {
+        // NOTE: The new library is loaded before the old one is dropped, so a half written
+        // library from a build that is still running leaves the current code in place.
+        let new_library_write_time = linux_get_last_write_time(&source_game_code_path);
+        if new_library_write_time != game.library_last_write_time
+            && new_library_write_time != failed_library_write_time
+        {
+            game_code_load_index += 1;
+            match linux_load_game_code(
+                &source_game_code_path,
+                &linux_temp_game_code_path(game_code_load_index),
+            ) {
+                Ok(new_game) => {
+                    // NOTE: Queued work calls into the old library.
+                    linux_complete_all_work(game_memory.work_queue);
+                    linux_unload_game_code(&mut game);
+                    game = new_game;
+                    failed_library_write_time = None;
+                }
+                Err(err) => {
+                    eprintln!("{}", err);
+                    failed_library_write_time = new_library_write_time;
+                }
+            }
+        }
+
+        let old_keyboard_controller = input[old_index].controllers[0];
+        let new_input = &mut input[new_index];
+        new_input.dt = target_seconds_per_frame;
+
+        let new_keyboard_controller = &mut new_input.controllers[0];
+        *new_keyboard_controller = old_keyboard_controller;
+        new_keyboard_controller.is_connected = 1;
+        for button in new_keyboard_controller.buttons_mut().iter_mut() {
+            button.half_transition_count = 0;
+        }
+        linux_synthesize_keyboard_input(frame_index, new_keyboard_controller);
+
+        if let Some(ref mut recording) = recording {
+            if let Err(err) = linux_record_input(recording, new_input) {
+                eprintln!("failed to record input: {}", err);
+                process::exit(1);
+            }
+        }
+        if let Some(ref mut playback) = playback {
+            linux_playback_input(playback, &mut game_memory, new_input);
+        }
+
+        let mut offscreen_buffer = back_buffer.as_game_offscreen_buffer();
+        if let Some(game_update_and_render) = game.game_update_and_render {
+            unsafe { game_update_and_render(&mut game_memory, new_input, &mut offscreen_buffer) };
+        }
+
+        let mut sound_buffer = GameSoundBuffer {
+            samples: samples.as_mut_ptr() as *mut c_void,
+            sample_count,
+            samples_per_second,
+        };
+        if let Some(game_get_sound_samples) = game.game_get_sound_samples {
+            unsafe { game_get_sound_samples(&mut game_memory, &mut sound_buffer) };
+        }
+
+        if let Some(ref output_path) = output_path {
+            if frame_index % options.dump_every == 0 {
+                let frame_path = output_path.join(format!("frame_{:05}.png", frame_index));
+                if let Err(err) = linux_write_frame(&mut back_buffer, &frame_path) {
+                    eprintln!("failed to write {}: {}", frame_path.display(), err);
+                    process::exit(1);
+                }
+            }
+        }
+
+        if options.realtime {
+            let target_frame_duration = Duration::from_secs_f32(target_seconds_per_frame);
+            let frame_duration = last_counter.elapsed();
+            if frame_duration < target_frame_duration {
+                thread::sleep(target_frame_duration - frame_duration);
+            }
+        }
+        last_counter = Instant::now();
+
+        std::mem::swap(&mut new_index, &mut old_index);
+        frame_index += 1;
+    }
+
+    if let Some(recording) = recording {
+        if let Err(err) = linux_end_input_recording(recording) {
+            eprintln!("failed to finish recording: {}", err);
+            process::exit(1);
+        }
+    }
+
+    linux_complete_all_work(game_memory.work_queue);
+    linux_unload_game_code(&mut game);
+}
@@ -0,0 +1,3 @@
+@echo off
+
+cargo run -p asset_packer -- --manifest data\manifest.txt --data build\win32 --out build\win32\test.hha
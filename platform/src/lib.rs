@@ -0,0 +1,169 @@
+#![no_std]
+
+extern crate libc;
+
+use core::ffi::c_void;
+use libc::{c_char, c_int};
+
+pub mod replay;
+
+#[repr(C)]
+pub struct DebugReadFileResult {
+    pub content_size: u32,
+    pub contents: *mut c_void,
+}
+
+pub type DebugPlatformReadEntireFile =
+    extern "C" fn(file_name: *const c_char) -> DebugReadFileResult;
+pub type DebugPlatformFreeFileMemory = extern "C" fn(memory: *mut c_void);
+pub type DebugPlatformWriteEntireFile =
+    extern "C" fn(file_name: *const c_char, memory_size: u32, memory: *const c_void) -> i32;
+
+/// Queue of work the platform runs in the background. The game only ever sees pointers to it.
+pub enum PlatformWorkQueue {}
+
+pub type PlatformWorkQueueCallback =
+    extern "C" fn(queue: *mut PlatformWorkQueue, data: *mut c_void);
+pub type PlatformAddEntry = extern "C" fn(
+    queue: *mut PlatformWorkQueue,
+    callback: PlatformWorkQueueCallback,
+    data: *mut c_void,
+);
+/// Returns once every entry added to the queue so far has finished.
+pub type PlatformCompleteAllWork = extern "C" fn(queue: *mut PlatformWorkQueue);
+
+/// File opened for reading by the platform, safe to read from several threads at once.
+pub enum PlatformFile {}
+
+/// Returns null if the file could not be opened.
+pub type PlatformOpenFile = extern "C" fn(file_name: *const c_char) -> *mut PlatformFile;
+/// Reads exactly `size` bytes at `offset` into `dest`, returns 0 if that was not possible.
+pub type PlatformReadDataFromFile =
+    extern "C" fn(file: *mut PlatformFile, offset: u64, size: u64, dest: *mut c_void) -> c_int;
+pub type PlatformCloseFile = extern "C" fn(file: *mut PlatformFile);
+
+#[repr(C)]
+pub struct GameMemory {
+    pub is_initialized: c_int,
+    pub permanent_storage_size: usize,
+    pub permanent_storage: *mut c_void,
+    pub transient_storage_size: usize,
+    pub transient_storage: *mut c_void,
+
+    pub debug_platform_read_entire_file: DebugPlatformReadEntireFile,
+    pub debug_platform_free_file_memory: DebugPlatformFreeFileMemory,
+    pub debug_platform_write_entire_file: DebugPlatformWriteEntireFile,
+
+    // NOTE: A platform may leave everything below null. The game then does its background work
+    // right away and reads files through `debug_platform_read_entire_file`.
+    pub work_queue: *mut PlatformWorkQueue,
+    pub platform_add_entry: Option<PlatformAddEntry>,
+    pub platform_complete_all_work: Option<PlatformCompleteAllWork>,
+
+    pub platform_open_file: Option<PlatformOpenFile>,
+    pub platform_read_data_from_file: Option<PlatformReadDataFromFile>,
+    pub platform_close_file: Option<PlatformCloseFile>,
+}
+
+#[repr(C)]
+pub struct GameOffscreenBuffer {
+    pub memory: *mut c_void,
+    pub width: c_int,
+    pub height: c_int,
+    pub pitch: c_int,
+    pub bytes_per_pixel: c_int,
+}
+
+#[repr(C)]
+pub struct GameSoundBuffer {
+    pub samples: *mut c_void,
+    pub sample_count: u32,
+    pub samples_per_second: u32,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct GameButtonState {
+    pub half_transition_count: c_int,
+    pub ended_down: c_int,
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct GameControllerInput {
+    pub is_connected: c_int,
+    pub is_analog: c_int,
+    pub stick_average_x: f32,
+    pub stick_average_y: f32,
+
+    pub move_up: GameButtonState,
+    pub move_down: GameButtonState,
+    pub move_left: GameButtonState,
+    pub move_right: GameButtonState,
+
+    pub action_up: GameButtonState,
+    pub action_down: GameButtonState,
+    pub action_left: GameButtonState,
+    pub action_right: GameButtonState,
+
+    pub left_shoulder: GameButtonState,
+    pub right_shoulder: GameButtonState,
+
+    pub back: GameButtonState,
+    pub start: GameButtonState,
+}
+
+impl GameControllerInput {
+    pub fn buttons(&self) -> [&GameButtonState; 12] {
+        [
+            &self.move_up,
+            &self.move_down,
+            &self.move_left,
+            &self.move_right,
+            &self.action_up,
+            &self.action_down,
+            &self.action_left,
+            &self.action_right,
+            &self.left_shoulder,
+            &self.right_shoulder,
+            &self.back,
+            &self.start,
+        ]
+    }
+
+    pub fn buttons_mut(&mut self) -> [&mut GameButtonState; 12] {
+        [
+            &mut self.move_up,
+            &mut self.move_down,
+            &mut self.move_left,
+            &mut self.move_right,
+            &mut self.action_up,
+            &mut self.action_down,
+            &mut self.action_left,
+            &mut self.action_right,
+            &mut self.left_shoulder,
+            &mut self.right_shoulder,
+            &mut self.back,
+            &mut self.start,
+        ]
+    }
+}
+
+#[repr(C)]
+#[derive(Copy, Clone, Default)]
+pub struct GameInput {
+    pub mouse_buttons: [GameButtonState; 5],
+    pub mouse_x: i32,
+    pub mouse_y: i32,
+    pub mouse_z: i32,
+    pub dt: f32,
+    pub controllers: [GameControllerInput; 4],
+}
+
+pub type GameUpdateAndRender = unsafe extern "C" fn(
+    memory: *mut GameMemory,
+    input: *const GameInput,
+    offscreen_buffer: *mut GameOffscreenBuffer,
+);
+pub type GameGetSoundSamples =
+    unsafe extern "C" fn(memory: *mut GameMemory, sound_buffer: *mut GameSoundBuffer);
@@ -0,0 +1,255 @@
+//! Input recording format shared by all platform hosts.
+//!
+//! A recording starts with a fixed size header, followed by a snapshot of the permanent storage
+//! taken when the recording began, followed by one encoded `GameInput` per frame. Everything is
+//! little endian and encoded field by field, so a recording does not depend on the layout rules
+//! of the machine that captured it.
+//!
+//! The snapshot contains absolute pointers into the permanent storage, so a host replaying it has
+//! to map the game memory at `permanent_storage_base` again.
+
+use {GameButtonState, GameControllerInput, GameInput};
+
+pub const REPLAY_MAGIC: [u8; 4] = *b"HMIR";
+pub const REPLAY_VERSION: u32 = 1;
+
+/// Bump whenever a field is added to, removed from or reordered in `GameInput`.
+pub const GAME_INPUT_LAYOUT_VERSION: u32 = 1;
+
+pub const REPLAY_HEADER_SIZE: usize = 48;
+
+const ENCODED_BUTTON_SIZE: usize = 8;
+const ENCODED_CONTROLLER_SIZE: usize = 16 + 12 * ENCODED_BUTTON_SIZE;
+pub const ENCODED_GAME_INPUT_SIZE: usize =
+    5 * ENCODED_BUTTON_SIZE + 16 + 4 * ENCODED_CONTROLLER_SIZE;
+
+#[derive(Debug, PartialEq)]
+pub enum ReplayError {
+    BadMagic,
+    UnsupportedVersion(u32),
+    InputLayoutMismatch(u32),
+    Truncated,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct ReplayHeader {
+    pub input_layout_version: u32,
+    pub memory_is_initialized: bool,
+    pub permanent_storage_base: u64,
+    pub permanent_storage_size: u64,
+    pub transient_storage_size: u64,
+    /// Zero while a recording is still being written.
+    pub frame_count: u32,
+}
+
+impl ReplayHeader {
+    pub fn new(
+        memory_is_initialized: bool,
+        permanent_storage_base: u64,
+        permanent_storage_size: u64,
+        transient_storage_size: u64,
+    ) -> ReplayHeader {
+        ReplayHeader {
+            input_layout_version: GAME_INPUT_LAYOUT_VERSION,
+            memory_is_initialized,
+            permanent_storage_base,
+            permanent_storage_size,
+            transient_storage_size,
+            frame_count: 0,
+        }
+    }
+
+    pub fn write(&self, bytes: &mut [u8; REPLAY_HEADER_SIZE]) {
+        let mut writer = ByteWriter::new(bytes);
+        writer.write_bytes(&REPLAY_MAGIC);
+        writer.write_u32(REPLAY_VERSION);
+        writer.write_u32(self.input_layout_version);
+        writer.write_u32(ENCODED_GAME_INPUT_SIZE as u32);
+        writer.write_u32(self.memory_is_initialized as u32);
+        writer.write_u64(self.permanent_storage_base);
+        writer.write_u64(self.permanent_storage_size);
+        writer.write_u64(self.transient_storage_size);
+        writer.write_u32(self.frame_count);
+    }
+
+    pub fn read(bytes: &[u8]) -> Result<ReplayHeader, ReplayError> {
+        if bytes.len() < REPLAY_HEADER_SIZE {
+            return Err(ReplayError::Truncated);
+        }
+
+        let mut reader = ByteReader::new(bytes);
+        if reader.read_bytes(4) != REPLAY_MAGIC {
+            return Err(ReplayError::BadMagic);
+        }
+
+        let version = reader.read_u32();
+        if version != REPLAY_VERSION {
+            return Err(ReplayError::UnsupportedVersion(version));
+        }
+
+        let input_layout_version = reader.read_u32();
+        let input_size = reader.read_u32();
+        if input_layout_version != GAME_INPUT_LAYOUT_VERSION
+            || input_size as usize != ENCODED_GAME_INPUT_SIZE
+        {
+            return Err(ReplayError::InputLayoutMismatch(input_layout_version));
+        }
+
+        Ok(ReplayHeader {
+            input_layout_version,
+            memory_is_initialized: reader.read_u32() != 0,
+            permanent_storage_base: reader.read_u64(),
+            permanent_storage_size: reader.read_u64(),
+            transient_storage_size: reader.read_u64(),
+            frame_count: reader.read_u32(),
+        })
+    }
+
+    /// Offset of the first encoded frame from the start of the recording.
+    pub fn frames_offset(&self) -> u64 {
+        REPLAY_HEADER_SIZE as u64 + self.permanent_storage_size
+    }
+
+    /// Number of frames a recording of `recording_size` bytes with this header plays back.
+    pub fn playable_frame_count(&self, recording_size: u64) -> Result<u32, ReplayError> {
+        let frames_size = recording_size
+            .checked_sub(self.frames_offset())
+            .ok_or(ReplayError::Truncated)?;
+        let complete_frames = frames_size / ENCODED_GAME_INPUT_SIZE as u64;
+        // NOTE: A recording that was not closed properly has no frame count, in that case every
+        // complete frame in the file is played back.
+        if self.frame_count == 0 {
+            Ok(complete_frames.min(u32::MAX as u64) as u32)
+        } else if complete_frames < self.frame_count as u64 {
+            Err(ReplayError::Truncated)
+        } else {
+            Ok(self.frame_count)
+        }
+    }
+}
+
+pub fn encode_game_input(input: &GameInput, bytes: &mut [u8; ENCODED_GAME_INPUT_SIZE]) {
+    let mut writer = ByteWriter::new(bytes);
+    for button in input.mouse_buttons.iter() {
+        writer.write_button(button);
+    }
+    writer.write_i32(input.mouse_x);
+    writer.write_i32(input.mouse_y);
+    writer.write_i32(input.mouse_z);
+    writer.write_f32(input.dt);
+    for controller in input.controllers.iter() {
+        writer.write_i32(controller.is_connected);
+        writer.write_i32(controller.is_analog);
+        writer.write_f32(controller.stick_average_x);
+        writer.write_f32(controller.stick_average_y);
+        for button in controller.buttons().iter() {
+            writer.write_button(button);
+        }
+    }
+}
+
+pub fn decode_game_input(bytes: &[u8; ENCODED_GAME_INPUT_SIZE]) -> GameInput {
+    let mut reader = ByteReader::new(bytes);
+    let mut input = GameInput::default();
+    for button in input.mouse_buttons.iter_mut() {
+        *button = reader.read_button();
+    }
+    input.mouse_x = reader.read_i32();
+    input.mouse_y = reader.read_i32();
+    input.mouse_z = reader.read_i32();
+    input.dt = reader.read_f32();
+    for controller in input.controllers.iter_mut() {
+        *controller = GameControllerInput::default();
+        controller.is_connected = reader.read_i32();
+        controller.is_analog = reader.read_i32();
+        controller.stick_average_x = reader.read_f32();
+        controller.stick_average_y = reader.read_f32();
+        for button in controller.buttons_mut().iter_mut() {
+            **button = reader.read_button();
+        }
+    }
+    input
+}
+
+struct ByteWriter<'a> {
+    bytes: &'a mut [u8],
+    at: usize,
+}
+
+impl<'a> ByteWriter<'a> {
+    fn new(bytes: &'a mut [u8]) -> ByteWriter<'a> {
+        ByteWriter { bytes, at: 0 }
+    }
+
+    fn write_bytes(&mut self, bytes: &[u8]) {
+        self.bytes[self.at..self.at + bytes.len()].copy_from_slice(bytes);
+        self.at += bytes.len();
+    }
+
+    fn write_u32(&mut self, value: u32) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    fn write_u64(&mut self, value: u64) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    fn write_i32(&mut self, value: i32) {
+        self.write_bytes(&value.to_le_bytes());
+    }
+
+    fn write_f32(&mut self, value: f32) {
+        self.write_bytes(&value.to_bits().to_le_bytes());
+    }
+
+    fn write_button(&mut self, button: &GameButtonState) {
+        self.write_i32(button.half_transition_count);
+        self.write_i32(button.ended_down);
+    }
+}
+
+struct ByteReader<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> ByteReader<'a> {
+    fn new(bytes: &'a [u8]) -> ByteReader<'a> {
+        ByteReader { bytes, at: 0 }
+    }
+
+    fn read_bytes(&mut self, count: usize) -> &'a [u8] {
+        let bytes = &self.bytes[self.at..self.at + count];
+        self.at += count;
+        bytes
+    }
+
+    fn read_array<const N: usize>(&mut self) -> [u8; N] {
+        let mut result = [0; N];
+        result.copy_from_slice(self.read_bytes(N));
+        result
+    }
+
+    fn read_u32(&mut self) -> u32 {
+        u32::from_le_bytes(self.read_array())
+    }
+
+    fn read_u64(&mut self) -> u64 {
+        u64::from_le_bytes(self.read_array())
+    }
+
+    fn read_i32(&mut self) -> i32 {
+        i32::from_le_bytes(self.read_array())
+    }
+
+    fn read_f32(&mut self) -> f32 {
+        f32::from_bits(self.read_u32())
+    }
+
+    fn read_button(&mut self) -> GameButtonState {
+        GameButtonState {
+            half_transition_count: self.read_i32(),
+            ended_down: self.read_i32(),
+        }
+    }
+}
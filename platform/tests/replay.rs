@@ -0,0 +1,175 @@
+extern crate handmade_platform;
+
+use handmade_platform::replay::*;
+use handmade_platform::{GameButtonState, GameInput};
+
+fn header() -> ReplayHeader {
+    let mut header = ReplayHeader::new(true, 0x7f00_0000_0000, 64 * 1024 * 1024, 1 << 30);
+    header.frame_count = 3;
+    header
+}
+
+fn header_bytes(header: &ReplayHeader) -> [u8; REPLAY_HEADER_SIZE] {
+    let mut bytes = [0; REPLAY_HEADER_SIZE];
+    header.write(&mut bytes);
+    bytes
+}
+
+fn button(half_transition_count: i32, ended_down: i32) -> GameButtonState {
+    GameButtonState {
+        half_transition_count,
+        ended_down,
+    }
+}
+
+#[test]
+fn header_round_trip() {
+    let header = header();
+    let read = ReplayHeader::read(&header_bytes(&header)).unwrap();
+    assert_eq!(read.input_layout_version, GAME_INPUT_LAYOUT_VERSION);
+    assert!(read.memory_is_initialized);
+    assert_eq!(read.permanent_storage_base, header.permanent_storage_base);
+    assert_eq!(read.permanent_storage_size, header.permanent_storage_size);
+    assert_eq!(read.transient_storage_size, header.transient_storage_size);
+    assert_eq!(read.frame_count, 3);
+    assert_eq!(
+        read.frames_offset(),
+        REPLAY_HEADER_SIZE as u64 + header.permanent_storage_size
+    );
+}
+
+#[test]
+fn game_input_round_trip() {
+    let mut input = GameInput::default();
+    input.mouse_buttons[2] = button(1, 1);
+    input.mouse_x = -12;
+    input.mouse_y = 480;
+    input.mouse_z = 3;
+    input.dt = 1.0 / 30.0;
+    for (controller_index, controller) in input.controllers.iter_mut().enumerate() {
+        controller.is_connected = 1;
+        controller.is_analog = controller_index as i32 % 2;
+        controller.stick_average_x = -0.5 * controller_index as f32;
+        controller.stick_average_y = 0.25;
+        for (button_index, state) in controller.buttons_mut().iter_mut().enumerate() {
+            **state = button(
+                button_index as i32,
+                (button_index + controller_index) as i32 % 2,
+            );
+        }
+    }
+
+    let mut bytes = [0; ENCODED_GAME_INPUT_SIZE];
+    encode_game_input(&input, &mut bytes);
+    let decoded = decode_game_input(&bytes);
+
+    for (decoded, input) in decoded.mouse_buttons.iter().zip(input.mouse_buttons.iter()) {
+        assert_eq!(decoded.half_transition_count, input.half_transition_count);
+        assert_eq!(decoded.ended_down, input.ended_down);
+    }
+    assert_eq!(
+        (decoded.mouse_x, decoded.mouse_y, decoded.mouse_z),
+        (-12, 480, 3)
+    );
+    assert_eq!(decoded.dt, input.dt);
+    for (decoded, input) in decoded.controllers.iter().zip(input.controllers.iter()) {
+        assert_eq!(decoded.is_connected, input.is_connected);
+        assert_eq!(decoded.is_analog, input.is_analog);
+        assert_eq!(decoded.stick_average_x, input.stick_average_x);
+        assert_eq!(decoded.stick_average_y, input.stick_average_y);
+        for (decoded, input) in decoded.buttons().iter().zip(input.buttons().iter()) {
+            assert_eq!(decoded.half_transition_count, input.half_transition_count);
+            assert_eq!(decoded.ended_down, input.ended_down);
+        }
+    }
+}
+
+#[test]
+fn bad_magic_is_rejected() {
+    let mut bytes = header_bytes(&header());
+    bytes[0..4].copy_from_slice(b"HMIX");
+    assert_eq!(
+        ReplayHeader::read(&bytes).err(),
+        Some(ReplayError::BadMagic)
+    );
+}
+
+#[test]
+fn unsupported_version_is_rejected() {
+    let mut bytes = header_bytes(&header());
+    bytes[4..8].copy_from_slice(&(REPLAY_VERSION + 1).to_le_bytes());
+    assert_eq!(
+        ReplayHeader::read(&bytes).err(),
+        Some(ReplayError::UnsupportedVersion(REPLAY_VERSION + 1))
+    );
+}
+
+#[test]
+fn truncated_header_is_rejected() {
+    let bytes = header_bytes(&header());
+    for len in 0..REPLAY_HEADER_SIZE {
+        assert_eq!(
+            ReplayHeader::read(&bytes[..len]).err(),
+            Some(ReplayError::Truncated)
+        );
+    }
+}
+
+#[test]
+fn input_layout_mismatch_is_rejected() {
+    let mut mismatched = header();
+    mismatched.input_layout_version = GAME_INPUT_LAYOUT_VERSION + 1;
+    assert_eq!(
+        ReplayHeader::read(&header_bytes(&mismatched)).err(),
+        Some(ReplayError::InputLayoutMismatch(
+            GAME_INPUT_LAYOUT_VERSION + 1
+        ))
+    );
+
+    // NOTE: The encoded input size follows the input layout version.
+    let mut bytes = header_bytes(&header());
+    bytes[12..16].copy_from_slice(&(ENCODED_GAME_INPUT_SIZE as u32 + 4).to_le_bytes());
+    assert_eq!(
+        ReplayHeader::read(&bytes).err(),
+        Some(ReplayError::InputLayoutMismatch(GAME_INPUT_LAYOUT_VERSION))
+    );
+}
+
+#[test]
+fn truncated_frames_are_rejected() {
+    let header = header();
+    let frames_offset = header.frames_offset();
+    let frame_size = ENCODED_GAME_INPUT_SIZE as u64;
+
+    assert_eq!(
+        header.playable_frame_count(frames_offset + 3 * frame_size),
+        Ok(3)
+    );
+    // NOTE: Anything after the counted frames is ignored.
+    assert_eq!(
+        header.playable_frame_count(frames_offset + 4 * frame_size + 1),
+        Ok(3)
+    );
+    assert_eq!(
+        header.playable_frame_count(frames_offset + 3 * frame_size - 1),
+        Err(ReplayError::Truncated)
+    );
+    assert_eq!(
+        header.playable_frame_count(frames_offset - 1),
+        Err(ReplayError::Truncated)
+    );
+}
+
+#[test]
+fn unfinished_recordings_play_every_complete_frame() {
+    let mut header = header();
+    header.frame_count = 0;
+    let frames_offset = header.frames_offset();
+    let frame_size = ENCODED_GAME_INPUT_SIZE as u64;
+
+    assert_eq!(header.playable_frame_count(frames_offset), Ok(0));
+    assert_eq!(
+        header.playable_frame_count(frames_offset + 2 * frame_size + frame_size / 2),
+        Ok(2)
+    );
+}
@@ -0,0 +1,203 @@
+use RenderBuffer;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PixelFormat {
+    /// 0xAARRGGBB with straight alpha.
+    Argb8888,
+    /// 0xXXRRGGBB, the top byte is ignored and the pixels are drawn opaque.
+    Xrgb8888,
+}
+
+enum Pixels<'a> {
+    Owned(Vec<u32>),
+    Borrowed(&'a mut [u32]),
+}
+
+/// A top-down bitmap whose pixels are either owned or borrowed, e.g. from a memory arena.
+///
+/// `pitch` is the distance between two rows in pixels, so a bitmap can live inside a larger
+/// block of pixels.
+pub struct Bitmap<'a> {
+    pixels: Pixels<'a>,
+    width: usize,
+    height: usize,
+    pitch: usize,
+    format: PixelFormat,
+}
+
+impl Bitmap<'static> {
+    /// Creates a cleared bitmap, e.g. to use as a render target.
+    pub fn new(width: usize, height: usize, format: PixelFormat) -> Bitmap<'static> {
+        Bitmap {
+            pixels: Pixels::Owned(vec![0; width * height]),
+            width,
+            height,
+            pitch: width,
+            format,
+        }
+    }
+}
+
+impl<'a> Bitmap<'a> {
+    pub fn from_pixels(
+        pixels: &'a mut [u32],
+        width: usize,
+        height: usize,
+        pitch: usize,
+        format: PixelFormat,
+    ) -> Bitmap<'a> {
+        assert!(pitch >= width);
+        assert!(height == 0 || pixels.len() >= (height - 1) * pitch + width);
+        Bitmap {
+            pixels: Pixels::Borrowed(pixels),
+            width,
+            height,
+            pitch,
+            format,
+        }
+    }
+
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn pitch(&self) -> usize {
+        self.pitch
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    pub fn pixels(&self) -> &[u32] {
+        match self.pixels {
+            Pixels::Owned(ref pixels) => pixels,
+            Pixels::Borrowed(ref pixels) => pixels,
+        }
+    }
+
+    pub fn pixels_mut(&mut self) -> &mut [u32] {
+        match self.pixels {
+            Pixels::Owned(ref mut pixels) => pixels,
+            Pixels::Borrowed(ref mut pixels) => pixels,
+        }
+    }
+
+    pub fn view(&self) -> BitmapView<'_> {
+        BitmapView {
+            pixels: self.pixels(),
+            width: self.width,
+            height: self.height,
+            pitch: self.pitch,
+            format: self.format,
+        }
+    }
+
+    pub fn sub_view(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Option<BitmapView<'_>> {
+        self.view().sub_view(x, y, width, height)
+    }
+
+    pub fn rows_mut(&mut self) -> impl Iterator<Item = &mut [u32]> {
+        let width = self.width;
+        let height = self.height;
+        let pitch = self.pitch;
+        self.pixels_mut()
+            .chunks_mut(pitch)
+            .take(height)
+            .map(move |row| &mut row[..width])
+    }
+
+    /// Lets the bitmap be drawn into with the regular drawing functions.
+    pub fn render_buffer(&mut self) -> RenderBuffer<'_> {
+        let width = self.width;
+        let height = self.height;
+        let pitch = self.pitch;
+        let pixels = self.pixels_mut();
+        RenderBuffer {
+            bytes: unsafe {
+                core::slice::from_raw_parts_mut(pixels.as_mut_ptr() as *mut u8, pixels.len() * 4)
+            },
+            width,
+            height,
+            pitch: pitch * 4,
+            bytes_per_pixel: 4,
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+pub struct BitmapView<'a> {
+    pixels: &'a [u32],
+    width: usize,
+    height: usize,
+    pitch: usize,
+    format: PixelFormat,
+}
+
+impl<'a> BitmapView<'a> {
+    pub fn width(&self) -> usize {
+        self.width
+    }
+
+    pub fn height(&self) -> usize {
+        self.height
+    }
+
+    pub fn format(&self) -> PixelFormat {
+        self.format
+    }
+
+    pub fn pixel(&self, x: usize, y: usize) -> Option<u32> {
+        if x >= self.width || y >= self.height {
+            return None;
+        }
+        Some(self.pixels[y * self.pitch + x])
+    }
+
+    pub fn rows(&self) -> impl Iterator<Item = &'a [u32]> {
+        let width = self.width;
+        self.pixels
+            .chunks(self.pitch)
+            .take(self.height)
+            .map(move |row| &row[..width])
+    }
+
+    /// Returns the `width` x `height` rectangle starting at `x`, `y`, or `None` if it does not
+    /// fit inside this view.
+    pub fn sub_view(
+        &self,
+        x: usize,
+        y: usize,
+        width: usize,
+        height: usize,
+    ) -> Option<BitmapView<'a>> {
+        if x.checked_add(width).is_none_or(|end| end > self.width)
+            || y.checked_add(height).is_none_or(|end| end > self.height)
+        {
+            return None;
+        }
+
+        let start = if width == 0 || height == 0 {
+            0
+        } else {
+            y * self.pitch + x
+        };
+        Some(BitmapView {
+            pixels: &self.pixels[start..],
+            width,
+            height,
+            pitch: self.pitch,
+            format: self.format,
+        })
+    }
+}
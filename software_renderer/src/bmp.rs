@@ -0,0 +1,271 @@
+//! Decoder for the subset of BMP files the game uses.
+//!
+//! Supported are uncompressed (BI_RGB) 24 and 32 bit images and BI_BITFIELDS 16 and 32 bit
+//! images with or without an alpha mask, stored either bottom-up or top-down. Decoding is split
+//! into `read_bmp_info` and `decode_bmp`, so the caller can allocate the pixels wherever it
+//! wants, e.g. in a memory arena.
+
+use PixelFormat;
+
+const FILE_HEADER_SIZE: usize = 14;
+const INFO_HEADER_SIZE: usize = 40;
+const BI_RGB: u32 = 0;
+const BI_BITFIELDS: u32 = 3;
+
+// NOTE: Anything bigger is almost certainly a corrupt header.
+const MAX_DIMENSION: u32 = 1 << 15;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum BmpError {
+    /// The file ends before the headers or the pixel data it describes.
+    Truncated,
+    BadSignature,
+    /// Only BITMAPINFOHEADER and its later versions are supported, not BITMAPCOREHEADER.
+    UnsupportedHeader(u32),
+    UnsupportedCompression(u32),
+    UnsupportedBitCount(u16),
+    InvalidDimensions,
+    /// A color mask is empty, overlaps another mask or is not contiguous.
+    InvalidMask,
+    /// The pixel data starts inside the headers or ends after the end of the file.
+    InvalidPixelOffset,
+    /// The buffer passed to `decode_bmp` is smaller than `width * height`.
+    BufferTooSmall,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct BmpInfo {
+    pub width: usize,
+    pub height: usize,
+    pub format: PixelFormat,
+    is_top_down: bool,
+    bytes_per_pixel: usize,
+    stride: usize,
+    pixel_offset: usize,
+    masks: Option<ChannelMasks>,
+}
+
+impl BmpInfo {
+    pub fn pixel_count(&self) -> usize {
+        self.width * self.height
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+struct ChannelMasks {
+    red: Channel,
+    green: Channel,
+    blue: Channel,
+    alpha: Option<Channel>,
+}
+
+/// One color channel of a BI_BITFIELDS image.
+#[derive(Copy, Clone, Debug)]
+struct Channel {
+    shift: u32,
+    bits: u32,
+}
+
+impl Channel {
+    fn from_mask(mask: u32) -> Result<Option<Channel>, BmpError> {
+        if mask == 0 {
+            return Ok(None);
+        }
+
+        let shift = mask.trailing_zeros();
+        let bits = (mask >> shift).trailing_ones();
+        if (mask >> shift) >> bits != 0 {
+            return Err(BmpError::InvalidMask);
+        }
+        Ok(Some(Channel { shift, bits }))
+    }
+
+    fn extract(&self, value: u32) -> u32 {
+        let max = ((1u64 << self.bits) - 1) as u32;
+        let channel = (value >> self.shift) & max;
+        if self.bits >= 8 {
+            channel >> (self.bits - 8)
+        } else {
+            (channel * 255 + max / 2) / max
+        }
+    }
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> u16 {
+    u16::from_le_bytes([bytes[at], bytes[at + 1]])
+}
+
+fn read_u32(bytes: &[u8], at: usize) -> u32 {
+    u32::from_le_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+pub fn read_bmp_info(bytes: &[u8]) -> Result<BmpInfo, BmpError> {
+    if bytes.len() < FILE_HEADER_SIZE + 4 {
+        return Err(BmpError::Truncated);
+    }
+    if &bytes[0..2] != b"BM" {
+        return Err(BmpError::BadSignature);
+    }
+
+    let pixel_offset = read_u32(bytes, 10) as usize;
+    let info = &bytes[FILE_HEADER_SIZE..];
+    let info_size = read_u32(info, 0);
+    // NOTE: Sizes between the plain header and version 2 do not exist.
+    if (info_size as usize) < INFO_HEADER_SIZE || (info_size > 40 && info_size < 52) {
+        return Err(BmpError::UnsupportedHeader(info_size));
+    }
+    if info.len() < info_size as usize {
+        return Err(BmpError::Truncated);
+    }
+
+    let width = read_u32(info, 4) as i32;
+    let height = read_u32(info, 8) as i32;
+    let planes = read_u16(info, 12);
+    let bits_per_pixel = read_u16(info, 14);
+    let compression = read_u32(info, 16);
+
+    if width <= 0
+        || height == 0
+        || width.unsigned_abs() > MAX_DIMENSION
+        || height.unsigned_abs() > MAX_DIMENSION
+        || planes != 1
+    {
+        return Err(BmpError::InvalidDimensions);
+    }
+
+    let mut headers_end = FILE_HEADER_SIZE + info_size as usize;
+    let (format, masks) = match compression {
+        BI_RGB => match bits_per_pixel {
+            24 | 32 => (PixelFormat::Xrgb8888, None),
+            _ => return Err(BmpError::UnsupportedBitCount(bits_per_pixel)),
+        },
+        BI_BITFIELDS => {
+            if bits_per_pixel != 16 && bits_per_pixel != 32 {
+                return Err(BmpError::UnsupportedBitCount(bits_per_pixel));
+            }
+
+            // NOTE: A plain BITMAPINFOHEADER is followed by the three color masks, the later
+            // header versions contain them, starting with the alpha mask in version 3.
+            let masks = if info_size as usize == INFO_HEADER_SIZE {
+                headers_end += 12;
+                if info.len() < INFO_HEADER_SIZE + 12 {
+                    return Err(BmpError::Truncated);
+                }
+                [
+                    read_u32(info, 40),
+                    read_u32(info, 44),
+                    read_u32(info, 48),
+                    0,
+                ]
+            } else {
+                let alpha_mask = if info_size >= 56 {
+                    read_u32(info, 52)
+                } else {
+                    0
+                };
+                [
+                    read_u32(info, 40),
+                    read_u32(info, 44),
+                    read_u32(info, 48),
+                    alpha_mask,
+                ]
+            };
+
+            let [red_mask, green_mask, blue_mask, alpha_mask] = masks;
+            if red_mask & green_mask != 0
+                || red_mask & blue_mask != 0
+                || green_mask & blue_mask != 0
+                || alpha_mask & (red_mask | green_mask | blue_mask) != 0
+            {
+                return Err(BmpError::InvalidMask);
+            }
+
+            let red = Channel::from_mask(red_mask)?.ok_or(BmpError::InvalidMask)?;
+            let green = Channel::from_mask(green_mask)?.ok_or(BmpError::InvalidMask)?;
+            let blue = Channel::from_mask(blue_mask)?.ok_or(BmpError::InvalidMask)?;
+            let alpha = Channel::from_mask(alpha_mask)?;
+            let format = if alpha.is_some() {
+                PixelFormat::Argb8888
+            } else {
+                PixelFormat::Xrgb8888
+            };
+            let masks = ChannelMasks {
+                red,
+                green,
+                blue,
+                alpha,
+            };
+            (format, Some(masks))
+        }
+        _ => return Err(BmpError::UnsupportedCompression(compression)),
+    };
+
+    let is_top_down = height < 0;
+    let width = width as usize;
+    let height = height.unsigned_abs() as usize;
+    let bytes_per_pixel = bits_per_pixel as usize / 8;
+    let stride = (width * bytes_per_pixel + 3) & !3;
+    let pixel_end = stride
+        .checked_mul(height)
+        .and_then(|size| size.checked_add(pixel_offset));
+    match pixel_end {
+        Some(pixel_end) if pixel_offset >= headers_end && pixel_end <= bytes.len() => {}
+        _ => return Err(BmpError::InvalidPixelOffset),
+    }
+
+    Ok(BmpInfo {
+        width,
+        height,
+        format,
+        is_top_down,
+        bytes_per_pixel,
+        stride,
+        pixel_offset,
+        masks,
+    })
+}
+
+/// Decodes the image into `pixels` as top-down 0xAARRGGBB rows of `info.width` pixels. Images
+/// without alpha get an alpha of 0xFF.
+pub fn decode_bmp(bytes: &[u8], pixels: &mut [u32]) -> Result<BmpInfo, BmpError> {
+    let info = read_bmp_info(bytes)?;
+    if pixels.len() < info.pixel_count() {
+        return Err(BmpError::BufferTooSmall);
+    }
+
+    let pixel_data = &bytes[info.pixel_offset..];
+    for (y, row) in pixels
+        .chunks_exact_mut(info.width)
+        .take(info.height)
+        .enumerate()
+    {
+        let src_y = if info.is_top_down {
+            y
+        } else {
+            info.height - 1 - y
+        };
+        let src_row = &pixel_data[src_y * info.stride..];
+        for (pixel, src) in row
+            .iter_mut()
+            .zip(src_row.chunks_exact(info.bytes_per_pixel))
+        {
+            *pixel = match info.masks {
+                None => 0xFF000000 | (src[2] as u32) << 16 | (src[1] as u32) << 8 | src[0] as u32,
+                Some(masks) => {
+                    let value = if info.bytes_per_pixel == 2 {
+                        read_u16(src, 0) as u32
+                    } else {
+                        read_u32(src, 0)
+                    };
+                    let alpha = masks.alpha.map_or(0xFF, |alpha| alpha.extract(value));
+                    alpha << 24
+                        | masks.red.extract(value) << 16
+                        | masks.green.extract(value) << 8
+                        | masks.blue.extract(value)
+                }
+            };
+        }
+    }
+
+    Ok(info)
+}
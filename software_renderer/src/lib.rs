@@ -1,5 +1,13 @@
 extern crate base;
 
+mod bitmap;
+mod bmp;
+mod png;
+
+pub use bitmap::*;
+pub use bmp::*;
+pub use png::*;
+
 use base::math::V2;
 
 // pub fn render_weird_gradient(memory: *mut u8, width: i32, height: i32, pitch: i32, x_offset: i32, y_offset: i32) {
@@ -51,11 +59,11 @@ pub fn draw_rectangle(buffer: &mut RenderBuffer, min: V2, max: V2, r: f32, g: f3
     let b = (b * 255.0).round() as u32;
     // PATTERN: BB GG RR AA
     //          0xAARRGGBB
-    let color = (r << 16) | (g << 8) | (b << 0);
+    let color = (r << 16) | (g << 8) | b;
 
     for row in buffer
         .bytes
-        .chunks_exact_mut(buffer.pitch)
+        .chunks_mut(buffer.pitch)
         .skip(min_y)
         .take(height)
     {
@@ -71,31 +79,11 @@ pub fn draw_rectangle(buffer: &mut RenderBuffer, min: V2, max: V2, r: f32, g: f3
     }
 }
 
-pub struct LoadedBitmap {
-    pub pixels: *mut u32,
-    pub width: usize,
-    pub height: usize,
-}
-
-impl LoadedBitmap {
-    pub fn rows(&self) -> impl Iterator<Item = &[u32]> {
-        self.pixels().chunks_exact(self.width).rev()
-    }
-
-    pub fn pixels(&self) -> &[u32] {
-        unsafe { core::slice::from_raw_parts(self.pixels, self.width * self.height) }
-    }
-
-    pub fn pixels_mut(&mut self) -> &mut [u32] {
-        unsafe { core::slice::from_raw_parts_mut(self.pixels, self.width * self.height) }
-    }
-}
-
-pub fn draw_bitmap(buffer: &mut RenderBuffer, bitmap: &LoadedBitmap, x: f32, y: f32) {
+pub fn draw_bitmap(buffer: &mut RenderBuffer, bitmap: BitmapView, x: f32, y: f32) {
     assert_eq!(buffer.bytes_per_pixel, 4);
 
-    let mut width = bitmap.width as isize;
-    let mut height = bitmap.height as isize;
+    let mut width = bitmap.width() as isize;
+    let mut height = bitmap.height() as isize;
     let mut src_min_x = 0;
     let mut src_min_y = 0;
     let mut dst_min_x = x.round() as isize;
@@ -126,9 +114,10 @@ pub fn draw_bitmap(buffer: &mut RenderBuffer, bitmap: &LoadedBitmap, x: f32, y:
         return;
     }
 
+    let has_alpha = bitmap.format() == PixelFormat::Argb8888;
     for (dst_row, src_row) in buffer
         .bytes
-        .chunks_exact_mut(buffer.pitch)
+        .chunks_mut(buffer.pitch)
         .skip(dst_min_y as usize)
         .take(height as usize)
         .zip(bitmap.rows().skip(src_min_y as usize).take(height as usize))
@@ -142,19 +131,22 @@ pub fn draw_bitmap(buffer: &mut RenderBuffer, bitmap: &LoadedBitmap, x: f32, y:
             unsafe {
                 let src_val = *src;
                 let dst_val = *(dst.as_ptr() as *const u32);
-                let a = ((src_val >> 24) & 0xFF) as f32 / 255.0;
+                let a = if has_alpha {
+                    ((src_val >> 24) & 0xFF) as f32 / 255.0
+                } else {
+                    1.0
+                };
                 let sr = ((src_val >> 16) & 0xFF) as f32;
                 let sg = ((src_val >> 8) & 0xFF) as f32;
-                let sb = ((src_val >> 0) & 0xFF) as f32;
+                let sb = (src_val & 0xFF) as f32;
                 let dr = ((dst_val >> 16) & 0xFF) as f32;
                 let dg = ((dst_val >> 8) & 0xFF) as f32;
-                let db = ((dst_val >> 0) & 0xFF) as f32;
+                let db = (dst_val & 0xFF) as f32;
                 let r = (1.0 - a) * dr + a * sr;
                 let g = (1.0 - a) * dg + a * sg;
                 let b = (1.0 - a) * db + a * sb;
-                *(dst.as_mut_ptr() as *mut u32) = (((r + 0.5) as u32) << 16)
-                    | (((g + 0.5) as u32) << 8)
-                    | (((b + 0.5) as u32) << 0);
+                *(dst.as_mut_ptr() as *mut u32) =
+                    (((r + 0.5) as u32) << 16) | (((g + 0.5) as u32) << 8) | ((b + 0.5) as u32);
             }
         }
     }
@@ -0,0 +1,924 @@
+//! PNG decoder and encoder that only depend on `core`.
+//!
+//! The decoder handles non-interlaced 8 bit RGB and RGBA images and palette images with 1, 2, 4
+//! or 8 bits per pixel, including palette transparency. Like the BMP decoder it is split into
+//! `read_png_info` and `decode_png`; on top of the pixels the caller also provides a scratch
+//! buffer of `PngInfo::scratch_size` bytes that receives the inflated image data.
+//!
+//! The encoder writes opaque RGB images compressed with fixed Huffman codes. It needs a scratch
+//! buffer of `png_encode_scratch_size` bytes for the filtered image data and hands the encoded
+//! file to a callback piece by piece.
+
+use {PixelFormat, RenderBuffer};
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1A, b'\n'];
+
+const COLOR_TYPE_RGB: u8 = 2;
+const COLOR_TYPE_PALETTE: u8 = 3;
+const COLOR_TYPE_RGBA: u8 = 6;
+
+// NOTE: Anything bigger is almost certainly a corrupt header.
+const MAX_DIMENSION: u32 = 1 << 15;
+
+#[derive(Copy, Clone, PartialEq, Eq, Debug)]
+pub enum PngError {
+    BadSignature,
+    /// The file ends in the middle of a chunk or before the IEND chunk.
+    Truncated,
+    BadChunkCrc,
+    /// The chunks are missing or out of order, e.g. no IHDR at the start or no IDAT.
+    BadChunkOrder,
+    UnsupportedColorType(u8),
+    UnsupportedBitDepth(u8),
+    UnsupportedInterlace,
+    InvalidDimensions,
+    MissingPalette,
+    InvalidPaletteIndex,
+    /// The zlib stream is malformed, has the wrong size or a wrong checksum.
+    InvalidImageData,
+    InvalidFilter(u8),
+    /// The scratch or pixel buffer passed in is too small.
+    BufferTooSmall,
+}
+
+#[derive(Copy, Clone, Debug)]
+pub struct PngInfo {
+    pub width: usize,
+    pub height: usize,
+    pub format: PixelFormat,
+    color_type: u8,
+    bit_depth: u8,
+}
+
+impl PngInfo {
+    pub fn pixel_count(&self) -> usize {
+        self.width * self.height
+    }
+
+    /// Size of the inflated image data: every row starts with a filter type byte.
+    pub fn scratch_size(&self) -> usize {
+        self.height * (1 + self.row_size())
+    }
+
+    fn channel_count(&self) -> usize {
+        match self.color_type {
+            COLOR_TYPE_RGB => 3,
+            COLOR_TYPE_RGBA => 4,
+            _ => 1,
+        }
+    }
+
+    fn row_size(&self) -> usize {
+        (self.width * self.channel_count() * self.bit_depth as usize).div_ceil(8)
+    }
+
+    /// Distance in bytes to the corresponding byte of the previous pixel, as used by the filters.
+    fn filter_distance(&self) -> usize {
+        (self.channel_count() * self.bit_depth as usize).div_ceil(8)
+    }
+}
+
+struct Chunk<'a> {
+    kind: [u8; 4],
+    data: &'a [u8],
+}
+
+struct ChunkIter<'a> {
+    bytes: &'a [u8],
+    at: usize,
+}
+
+impl<'a> ChunkIter<'a> {
+    fn new(bytes: &'a [u8]) -> Result<ChunkIter<'a>, PngError> {
+        if bytes.len() < SIGNATURE.len() || bytes[..SIGNATURE.len()] != SIGNATURE {
+            return Err(PngError::BadSignature);
+        }
+        Ok(ChunkIter {
+            bytes,
+            at: SIGNATURE.len(),
+        })
+    }
+
+    fn next_chunk(&mut self) -> Result<Chunk<'a>, PngError> {
+        let header = self
+            .bytes
+            .get(self.at..self.at + 8)
+            .ok_or(PngError::Truncated)?;
+        let length = read_u32_be(header, 0) as usize;
+        let kind = [header[4], header[5], header[6], header[7]];
+        let data_start = self.at + 8;
+        let data_end = data_start.checked_add(length).ok_or(PngError::Truncated)?;
+        let crc_bytes = self
+            .bytes
+            .get(data_end..data_end + 4)
+            .ok_or(PngError::Truncated)?;
+        if crc32(&self.bytes[self.at + 4..data_end]) != read_u32_be(crc_bytes, 0) {
+            return Err(PngError::BadChunkCrc);
+        }
+
+        self.at = data_end + 4;
+        Ok(Chunk {
+            kind,
+            data: &self.bytes[data_start..data_end],
+        })
+    }
+}
+
+fn read_u32_be(bytes: &[u8], at: usize) -> u32 {
+    u32::from_be_bytes([bytes[at], bytes[at + 1], bytes[at + 2], bytes[at + 3]])
+}
+
+/// Reads the header and checks the chunk structure of the whole file.
+pub fn read_png_info(bytes: &[u8]) -> Result<PngInfo, PngError> {
+    let mut chunks = ChunkIter::new(bytes)?;
+    let header = chunks.next_chunk()?;
+    if &header.kind != b"IHDR" || header.data.len() != 13 {
+        return Err(PngError::BadChunkOrder);
+    }
+
+    let width = read_u32_be(header.data, 0);
+    let height = read_u32_be(header.data, 4);
+    let bit_depth = header.data[8];
+    let color_type = header.data[9];
+    let compression = header.data[10];
+    let filter = header.data[11];
+    let interlace = header.data[12];
+
+    if width == 0 || height == 0 || width > MAX_DIMENSION || height > MAX_DIMENSION {
+        return Err(PngError::InvalidDimensions);
+    }
+    match (color_type, bit_depth) {
+        (COLOR_TYPE_RGB, 8) | (COLOR_TYPE_RGBA, 8) => {}
+        (COLOR_TYPE_PALETTE, 1) | (COLOR_TYPE_PALETTE, 2) => {}
+        (COLOR_TYPE_PALETTE, 4) | (COLOR_TYPE_PALETTE, 8) => {}
+        (COLOR_TYPE_RGB, _) | (COLOR_TYPE_RGBA, _) | (COLOR_TYPE_PALETTE, _) => {
+            return Err(PngError::UnsupportedBitDepth(bit_depth));
+        }
+        _ => return Err(PngError::UnsupportedColorType(color_type)),
+    }
+    if compression != 0 || filter != 0 {
+        return Err(PngError::InvalidImageData);
+    }
+    if interlace != 0 {
+        return Err(PngError::UnsupportedInterlace);
+    }
+
+    let mut has_palette = false;
+    let mut has_transparency = false;
+    let mut has_image_data = false;
+    loop {
+        let chunk = chunks.next_chunk()?;
+        match &chunk.kind {
+            b"PLTE" => {
+                if has_image_data || chunk.data.len() % 3 != 0 || chunk.data.len() > 3 * 256 {
+                    return Err(PngError::BadChunkOrder);
+                }
+                has_palette = true;
+            }
+            b"tRNS" => {
+                if has_image_data {
+                    return Err(PngError::BadChunkOrder);
+                }
+                has_transparency = true;
+            }
+            b"IDAT" => has_image_data = true,
+            b"IEND" => break,
+            _ => {}
+        }
+    }
+    if !has_image_data {
+        return Err(PngError::BadChunkOrder);
+    }
+    if color_type == COLOR_TYPE_PALETTE && !has_palette {
+        return Err(PngError::MissingPalette);
+    }
+
+    // NOTE: tRNS on an RGB image marks a single color key, which is ignored.
+    let format = match color_type {
+        COLOR_TYPE_RGBA => PixelFormat::Argb8888,
+        COLOR_TYPE_PALETTE if has_transparency => PixelFormat::Argb8888,
+        _ => PixelFormat::Xrgb8888,
+    };
+
+    Ok(PngInfo {
+        width: width as usize,
+        height: height as usize,
+        format,
+        color_type,
+        bit_depth,
+    })
+}
+
+/// Decodes the image into `pixels` as top-down 0xAARRGGBB rows of `info.width` pixels with
+/// straight alpha. Opaque images get an alpha of 0xFF.
+pub fn decode_png(
+    bytes: &[u8],
+    scratch: &mut [u8],
+    pixels: &mut [u32],
+) -> Result<PngInfo, PngError> {
+    let info = read_png_info(bytes)?;
+    if scratch.len() < info.scratch_size() || pixels.len() < info.pixel_count() {
+        return Err(PngError::BufferTooSmall);
+    }
+    let scratch = &mut scratch[..info.scratch_size()];
+
+    let mut palette = [0xFF000000u32; 256];
+    let mut palette_len = 0;
+    let mut chunks = ChunkIter::new(bytes)?;
+    loop {
+        let chunk = chunks.next_chunk()?;
+        match &chunk.kind {
+            b"PLTE" => {
+                palette_len = chunk.data.len() / 3;
+                for (entry, rgb) in palette.iter_mut().zip(chunk.data.chunks_exact(3)) {
+                    *entry =
+                        0xFF000000 | (rgb[0] as u32) << 16 | (rgb[1] as u32) << 8 | rgb[2] as u32;
+                }
+            }
+            b"tRNS" if info.color_type == COLOR_TYPE_PALETTE => {
+                for (entry, alpha) in palette.iter_mut().zip(chunk.data.iter()) {
+                    *entry = (*entry & 0x00FFFFFF) | (*alpha as u32) << 24;
+                }
+            }
+            b"IEND" => break,
+            _ => {}
+        }
+    }
+
+    let mut inflater = Inflater {
+        input: IdatReader::new(bytes)?,
+        bit_buffer: 0,
+        bit_count: 0,
+        output: scratch,
+        out_at: 0,
+    };
+    inflater.inflate_zlib()?;
+    let scratch = inflater.output;
+
+    unfilter(&info, scratch)?;
+
+    let row_size = info.row_size();
+    for (dst_row, src_row) in pixels
+        .chunks_exact_mut(info.width)
+        .zip(scratch.chunks_exact(1 + row_size))
+    {
+        let src_row = &src_row[1..];
+        match info.color_type {
+            COLOR_TYPE_RGB => {
+                for (dst, src) in dst_row.iter_mut().zip(src_row.chunks_exact(3)) {
+                    *dst =
+                        0xFF000000 | (src[0] as u32) << 16 | (src[1] as u32) << 8 | src[2] as u32;
+                }
+            }
+            COLOR_TYPE_RGBA => {
+                for (dst, src) in dst_row.iter_mut().zip(src_row.chunks_exact(4)) {
+                    *dst = (src[3] as u32) << 24
+                        | (src[0] as u32) << 16
+                        | (src[1] as u32) << 8
+                        | src[2] as u32;
+                }
+            }
+            _ => {
+                let bit_depth = info.bit_depth as usize;
+                let mask = (1u32 << bit_depth) - 1;
+                for (x, dst) in dst_row.iter_mut().enumerate() {
+                    let bit = x * bit_depth;
+                    let shift = 8 - bit_depth - bit % 8;
+                    let index = (src_row[bit / 8] as u32 >> shift) & mask;
+                    if index as usize >= palette_len {
+                        return Err(PngError::InvalidPaletteIndex);
+                    }
+                    *dst = palette[index as usize];
+                }
+            }
+        }
+    }
+
+    Ok(info)
+}
+
+fn paeth_predictor(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+fn unfilter(info: &PngInfo, scratch: &mut [u8]) -> Result<(), PngError> {
+    let row_size = info.row_size();
+    let distance = info.filter_distance();
+    let stride = 1 + row_size;
+    for y in 0..info.height {
+        let (before, rest) = scratch.split_at_mut(y * stride);
+        let prev = if y > 0 {
+            Some(&before[before.len() - row_size..])
+        } else {
+            None
+        };
+        let filter = rest[0];
+        let row = &mut rest[1..stride];
+        for x in 0..row_size {
+            let a = if x >= distance { row[x - distance] } else { 0 };
+            let b = prev.map_or(0, |prev| prev[x]);
+            let c = match prev {
+                Some(prev) if x >= distance => prev[x - distance],
+                _ => 0,
+            };
+            let predictor = match filter {
+                0 => 0,
+                1 => a,
+                2 => b,
+                3 => ((a as u16 + b as u16) / 2) as u8,
+                4 => paeth_predictor(a, b, c),
+                _ => return Err(PngError::InvalidFilter(filter)),
+            };
+            row[x] = row[x].wrapping_add(predictor);
+        }
+    }
+    Ok(())
+}
+
+/// Reads the zlib stream that is split across all IDAT chunks.
+struct IdatReader<'a> {
+    chunks: ChunkIter<'a>,
+    current: &'a [u8],
+    at: usize,
+}
+
+impl<'a> IdatReader<'a> {
+    fn new(bytes: &'a [u8]) -> Result<IdatReader<'a>, PngError> {
+        Ok(IdatReader {
+            chunks: ChunkIter::new(bytes)?,
+            current: &[],
+            at: 0,
+        })
+    }
+
+    fn next_byte(&mut self) -> Result<u8, PngError> {
+        while self.at >= self.current.len() {
+            let chunk = self.chunks.next_chunk()?;
+            if &chunk.kind == b"IEND" {
+                return Err(PngError::InvalidImageData);
+            }
+            if &chunk.kind == b"IDAT" {
+                self.current = chunk.data;
+                self.at = 0;
+            }
+        }
+        let byte = self.current[self.at];
+        self.at += 1;
+        Ok(byte)
+    }
+}
+
+const LENGTH_BASE: [u16; 29] = [
+    3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115, 131,
+    163, 195, 227, 258,
+];
+const LENGTH_EXTRA: [u8; 29] = [
+    0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+];
+const DISTANCE_BASE: [u16; 30] = [
+    1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+    2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+];
+const DISTANCE_EXTRA: [u8; 30] = [
+    0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12, 13,
+    13,
+];
+
+/// Order in which the code length code lengths of a dynamic block are stored.
+const CODE_LENGTH_ORDER: [usize; 19] = [
+    16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+];
+
+const MAX_CODE_BITS: usize = 15;
+
+/// Canonical Huffman code, decoded one bit at a time.
+struct Huffman {
+    counts: [u16; MAX_CODE_BITS + 1],
+    symbols: [u16; 288],
+}
+
+impl Huffman {
+    fn new(lengths: &[u8]) -> Result<Huffman, PngError> {
+        let mut huffman = Huffman {
+            counts: [0; MAX_CODE_BITS + 1],
+            symbols: [0; 288],
+        };
+        for &length in lengths {
+            huffman.counts[length as usize] += 1;
+        }
+
+        // NOTE: Over-subscribed codes are invalid, incomplete ones are allowed and only fail
+        // when one of the missing codes is actually read.
+        let mut left: i32 = 1;
+        for &count in huffman.counts.iter().skip(1) {
+            left = (left << 1) - count as i32;
+            if left < 0 {
+                return Err(PngError::InvalidImageData);
+            }
+        }
+
+        let mut offsets = [0u16; MAX_CODE_BITS + 1];
+        for length in 1..MAX_CODE_BITS {
+            offsets[length + 1] = offsets[length] + huffman.counts[length];
+        }
+        for (symbol, &length) in lengths.iter().enumerate() {
+            if length != 0 {
+                huffman.symbols[offsets[length as usize] as usize] = symbol as u16;
+                offsets[length as usize] += 1;
+            }
+        }
+        Ok(huffman)
+    }
+}
+
+struct Inflater<'a, 'b> {
+    input: IdatReader<'a>,
+    bit_buffer: u32,
+    bit_count: u32,
+    output: &'b mut [u8],
+    out_at: usize,
+}
+
+impl<'a, 'b> Inflater<'a, 'b> {
+    fn bits(&mut self, count: u32) -> Result<u32, PngError> {
+        while self.bit_count < count {
+            self.bit_buffer |= (self.input.next_byte()? as u32) << self.bit_count;
+            self.bit_count += 8;
+        }
+        let value = self.bit_buffer & ((1 << count) - 1);
+        self.bit_buffer >>= count;
+        self.bit_count -= count;
+        Ok(value)
+    }
+
+    fn decode_symbol(&mut self, huffman: &Huffman) -> Result<u16, PngError> {
+        let mut code: i32 = 0;
+        let mut first: i32 = 0;
+        let mut index: i32 = 0;
+        for length in 1..=MAX_CODE_BITS {
+            code |= self.bits(1)? as i32;
+            let count = huffman.counts[length] as i32;
+            if code - count < first {
+                return Ok(huffman.symbols[(index + code - first) as usize]);
+            }
+            index += count;
+            first += count;
+            first <<= 1;
+            code <<= 1;
+        }
+        Err(PngError::InvalidImageData)
+    }
+
+    fn push(&mut self, byte: u8) -> Result<(), PngError> {
+        let out = self
+            .output
+            .get_mut(self.out_at)
+            .ok_or(PngError::InvalidImageData)?;
+        *out = byte;
+        self.out_at += 1;
+        Ok(())
+    }
+
+    fn inflate_zlib(&mut self) -> Result<(), PngError> {
+        let cmf = self.bits(8)?;
+        let flags = self.bits(8)?;
+        if cmf & 0x0F != 8 || cmf >> 4 > 7 || (cmf << 8 | flags) % 31 != 0 || flags & 0x20 != 0 {
+            return Err(PngError::InvalidImageData);
+        }
+
+        loop {
+            let is_final = self.bits(1)? == 1;
+            match self.bits(2)? {
+                0 => self.inflate_stored()?,
+                1 => {
+                    let (literals, distances) = fixed_codes()?;
+                    self.inflate_codes(&literals, &distances)?;
+                }
+                2 => {
+                    let (literals, distances) = self.read_dynamic_codes()?;
+                    self.inflate_codes(&literals, &distances)?;
+                }
+                _ => return Err(PngError::InvalidImageData),
+            }
+            if is_final {
+                break;
+            }
+        }
+
+        // NOTE: The checksum starts at the next byte boundary.
+        self.bit_buffer = 0;
+        self.bit_count = 0;
+        let mut checksum = 0;
+        for _ in 0..4 {
+            checksum = checksum << 8 | self.input.next_byte()? as u32;
+        }
+        if self.out_at != self.output.len() || checksum != adler32(self.output) {
+            return Err(PngError::InvalidImageData);
+        }
+        Ok(())
+    }
+
+    fn inflate_stored(&mut self) -> Result<(), PngError> {
+        self.bit_buffer = 0;
+        self.bit_count = 0;
+        let mut header = [0; 4];
+        for byte in header.iter_mut() {
+            *byte = self.input.next_byte()?;
+        }
+        let length = u16::from_le_bytes([header[0], header[1]]);
+        let inverted_length = u16::from_le_bytes([header[2], header[3]]);
+        if length != !inverted_length {
+            return Err(PngError::InvalidImageData);
+        }
+        for _ in 0..length {
+            let byte = self.input.next_byte()?;
+            self.push(byte)?;
+        }
+        Ok(())
+    }
+
+    fn read_dynamic_codes(&mut self) -> Result<(Huffman, Huffman), PngError> {
+        let literal_count = self.bits(5)? as usize + 257;
+        let distance_count = self.bits(5)? as usize + 1;
+        let code_length_count = self.bits(4)? as usize + 4;
+        if literal_count > 286 || distance_count > 30 {
+            return Err(PngError::InvalidImageData);
+        }
+
+        let mut code_lengths = [0u8; 19];
+        for &index in CODE_LENGTH_ORDER.iter().take(code_length_count) {
+            code_lengths[index] = self.bits(3)? as u8;
+        }
+        let code_length_code = Huffman::new(&code_lengths)?;
+
+        let mut lengths = [0u8; 286 + 30];
+        let mut index = 0;
+        while index < literal_count + distance_count {
+            let symbol = self.decode_symbol(&code_length_code)?;
+            let (length, repeat) = match symbol {
+                0..=15 => (symbol as u8, 1),
+                16 => {
+                    if index == 0 {
+                        return Err(PngError::InvalidImageData);
+                    }
+                    (lengths[index - 1], 3 + self.bits(2)? as usize)
+                }
+                17 => (0, 3 + self.bits(3)? as usize),
+                _ => (0, 11 + self.bits(7)? as usize),
+            };
+            if index + repeat > literal_count + distance_count {
+                return Err(PngError::InvalidImageData);
+            }
+            for length_slot in &mut lengths[index..index + repeat] {
+                *length_slot = length;
+            }
+            index += repeat;
+        }
+
+        if lengths[256] == 0 {
+            return Err(PngError::InvalidImageData);
+        }
+        let literals = Huffman::new(&lengths[..literal_count])?;
+        let distances = Huffman::new(&lengths[literal_count..literal_count + distance_count])?;
+        Ok((literals, distances))
+    }
+
+    fn inflate_codes(&mut self, literals: &Huffman, distances: &Huffman) -> Result<(), PngError> {
+        loop {
+            let symbol = self.decode_symbol(literals)? as usize;
+            if symbol < 256 {
+                self.push(symbol as u8)?;
+            } else if symbol == 256 {
+                return Ok(());
+            } else {
+                let symbol = symbol - 257;
+                if symbol >= LENGTH_BASE.len() {
+                    return Err(PngError::InvalidImageData);
+                }
+                let length =
+                    LENGTH_BASE[symbol] as usize + self.bits(LENGTH_EXTRA[symbol] as u32)? as usize;
+
+                let symbol = self.decode_symbol(distances)? as usize;
+                if symbol >= DISTANCE_BASE.len() {
+                    return Err(PngError::InvalidImageData);
+                }
+                let distance = DISTANCE_BASE[symbol] as usize
+                    + self.bits(DISTANCE_EXTRA[symbol] as u32)? as usize;
+                if distance > self.out_at {
+                    return Err(PngError::InvalidImageData);
+                }
+                for _ in 0..length {
+                    let byte = self.output[self.out_at - distance];
+                    self.push(byte)?;
+                }
+            }
+        }
+    }
+}
+
+fn fixed_codes() -> Result<(Huffman, Huffman), PngError> {
+    let mut lengths = [0u8; 288];
+    for (symbol, length) in lengths.iter_mut().enumerate() {
+        *length = match symbol {
+            0..=143 => 8,
+            144..=255 => 9,
+            256..=279 => 7,
+            _ => 8,
+        };
+    }
+    Ok((Huffman::new(&lengths)?, Huffman::new(&[5; 30])?))
+}
+
+const CRC_TABLE: [u32; 256] = {
+    let mut table = [0u32; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = if c & 1 != 0 {
+                0xEDB88320 ^ (c >> 1)
+            } else {
+                c >> 1
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+    table
+};
+
+fn update_crc32(crc: u32, bytes: &[u8]) -> u32 {
+    let mut c = crc;
+    for &byte in bytes {
+        c = CRC_TABLE[((c ^ byte as u32) & 0xFF) as usize] ^ (c >> 8);
+    }
+    c
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    update_crc32(0xFFFFFFFF, bytes) ^ 0xFFFFFFFF
+}
+
+fn adler32(bytes: &[u8]) -> u32 {
+    let mut a: u32 = 1;
+    let mut b: u32 = 0;
+    // NOTE: 5552 is the largest block for which b cannot overflow before the modulo.
+    for block in bytes.chunks(5552) {
+        for &byte in block {
+            a += byte as u32;
+            b += a;
+        }
+        a %= 65521;
+        b %= 65521;
+    }
+    b << 16 | a
+}
+
+/// Size of the scratch buffer `encode_png` needs for a `width` x `height` image.
+pub fn png_encode_scratch_size(width: usize, height: usize) -> usize {
+    height * (1 + width * 3)
+}
+
+/// Encodes the buffer as an opaque RGB PNG and passes the file to `write` in pieces.
+pub fn encode_png<W: FnMut(&[u8])>(
+    buffer: &RenderBuffer,
+    scratch: &mut [u8],
+    mut write: W,
+) -> Result<(), PngError> {
+    assert_eq!(buffer.bytes_per_pixel, 4);
+    if buffer.width == 0
+        || buffer.height == 0
+        || buffer.width > MAX_DIMENSION as usize
+        || buffer.height > MAX_DIMENSION as usize
+    {
+        return Err(PngError::InvalidDimensions);
+    }
+    let scratch_size = png_encode_scratch_size(buffer.width, buffer.height);
+    if scratch.len() < scratch_size {
+        return Err(PngError::BufferTooSmall);
+    }
+    let scratch = &mut scratch[..scratch_size];
+
+    filter_rows(buffer, scratch);
+
+    write(&SIGNATURE);
+    let mut header = [0; 13];
+    header[0..4].copy_from_slice(&(buffer.width as u32).to_be_bytes());
+    header[4..8].copy_from_slice(&(buffer.height as u32).to_be_bytes());
+    header[8] = 8;
+    header[9] = COLOR_TYPE_RGB;
+    write_chunk(&mut write, b"IHDR", &header);
+
+    let mut deflater = Deflater {
+        write: &mut write,
+        chunk: [0; IDAT_CHUNK_SIZE],
+        chunk_len: 0,
+        bit_buffer: 0,
+        bit_count: 0,
+    };
+    // NOTE: 32K window, default compression level.
+    deflater.write_bits(0x78, 8);
+    deflater.write_bits(0x9C, 8);
+    deflater.compress(scratch);
+    deflater.write_bits(0, (8 - deflater.bit_count % 8) % 8);
+    for byte in adler32(scratch).to_be_bytes().iter() {
+        deflater.write_bits(*byte as u32, 8);
+    }
+    deflater.flush_chunk();
+
+    write_chunk(&mut write, b"IEND", &[]);
+    Ok(())
+}
+
+fn write_chunk<W: FnMut(&[u8])>(write: &mut W, kind: &[u8; 4], data: &[u8]) {
+    write(&(data.len() as u32).to_be_bytes());
+    write(kind);
+    write(data);
+    let crc = update_crc32(update_crc32(0xFFFFFFFF, kind), data) ^ 0xFFFFFFFF;
+    write(&crc.to_be_bytes());
+}
+
+/// Converts the buffer to RGB rows and filters every row with the filter that gives the smallest
+/// sum of absolute differences, the heuristic recommended by the PNG specification.
+fn filter_rows(buffer: &RenderBuffer, scratch: &mut [u8]) {
+    let row_size = buffer.width * 3;
+    let rgb = |y: usize, i: usize| -> u8 {
+        let pixel = &buffer.bytes[y * buffer.pitch + (i / 3) * 4..];
+        // NOTE: Pixels are 0xXXRRGGBB in little endian, so the bytes are B, G, R, X.
+        pixel[2 - i % 3]
+    };
+    let filtered = |filter: u8, y: usize, i: usize| -> u8 {
+        let x = rgb(y, i);
+        let a = if i >= 3 { rgb(y, i - 3) } else { 0 };
+        let b = if y > 0 { rgb(y - 1, i) } else { 0 };
+        let c = if y > 0 && i >= 3 {
+            rgb(y - 1, i - 3)
+        } else {
+            0
+        };
+        let predictor = match filter {
+            0 => 0,
+            1 => a,
+            2 => b,
+            3 => ((a as u16 + b as u16) / 2) as u8,
+            _ => paeth_predictor(a, b, c),
+        };
+        x.wrapping_sub(predictor)
+    };
+
+    for (y, row) in scratch.chunks_exact_mut(1 + row_size).enumerate() {
+        let mut best_filter = 0;
+        let mut best_sum = u64::MAX;
+        for filter in 0..5 {
+            let sum = (0..row_size)
+                .map(|i| (filtered(filter, y, i) as i8).unsigned_abs() as u64)
+                .sum();
+            if sum < best_sum {
+                best_sum = sum;
+                best_filter = filter;
+            }
+        }
+
+        row[0] = best_filter;
+        for (i, byte) in row[1..].iter_mut().enumerate() {
+            *byte = filtered(best_filter, y, i);
+        }
+    }
+}
+
+const IDAT_CHUNK_SIZE: usize = 1 << 14;
+const HASH_BITS: u32 = 14;
+const MIN_MATCH: usize = 3;
+const MAX_MATCH: usize = 258;
+const WINDOW_SIZE: usize = 1 << 15;
+
+/// Deflate compressor emitting a single block with the fixed Huffman codes, chunked into IDATs.
+struct Deflater<'w, W: FnMut(&[u8]) + 'w> {
+    write: &'w mut W,
+    chunk: [u8; IDAT_CHUNK_SIZE],
+    chunk_len: usize,
+    bit_buffer: u64,
+    bit_count: u32,
+}
+
+impl<'w, W: FnMut(&[u8]) + 'w> Deflater<'w, W> {
+    fn write_bits(&mut self, value: u32, count: u32) {
+        self.bit_buffer |= (value as u64) << self.bit_count;
+        self.bit_count += count;
+        while self.bit_count >= 8 {
+            self.chunk[self.chunk_len] = self.bit_buffer as u8;
+            self.chunk_len += 1;
+            self.bit_buffer >>= 8;
+            self.bit_count -= 8;
+            if self.chunk_len == IDAT_CHUNK_SIZE {
+                self.flush_chunk();
+            }
+        }
+    }
+
+    /// Huffman codes are packed starting with their most significant bit.
+    fn write_code(&mut self, code: u32, count: u32) {
+        self.write_bits(code.reverse_bits() >> (32 - count), count);
+    }
+
+    fn flush_chunk(&mut self) {
+        if self.chunk_len > 0 {
+            write_chunk(self.write, b"IDAT", &self.chunk[..self.chunk_len]);
+            self.chunk_len = 0;
+        }
+    }
+
+    fn write_literal(&mut self, symbol: u32) {
+        match symbol {
+            0..=143 => self.write_code(0x30 + symbol, 8),
+            144..=255 => self.write_code(0x190 + symbol - 144, 9),
+            256..=279 => self.write_code(symbol - 256, 7),
+            _ => self.write_code(0xC0 + symbol - 280, 8),
+        }
+    }
+
+    fn write_match(&mut self, length: usize, distance: usize) {
+        let symbol = LENGTH_BASE
+            .iter()
+            .rposition(|&base| base as usize <= length)
+            .unwrap();
+        self.write_literal(257 + symbol as u32);
+        self.write_bits(
+            (length - LENGTH_BASE[symbol] as usize) as u32,
+            LENGTH_EXTRA[symbol] as u32,
+        );
+
+        let symbol = DISTANCE_BASE
+            .iter()
+            .rposition(|&base| base as usize <= distance)
+            .unwrap();
+        self.write_code(symbol as u32, 5);
+        self.write_bits(
+            (distance - DISTANCE_BASE[symbol] as usize) as u32,
+            DISTANCE_EXTRA[symbol] as u32,
+        );
+    }
+
+    fn compress(&mut self, data: &[u8]) {
+        fn hash(bytes: &[u8]) -> usize {
+            let value = (bytes[0] as u32) << 16 | (bytes[1] as u32) << 8 | bytes[2] as u32;
+            (value.wrapping_mul(0x9E3779B1) >> (32 - HASH_BITS)) as usize
+        }
+
+        // NOTE: Only the most recent position for every hash is kept, which is a lot faster than
+        // hash chains and good enough for screenshots.
+        let mut last_position = [0u32; 1 << HASH_BITS];
+
+        self.write_bits(1, 1);
+        self.write_bits(1, 2);
+
+        let mut at = 0;
+        while at < data.len() {
+            let mut match_length = 0;
+            let mut match_distance = 0;
+            if at + MIN_MATCH <= data.len() {
+                let h = hash(&data[at..]);
+                let candidate = last_position[h] as usize;
+                last_position[h] = at as u32 + 1;
+                if candidate > 0 && at - (candidate - 1) <= WINDOW_SIZE {
+                    let candidate = candidate - 1;
+                    let max_length = MAX_MATCH.min(data.len() - at);
+                    let length = data[candidate..]
+                        .iter()
+                        .zip(data[at..at + max_length].iter())
+                        .take_while(|(a, b)| a == b)
+                        .count();
+                    if length >= MIN_MATCH {
+                        match_length = length;
+                        match_distance = at - candidate;
+                    }
+                }
+            }
+
+            if match_length > 0 {
+                self.write_match(match_length, match_distance);
+                for position in at + 1..(at + match_length).min(data.len() - MIN_MATCH + 1) {
+                    last_position[hash(&data[position..])] = position as u32 + 1;
+                }
+                at += match_length;
+            } else {
+                self.write_literal(data[at] as u32);
+                at += 1;
+            }
+        }
+
+        self.write_literal(256);
+    }
+}
@@ -0,0 +1,318 @@
+extern crate software_renderer;
+
+use software_renderer::*;
+
+const BI_RGB: u32 = 0;
+const BI_RLE8: u32 = 1;
+const BI_BITFIELDS: u32 = 3;
+
+/// Builds a BMP file in memory. `rows` are given top-down and already encoded with
+/// `bits_per_pixel`, the builder adds the row padding and flips them for bottom-up images.
+struct BmpBuilder {
+    width: i32,
+    height: i32,
+    bits_per_pixel: u16,
+    compression: u32,
+    info_size: u32,
+    masks: Option<[u32; 4]>,
+    rows: Vec<Vec<u8>>,
+}
+
+impl BmpBuilder {
+    fn new(width: i32, bits_per_pixel: u16, compression: u32, rows: Vec<Vec<u8>>) -> BmpBuilder {
+        BmpBuilder {
+            width,
+            height: rows.len() as i32,
+            bits_per_pixel,
+            compression,
+            info_size: 40,
+            masks: None,
+            rows,
+        }
+    }
+
+    fn top_down(mut self) -> BmpBuilder {
+        self.height = -self.height;
+        self
+    }
+
+    /// Color masks following a plain BITMAPINFOHEADER, the alpha mask is ignored.
+    fn masks(mut self, red: u32, green: u32, blue: u32) -> BmpBuilder {
+        self.masks = Some([red, green, blue, 0]);
+        self
+    }
+
+    /// Color and alpha masks inside a version 3 header.
+    fn v3_masks(mut self, red: u32, green: u32, blue: u32, alpha: u32) -> BmpBuilder {
+        self.info_size = 56;
+        self.masks = Some([red, green, blue, alpha]);
+        self
+    }
+
+    fn build(&self) -> Vec<u8> {
+        let mut info = Vec::new();
+        info.extend_from_slice(&self.info_size.to_le_bytes());
+        info.extend_from_slice(&self.width.to_le_bytes());
+        info.extend_from_slice(&self.height.to_le_bytes());
+        info.extend_from_slice(&1u16.to_le_bytes());
+        info.extend_from_slice(&self.bits_per_pixel.to_le_bytes());
+        info.extend_from_slice(&self.compression.to_le_bytes());
+        info.extend_from_slice(&[0; 20]);
+        if let Some(masks) = self.masks {
+            let mask_count = if self.info_size == 40 { 3 } else { 4 };
+            for mask in masks.iter().take(mask_count) {
+                info.extend_from_slice(&mask.to_le_bytes());
+            }
+        }
+
+        let mut pixels = Vec::new();
+        let mut rows: Vec<&Vec<u8>> = self.rows.iter().collect();
+        if self.height > 0 {
+            rows.reverse();
+        }
+        for row in rows {
+            pixels.extend_from_slice(row);
+            while pixels.len() % 4 != 0 {
+                pixels.push(0);
+            }
+        }
+
+        let pixel_offset = 14 + info.len() as u32;
+        let mut bytes = Vec::new();
+        bytes.extend_from_slice(b"BM");
+        bytes.extend_from_slice(&(pixel_offset + pixels.len() as u32).to_le_bytes());
+        bytes.extend_from_slice(&[0; 4]);
+        bytes.extend_from_slice(&pixel_offset.to_le_bytes());
+        bytes.extend_from_slice(&info);
+        bytes.extend_from_slice(&pixels);
+        bytes
+    }
+}
+
+fn row_u32(pixels: &[u32]) -> Vec<u8> {
+    pixels
+        .iter()
+        .flat_map(|pixel| pixel.to_le_bytes())
+        .collect()
+}
+
+fn row_u16(pixels: &[u16]) -> Vec<u8> {
+    pixels
+        .iter()
+        .flat_map(|pixel| pixel.to_le_bytes())
+        .collect()
+}
+
+fn decode(bytes: &[u8]) -> Result<(BmpInfo, Vec<u32>), BmpError> {
+    let info = read_bmp_info(bytes)?;
+    let mut pixels = vec![0; info.pixel_count()];
+    decode_bmp(bytes, &mut pixels)?;
+    Ok((info, pixels))
+}
+
+#[test]
+fn bitfields_with_alpha_mask() {
+    let bytes = BmpBuilder::new(
+        2,
+        32,
+        BI_BITFIELDS,
+        vec![
+            row_u32(&[0x11223344, 0x80FF0000]),
+            row_u32(&[0x00000000, 0xFFFFFFFF]),
+        ],
+    )
+    .v3_masks(0x000000FF, 0x0000FF00, 0x00FF0000, 0xFF000000)
+    .build();
+
+    let (info, pixels) = decode(&bytes).unwrap();
+    assert_eq!((info.width, info.height), (2, 2));
+    assert_eq!(info.format, PixelFormat::Argb8888);
+    assert_eq!(pixels, vec![0x11443322, 0x800000FF, 0x00000000, 0xFFFFFFFF]);
+}
+
+#[test]
+fn bitfields_without_alpha_mask() {
+    let bytes = BmpBuilder::new(
+        2,
+        32,
+        BI_BITFIELDS,
+        vec![row_u32(&[0x00112233, 0x12345678])],
+    )
+    .masks(0x00FF0000, 0x0000FF00, 0x000000FF)
+    .build();
+
+    let (info, pixels) = decode(&bytes).unwrap();
+    assert_eq!(info.format, PixelFormat::Xrgb8888);
+    assert_eq!(pixels, vec![0xFF112233, 0xFF345678]);
+}
+
+#[test]
+fn bitfields_16_bit() {
+    let bytes = BmpBuilder::new(
+        3,
+        16,
+        BI_BITFIELDS,
+        vec![
+            row_u16(&[0xF800, 0x07E0, 0x001F]),
+            row_u16(&[0x0000, 0xFFFF, 0x8410]),
+        ],
+    )
+    .masks(0xF800, 0x07E0, 0x001F)
+    .build();
+
+    let (_, pixels) = decode(&bytes).unwrap();
+    assert_eq!(
+        pixels,
+        vec![0xFFFF0000, 0xFF00FF00, 0xFF0000FF, 0xFF000000, 0xFFFFFFFF, 0xFF848284]
+    );
+}
+
+#[test]
+fn rgb_24_bit_with_row_padding() {
+    let bytes = BmpBuilder::new(
+        3,
+        24,
+        BI_RGB,
+        vec![
+            vec![0x33, 0x22, 0x11, 0x66, 0x55, 0x44, 0x99, 0x88, 0x77],
+            vec![0xFF, 0x00, 0x00, 0x00, 0xFF, 0x00, 0x00, 0x00, 0xFF],
+        ],
+    )
+    .build();
+
+    let (info, pixels) = decode(&bytes).unwrap();
+    assert_eq!((info.width, info.height), (3, 2));
+    assert_eq!(info.format, PixelFormat::Xrgb8888);
+    assert_eq!(
+        pixels,
+        vec![0xFF112233, 0xFF445566, 0xFF778899, 0xFF0000FF, 0xFF00FF00, 0xFFFF0000]
+    );
+}
+
+#[test]
+fn rgb_32_bit_ignores_top_byte() {
+    let bytes = BmpBuilder::new(1, 32, BI_RGB, vec![row_u32(&[0x00ABCDEF])]).build();
+
+    let (info, pixels) = decode(&bytes).unwrap();
+    assert_eq!(info.format, PixelFormat::Xrgb8888);
+    assert_eq!(pixels, vec![0xFFABCDEF]);
+}
+
+#[test]
+fn top_down() {
+    let rows = vec![row_u32(&[0x00000001]), row_u32(&[0x00000002])];
+    let bottom_up = BmpBuilder::new(1, 32, BI_RGB, rows.clone()).build();
+    let top_down = BmpBuilder::new(1, 32, BI_RGB, rows).top_down().build();
+
+    assert_eq!(decode(&bottom_up).unwrap().1, vec![0xFF000001, 0xFF000002]);
+    assert_eq!(decode(&top_down).unwrap().1, vec![0xFF000001, 0xFF000002]);
+}
+
+#[test]
+fn rejects_truncated_files() {
+    let bytes = BmpBuilder::new(2, 32, BI_RGB, vec![row_u32(&[0, 0]), row_u32(&[0, 0])]).build();
+
+    assert_eq!(read_bmp_info(&[]).unwrap_err(), BmpError::Truncated);
+    assert_eq!(
+        read_bmp_info(&bytes[..30]).unwrap_err(),
+        BmpError::Truncated
+    );
+    assert_eq!(
+        read_bmp_info(&bytes[..bytes.len() - 1]).unwrap_err(),
+        BmpError::InvalidPixelOffset
+    );
+}
+
+#[test]
+fn rejects_bad_pixel_offset() {
+    let mut bytes = BmpBuilder::new(1, 32, BI_RGB, vec![row_u32(&[0])]).build();
+    bytes[10..14].copy_from_slice(&20u32.to_le_bytes());
+    assert_eq!(
+        read_bmp_info(&bytes).unwrap_err(),
+        BmpError::InvalidPixelOffset
+    );
+
+    bytes[10..14].copy_from_slice(&u32::MAX.to_le_bytes());
+    assert_eq!(
+        read_bmp_info(&bytes).unwrap_err(),
+        BmpError::InvalidPixelOffset
+    );
+}
+
+#[test]
+fn rejects_unsupported_formats() {
+    let mut bytes = BmpBuilder::new(1, 32, BI_RGB, vec![row_u32(&[0])]).build();
+    bytes[0] = b'X';
+    assert_eq!(read_bmp_info(&bytes).unwrap_err(), BmpError::BadSignature);
+
+    let bytes = BmpBuilder::new(4, 8, BI_RGB, vec![vec![0; 4]]).build();
+    assert_eq!(
+        read_bmp_info(&bytes).unwrap_err(),
+        BmpError::UnsupportedBitCount(8)
+    );
+
+    let bytes = BmpBuilder::new(4, 8, BI_RLE8, vec![vec![0; 4]]).build();
+    assert_eq!(
+        read_bmp_info(&bytes).unwrap_err(),
+        BmpError::UnsupportedCompression(BI_RLE8)
+    );
+
+    let mut bytes = BmpBuilder::new(1, 32, BI_RGB, vec![row_u32(&[0])]).build();
+    bytes[14..18].copy_from_slice(&12u32.to_le_bytes());
+    assert_eq!(
+        read_bmp_info(&bytes).unwrap_err(),
+        BmpError::UnsupportedHeader(12)
+    );
+}
+
+#[test]
+fn rejects_invalid_dimensions() {
+    let bytes = BmpBuilder::new(0, 32, BI_RGB, vec![vec![]]).build();
+    assert_eq!(
+        read_bmp_info(&bytes).unwrap_err(),
+        BmpError::InvalidDimensions
+    );
+
+    let bytes = BmpBuilder::new(-1, 32, BI_RGB, vec![row_u32(&[0])]).build();
+    assert_eq!(
+        read_bmp_info(&bytes).unwrap_err(),
+        BmpError::InvalidDimensions
+    );
+
+    let bytes = BmpBuilder::new(1, 32, BI_RGB, vec![]).build();
+    assert_eq!(
+        read_bmp_info(&bytes).unwrap_err(),
+        BmpError::InvalidDimensions
+    );
+}
+
+#[test]
+fn rejects_invalid_masks() {
+    let rows = vec![row_u32(&[0])];
+    let missing = BmpBuilder::new(1, 32, BI_BITFIELDS, rows.clone())
+        .masks(0x00FF0000, 0x0000FF00, 0)
+        .build();
+    let overlapping = BmpBuilder::new(1, 32, BI_BITFIELDS, rows.clone())
+        .v3_masks(0x00FF0000, 0x0000FF00, 0x000000FF, 0xFF0000FF)
+        .build();
+    let split = BmpBuilder::new(1, 32, BI_BITFIELDS, rows)
+        .masks(0x00F0F000, 0x00000F00, 0x000000FF)
+        .build();
+
+    assert_eq!(read_bmp_info(&missing).unwrap_err(), BmpError::InvalidMask);
+    assert_eq!(
+        read_bmp_info(&overlapping).unwrap_err(),
+        BmpError::InvalidMask
+    );
+    assert_eq!(read_bmp_info(&split).unwrap_err(), BmpError::InvalidMask);
+}
+
+#[test]
+fn rejects_small_buffer() {
+    let bytes = BmpBuilder::new(2, 32, BI_RGB, vec![row_u32(&[0, 0])]).build();
+    let mut pixels = [0; 1];
+    assert_eq!(
+        decode_bmp(&bytes, &mut pixels).unwrap_err(),
+        BmpError::BufferTooSmall
+    );
+}
@@ -0,0 +1,409 @@
+//! Golden image tests for the software renderer.
+//!
+//! Every test renders into an owned buffer and compares the result against a reference image in
+//! `tests/golden`. Run with `UPDATE_GOLDEN=1` to (re)write the reference images after an
+//! intended change to the rendering output. When a comparison fails, the actual image and a diff
+//! image highlighting the mismatching pixels in red are written to `target/tmp/golden`.
+
+extern crate base;
+extern crate software_renderer;
+
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use base::math::V2;
+use software_renderer::*;
+
+const WIDTH: usize = 16;
+const HEIGHT: usize = 16;
+const CHANNEL_TOLERANCE: u8 = 1;
+
+struct TestBuffer {
+    bytes: Vec<u8>,
+    width: usize,
+    height: usize,
+}
+
+impl TestBuffer {
+    // NOTE: Start from a checkerboard so blending is tested against more than one destination.
+    fn new(width: usize, height: usize) -> TestBuffer {
+        let mut buffer = TestBuffer {
+            bytes: vec![0; width * height * 4],
+            width,
+            height,
+        };
+        for y in 0..height {
+            for x in 0..width {
+                let color = if (x + y) % 2 == 0 {
+                    0x00202020
+                } else {
+                    0x00C0C0C0
+                };
+                buffer.set_pixel(x, y, color);
+            }
+        }
+        buffer
+    }
+
+    fn render_buffer(&mut self) -> RenderBuffer<'_> {
+        RenderBuffer {
+            bytes: &mut self.bytes,
+            width: self.width,
+            height: self.height,
+            pitch: self.width * 4,
+            bytes_per_pixel: 4,
+        }
+    }
+
+    fn pixel(&self, x: usize, y: usize) -> u32 {
+        let at = (y * self.width + x) * 4;
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(&self.bytes[at..at + 4]);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn set_pixel(&mut self, x: usize, y: usize, color: u32) {
+        let at = (y * self.width + x) * 4;
+        self.bytes[at..at + 4].copy_from_slice(&color.to_le_bytes());
+    }
+
+    fn rgb(&self) -> Vec<u8> {
+        let mut rgb = Vec::with_capacity(self.width * self.height * 3);
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let pixel = self.pixel(x, y);
+                rgb.extend_from_slice(&[(pixel >> 16) as u8, (pixel >> 8) as u8, pixel as u8]);
+            }
+        }
+        rgb
+    }
+}
+
+/// Pixels are generated from their position, so a bitmap drawn at the wrong offset or flipped
+/// shows up in the diff.
+fn gradient(width: usize, height: usize, alpha: u8) -> Bitmap<'static> {
+    let mut bitmap = Bitmap::new(width, height, PixelFormat::Argb8888);
+    for (row, pixels) in bitmap.rows_mut().enumerate() {
+        for (column, pixel) in pixels.iter_mut().enumerate() {
+            let r = (column * 255 / (width - 1)) as u32;
+            let g = (row * 255 / (height - 1)) as u32;
+            let b = 0x80;
+            *pixel = ((alpha as u32) << 24) | (r << 16) | (g << 8) | b;
+        }
+    }
+    bitmap
+}
+
+fn alpha_ramp(width: usize, height: usize) -> Bitmap<'static> {
+    let mut bitmap = Bitmap::new(width, height, PixelFormat::Argb8888);
+    for row in bitmap.rows_mut() {
+        for (column, pixel) in row.iter_mut().enumerate() {
+            let alpha = (column * 255 / (width - 1)) as u32;
+            *pixel = (alpha << 24) | 0x00F04010;
+        }
+    }
+    bitmap
+}
+
+fn golden_path(name: &str) -> PathBuf {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("golden")
+        .join(format!("{}.ppm", name))
+}
+
+fn failure_path(name: &str, suffix: &str) -> PathBuf {
+    Path::new(env!("CARGO_TARGET_TMPDIR"))
+        .join("golden")
+        .join(format!("{}.{}.ppm", name, suffix))
+}
+
+fn write_ppm(path: &Path, width: usize, height: usize, rgb: &[u8]) {
+    fs::create_dir_all(path.parent().unwrap()).unwrap();
+    let mut contents = format!("P6\n{} {}\n255\n", width, height).into_bytes();
+    contents.extend_from_slice(rgb);
+    fs::write(path, contents).unwrap();
+}
+
+fn read_ppm(path: &Path) -> Option<(usize, usize, Vec<u8>)> {
+    let contents = fs::read(path).ok()?;
+    let mut fields = Vec::new();
+    let mut at = 0;
+    while fields.len() < 4 {
+        while contents.get(at)?.is_ascii_whitespace() {
+            at += 1;
+        }
+        let start = at;
+        while !contents.get(at)?.is_ascii_whitespace() {
+            at += 1;
+        }
+        fields.push(String::from_utf8_lossy(&contents[start..at]).into_owned());
+    }
+    if fields[0] != "P6" || fields[3] != "255" {
+        return None;
+    }
+
+    let width = fields[1].parse().ok()?;
+    let height = fields[2].parse().ok()?;
+    let rgb = contents.get(at + 1..)?.to_vec();
+    if rgb.len() != width * height * 3 {
+        return None;
+    }
+    Some((width, height, rgb))
+}
+
+fn assert_golden(name: &str, buffer: &TestBuffer) {
+    let actual = buffer.rgb();
+    let path = golden_path(name);
+    if env::var_os("UPDATE_GOLDEN").is_some() {
+        write_ppm(&path, buffer.width, buffer.height, &actual);
+        return;
+    }
+
+    let (width, height, expected) = read_ppm(&path).unwrap_or_else(|| {
+        panic!(
+            "missing or unreadable reference image {}, run with UPDATE_GOLDEN=1 to create it",
+            path.display()
+        )
+    });
+    assert_eq!(
+        (width, height),
+        (buffer.width, buffer.height),
+        "{}: reference image has a different size",
+        name
+    );
+
+    let mut mismatch_count = 0;
+    let mut diff = Vec::with_capacity(expected.len());
+    for (expected, actual) in expected.chunks_exact(3).zip(actual.chunks_exact(3)) {
+        let is_mismatch = expected
+            .iter()
+            .zip(actual.iter())
+            .any(|(e, a)| (*e as i32 - *a as i32).unsigned_abs() > CHANNEL_TOLERANCE as u32);
+        if is_mismatch {
+            mismatch_count += 1;
+            diff.extend_from_slice(&[255, 0, 0]);
+        } else {
+            let gray = ((expected[0] as u32 + expected[1] as u32 + expected[2] as u32) / 12) as u8;
+            diff.extend_from_slice(&[gray, gray, gray]);
+        }
+    }
+
+    if mismatch_count > 0 {
+        let actual_path = failure_path(name, "actual");
+        let diff_path = failure_path(name, "diff");
+        write_ppm(&actual_path, width, height, &actual);
+        write_ppm(&diff_path, width, height, &diff);
+        panic!(
+            "{}: {} pixels differ from {} by more than {}, see {} and {}",
+            name,
+            mismatch_count,
+            path.display(),
+            CHANNEL_TOLERANCE,
+            actual_path.display(),
+            diff_path.display()
+        );
+    }
+}
+
+fn render_rectangle(name: &str, min: V2, max: V2) {
+    let mut buffer = TestBuffer::new(WIDTH, HEIGHT);
+    draw_rectangle(&mut buffer.render_buffer(), min, max, 1.0, 0.5, 0.0);
+    assert_golden(name, &buffer);
+}
+
+fn render_bitmap(name: &str, bitmap: BitmapView, x: f32, y: f32) {
+    let mut buffer = TestBuffer::new(WIDTH, HEIGHT);
+    draw_bitmap(&mut buffer.render_buffer(), bitmap, x, y);
+    assert_golden(name, &buffer);
+}
+
+#[test]
+fn rectangle_inside() {
+    render_rectangle("rectangle_inside", V2::new(3.0, 4.0), V2::new(11.0, 9.0));
+}
+
+#[test]
+fn rectangle_sub_pixel() {
+    render_rectangle(
+        "rectangle_sub_pixel",
+        V2::new(2.5, 3.49),
+        V2::new(10.51, 9.5),
+    );
+}
+
+#[test]
+fn rectangle_clipped_left_top() {
+    render_rectangle(
+        "rectangle_clipped_left_top",
+        V2::new(-5.0, -3.0),
+        V2::new(6.0, 7.0),
+    );
+}
+
+#[test]
+fn rectangle_clipped_right_bottom() {
+    render_rectangle(
+        "rectangle_clipped_right_bottom",
+        V2::new(9.0, 10.0),
+        V2::new(30.0, 25.0),
+    );
+}
+
+#[test]
+fn rectangle_outside() {
+    render_rectangle(
+        "rectangle_outside",
+        V2::new(20.0, -8.0),
+        V2::new(30.0, -1.0),
+    );
+}
+
+#[test]
+fn bitmap_inside() {
+    render_bitmap("bitmap_inside", gradient(8, 6, 255).view(), 4.0, 5.0);
+}
+
+#[test]
+fn bitmap_sub_pixel() {
+    render_bitmap("bitmap_sub_pixel", gradient(8, 6, 255).view(), 3.5, 4.49);
+}
+
+#[test]
+fn bitmap_clipped_left() {
+    render_bitmap("bitmap_clipped_left", gradient(8, 6, 255).view(), -3.0, 5.0);
+}
+
+#[test]
+fn bitmap_clipped_right() {
+    render_bitmap(
+        "bitmap_clipped_right",
+        gradient(8, 6, 255).view(),
+        12.0,
+        5.0,
+    );
+}
+
+#[test]
+fn bitmap_clipped_top() {
+    render_bitmap("bitmap_clipped_top", gradient(8, 6, 255).view(), 4.0, -2.6);
+}
+
+#[test]
+fn bitmap_clipped_bottom() {
+    render_bitmap(
+        "bitmap_clipped_bottom",
+        gradient(8, 6, 255).view(),
+        4.0,
+        13.0,
+    );
+}
+
+#[test]
+fn bitmap_clipped_all_edges() {
+    render_bitmap(
+        "bitmap_clipped_all_edges",
+        gradient(24, 24, 255).view(),
+        -4.0,
+        -4.0,
+    );
+}
+
+#[test]
+fn bitmap_outside() {
+    render_bitmap("bitmap_outside", gradient(8, 6, 255).view(), -8.0, 16.0);
+}
+
+#[test]
+fn bitmap_fully_transparent() {
+    render_bitmap(
+        "bitmap_fully_transparent",
+        gradient(8, 6, 0).view(),
+        4.0,
+        5.0,
+    );
+}
+
+#[test]
+fn bitmap_half_transparent() {
+    render_bitmap(
+        "bitmap_half_transparent",
+        gradient(8, 6, 128).view(),
+        4.0,
+        5.0,
+    );
+}
+
+#[test]
+fn bitmap_alpha_ramp() {
+    render_bitmap("bitmap_alpha_ramp", alpha_ramp(16, 4).view(), 0.0, 6.0);
+}
+
+#[test]
+fn bitmap_sub_view() {
+    let bitmap = gradient(12, 10, 255);
+    render_bitmap(
+        "bitmap_sub_view",
+        bitmap.sub_view(3, 2, 6, 5).unwrap(),
+        5.0,
+        5.0,
+    );
+}
+
+#[test]
+fn bitmap_sub_view_out_of_bounds() {
+    let bitmap = gradient(12, 10, 255);
+    assert!(bitmap.sub_view(8, 2, 6, 5).is_none());
+    assert!(bitmap.sub_view(0, 6, 12, 5).is_none());
+    assert!(bitmap.sub_view(12, 10, 0, 0).is_some());
+    assert!(bitmap.sub_view(1, 0, usize::MAX, 1).is_none());
+    assert!(bitmap.sub_view(0, usize::MAX, 1, 2).is_none());
+}
+
+#[test]
+fn bitmap_opaque_format_ignores_alpha() {
+    let mut pixels = gradient(8, 6, 0).pixels().to_vec();
+    let bitmap = Bitmap::from_pixels(&mut pixels, 8, 6, 8, PixelFormat::Xrgb8888);
+    render_bitmap("bitmap_inside", bitmap.view(), 4.0, 5.0);
+}
+
+#[test]
+fn bitmap_with_pitch() {
+    // NOTE: The gradient sits in the right half of a wider block of pixels.
+    let source = gradient(8, 6, 255);
+    let mut pixels = vec![0xFF00FF00; 16 * 6];
+    for (row, source_row) in pixels.chunks_exact_mut(16).zip(source.view().rows()) {
+        row[8..].copy_from_slice(source_row);
+    }
+    let bitmap = Bitmap::from_pixels(&mut pixels[8..], 8, 6, 16, PixelFormat::Argb8888);
+    render_bitmap("bitmap_inside", bitmap.view(), 4.0, 5.0);
+}
+
+#[test]
+fn render_target() {
+    let mut target = Bitmap::new(10, 8, PixelFormat::Xrgb8888);
+    draw_rectangle(
+        &mut target.render_buffer(),
+        V2::new(0.0, 0.0),
+        V2::new(10.0, 8.0),
+        0.0,
+        0.0,
+        1.0,
+    );
+    draw_rectangle(
+        &mut target.render_buffer(),
+        V2::new(2.0, 2.0),
+        V2::new(8.0, 6.0),
+        1.0,
+        0.5,
+        0.0,
+    );
+    draw_bitmap(
+        &mut target.render_buffer(),
+        gradient(4, 3, 128).view(),
+        3.0,
+        2.0,
+    );
+    render_bitmap("render_target", target.view(), 3.0, 4.0);
+}
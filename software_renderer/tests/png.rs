@@ -0,0 +1,250 @@
+//! The files in `tests/png` were written with Python's zlib, so the decoder is checked against an
+//! independent encoder. They are 7x5 images generated from the formulas below, with the filter
+//! type of every row cycling through all five filters.
+
+extern crate software_renderer;
+
+use std::fs;
+use std::path::Path;
+
+use software_renderer::*;
+
+const WIDTH: usize = 7;
+const HEIGHT: usize = 5;
+
+fn read_test_file(name: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("png")
+        .join(name);
+    fs::read(&path).unwrap_or_else(|err| panic!("failed to read {}: {}", path.display(), err))
+}
+
+fn decode(bytes: &[u8]) -> Result<(PngInfo, Vec<u32>), PngError> {
+    let info = read_png_info(bytes)?;
+    let mut scratch = vec![0; info.scratch_size()];
+    let mut pixels = vec![0; info.pixel_count()];
+    decode_png(bytes, &mut scratch, &mut pixels)?;
+    Ok((info, pixels))
+}
+
+fn rgb(x: usize, y: usize) -> u32 {
+    let r = (x * 30 % 256) as u32;
+    let g = (y * 40 % 256) as u32;
+    let b = (x * y * 7 % 256) as u32;
+    r << 16 | g << 8 | b
+}
+
+fn alpha(x: usize, y: usize) -> u32 {
+    ((x * 37 + y * 11) % 256) as u32
+}
+
+fn expected(pixel: impl Fn(usize, usize) -> u32) -> Vec<u32> {
+    let mut pixels = Vec::new();
+    for y in 0..HEIGHT {
+        for x in 0..WIDTH {
+            pixels.push(pixel(x, y));
+        }
+    }
+    pixels
+}
+
+fn palette_entry(index: usize, bit_depth: usize) -> u32 {
+    let r = (index * 13 % 256) as u32;
+    let g = (index * 29 % 256) as u32;
+    let b = (index * 71 % 256) as u32;
+    // NOTE: All palette images but the 8 bit one have alpha for their first four entries.
+    let a = if bit_depth != 8 && index < 4 {
+        255 - index as u32 * 9
+    } else {
+        255
+    };
+    a << 24 | r << 16 | g << 8 | b
+}
+
+fn check_palette(name: &str, bit_depth: usize) {
+    let (info, pixels) = decode(&read_test_file(name)).unwrap();
+    assert_eq!((info.width, info.height), (WIDTH, HEIGHT));
+    let expected_format = if bit_depth == 8 {
+        PixelFormat::Xrgb8888
+    } else {
+        PixelFormat::Argb8888
+    };
+    assert_eq!(info.format, expected_format);
+    assert_eq!(
+        pixels,
+        expected(|x, y| palette_entry((x + y * 3) % (1 << bit_depth), bit_depth))
+    );
+}
+
+#[test]
+fn rgb8_dynamic_huffman() {
+    let (info, pixels) = decode(&read_test_file("rgb8.png")).unwrap();
+    assert_eq!((info.width, info.height), (WIDTH, HEIGHT));
+    assert_eq!(info.format, PixelFormat::Xrgb8888);
+    assert_eq!(pixels, expected(|x, y| 0xFF000000 | rgb(x, y)));
+}
+
+#[test]
+fn rgb8_stored_blocks() {
+    let (_, pixels) = decode(&read_test_file("rgb8_stored.png")).unwrap();
+    assert_eq!(pixels, expected(|x, y| 0xFF000000 | rgb(x, y)));
+}
+
+#[test]
+fn rgba8_split_across_idat_chunks() {
+    let (info, pixels) = decode(&read_test_file("rgba8.png")).unwrap();
+    assert_eq!(info.format, PixelFormat::Argb8888);
+    assert_eq!(pixels, expected(|x, y| alpha(x, y) << 24 | rgb(x, y)));
+}
+
+#[test]
+fn palette_1_bit() {
+    check_palette("palette1.png", 1);
+}
+
+#[test]
+fn palette_2_bit() {
+    check_palette("palette2.png", 2);
+}
+
+#[test]
+fn palette_4_bit() {
+    check_palette("palette4.png", 4);
+}
+
+#[test]
+fn palette_8_bit() {
+    check_palette("palette8.png", 8);
+}
+
+#[test]
+fn rejects_interlaced() {
+    assert_eq!(
+        read_png_info(&read_test_file("interlaced.png")).unwrap_err(),
+        PngError::UnsupportedInterlace
+    );
+}
+
+#[test]
+fn rejects_corrupt_files() {
+    let bytes = read_test_file("rgb8.png");
+
+    let mut bad_signature = bytes.clone();
+    bad_signature[1] = b'X';
+    assert_eq!(
+        read_png_info(&bad_signature).unwrap_err(),
+        PngError::BadSignature
+    );
+
+    assert_eq!(
+        read_png_info(&bytes[..40]).unwrap_err(),
+        PngError::Truncated
+    );
+    assert_eq!(
+        read_png_info(&bytes[..bytes.len() - 1]).unwrap_err(),
+        PngError::Truncated
+    );
+
+    let mut bad_crc = bytes.clone();
+    bad_crc[20] ^= 1;
+    assert_eq!(read_png_info(&bad_crc).unwrap_err(), PngError::BadChunkCrc);
+}
+
+#[test]
+fn rejects_small_buffers() {
+    let bytes = read_test_file("rgb8.png");
+    let info = read_png_info(&bytes).unwrap();
+    let mut scratch = vec![0; info.scratch_size() - 1];
+    let mut pixels = vec![0; info.pixel_count()];
+    assert_eq!(
+        decode_png(&bytes, &mut scratch, &mut pixels).unwrap_err(),
+        PngError::BufferTooSmall
+    );
+}
+
+fn encode(bytes: &mut [u8], width: usize, height: usize) -> Vec<u8> {
+    let buffer = RenderBuffer {
+        bytes,
+        width,
+        height,
+        pitch: width * 4,
+        bytes_per_pixel: 4,
+    };
+    let mut scratch = vec![0; png_encode_scratch_size(width, height)];
+    let mut encoded = Vec::new();
+    encode_png(&buffer, &mut scratch, |bytes| {
+        encoded.extend_from_slice(bytes)
+    })
+    .unwrap();
+    encoded
+}
+
+#[test]
+fn encode_round_trip() {
+    let width = 300;
+    let height = 200;
+    let mut pixels = Vec::new();
+    for y in 0..height {
+        for x in 0..width {
+            // NOTE: Mix smooth areas that compress well with noise that does not.
+            let noise = (x as u32).wrapping_mul(2654435761) ^ (y as u32).wrapping_mul(40503);
+            let pixel = if (x / 50 + y / 50) % 2 == 0 {
+                rgb(x, y)
+            } else {
+                noise & 0x00FFFFFF
+            };
+            pixels.push(pixel);
+        }
+    }
+    let mut bytes: Vec<u8> = pixels
+        .iter()
+        .flat_map(|pixel| pixel.to_le_bytes())
+        .collect();
+
+    let encoded = encode(&mut bytes, width, height);
+    let (info, decoded) = decode(&encoded).unwrap();
+    assert_eq!((info.width, info.height), (width, height));
+    assert_eq!(info.format, PixelFormat::Xrgb8888);
+    let expected: Vec<u32> = pixels.iter().map(|pixel| 0xFF000000 | pixel).collect();
+    assert_eq!(decoded, expected);
+    assert!(encoded.len() < width * height * 3);
+}
+
+#[test]
+fn encode_ignores_padding_and_top_byte() {
+    let width = 3;
+    let height = 2;
+    let pitch = 16;
+    let mut bytes = vec![0xAB; pitch * height];
+    let pixels = [
+        0x11223344u32,
+        0x55667788,
+        0x99AABBCC,
+        0x00000000,
+        0xFFFFFFFF,
+        0x12345678,
+    ];
+    for (index, pixel) in pixels.iter().enumerate() {
+        let at = (index / width) * pitch + (index % width) * 4;
+        bytes[at..at + 4].copy_from_slice(&pixel.to_le_bytes());
+    }
+
+    let buffer = RenderBuffer {
+        bytes: &mut bytes,
+        width,
+        height,
+        pitch,
+        bytes_per_pixel: 4,
+    };
+    let mut scratch = vec![0; png_encode_scratch_size(width, height)];
+    let mut encoded = Vec::new();
+    encode_png(&buffer, &mut scratch, |bytes| {
+        encoded.extend_from_slice(bytes)
+    })
+    .unwrap();
+
+    let (_, decoded) = decode(&encoded).unwrap();
+    let expected: Vec<u32> = pixels.iter().map(|pixel| 0xFF000000 | pixel).collect();
+    assert_eq!(decoded, expected);
+}